@@ -0,0 +1,387 @@
+use std::{path::PathBuf, str::FromStr};
+
+use ray_tarcing_in_one_weekend::rt::{
+    framebuffer::OutputFormat,
+    integrators::{self, debug_view::DebugView, Integrator},
+    renderer::AdaptiveSampling,
+    tonemap::{DisplayTransform, ToneMapper},
+};
+
+pub const USAGE: &str = "\
+Usage: ray_tarcing_in_one_weekend [OPTIONS]
+
+Options:
+  -s, --scene <NAME>      Built-in scene to render [default: cornell_aluminum_glass]
+  -f, --file <PATH>       Load the scene from a TOML file instead
+  -w, --width <PIXELS>    Image width, the height follows the aspect ratio of the scene
+  -n, --samples <N>       Samples per pixel, with --adaptive what pixels get on average
+  -d, --max-depth <N>     Ray bounce limit
+      --roulette-depth <N> Bounces before Russian roulette may end a path [default: 3]
+  -i, --integrator <NAME> How to shade camera rays: path, direct, bdpt,
+                          photon_map, progressive_photon_map, ambient_occlusion,
+                          or the debug views normal, uv, barycentric, facing
+                          and bvh_cost, with --features bvh_stats
+                          [default: from the scene, path]
+  -t, --threads <N>       Number of render threads [default: one per core]
+  -o, --output <PATH>     Output image, repeat to write several files [default: image.png]
+      --format <FORMAT>   Image format (png, jpg, bmp, tiff, ..., or exr, hdr and pfm for
+                          linear floating point) [default: from the output extension]
+      --exposure <EV>     Exposure adjustment in stops [default: 0]
+      --tonemap <NAME>    Tone mapper for 8-bit output: clamp, reinhard, reinhard_extended,
+                          aces or hable [default: clamp]
+      --white <L>         Luminance mapped to white by reinhard_extended [default: 4]
+      --adaptive <ERROR>  Stop sampling a pixel once its standard error relative to its
+                          luminance drops below ERROR, e.g. 0.01
+      --min-samples <N>   Samples every pixel gets with --adaptive [default: 16]
+      --max-samples <N>   The most samples a pixel gets with --adaptive, from those the
+                          converged pixels didn't need [default: 4 times --samples]
+      --passes <N>        Render progressively in N passes over the image, writing the
+                          outputs after each one [default: 1]
+      --checkpoint <PATH> Save the samples after every pass, for --resume
+      --resume <PATH>     Continue from a checkpoint, repeat to merge checkpoints rendered
+                          elsewhere with different seeds
+      --aovs              Also write albedo, normal, position, UV, depth, material ID, object
+                          ID and light group images, as <output>.<aov>.exr. Object IDs are
+                          per top-level object, a BVH of many objects gets one ID
+      --denoise           Filter the noise out of the image, guided by the AOVs
+      --seed <N>          Seed the random number generator, for reproducible renders
+  -l, --list-scenes       Print the built-in scenes and exit
+  -h, --help              Print this help and exit
+";
+
+pub enum SceneSource {
+    Builtin(String),
+    File(PathBuf),
+}
+
+pub struct Options {
+    pub scene: SceneSource,
+    pub width: Option<usize>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub roulette_depth: Option<usize>,
+    pub integrator: Option<Box<dyn Integrator>>,
+    pub threads: Option<usize>,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub passes: usize,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: Vec<PathBuf>,
+    pub outputs: Vec<(PathBuf, OutputFormat)>,
+    pub display: DisplayTransform,
+    pub aovs: bool,
+    pub denoise: bool,
+    pub seed: Option<u64>,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    ListScenes,
+    Help,
+}
+
+/// Parses the arguments that follow the program name. Values can be given as
+/// `--samples 100` or `--samples=100`.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut scene = None;
+    let mut width = None;
+    let mut samples_per_pixel = None;
+    let mut max_depth = None;
+    let mut roulette_depth = None;
+    let mut integrator = None;
+    let mut threads = None;
+    let mut threshold = None;
+    let mut min_samples = None;
+    let mut max_samples = None;
+    let mut passes = 1;
+    let mut checkpoint = None;
+    let mut resume = Vec::new();
+    let mut outputs: Vec<PathBuf> = Vec::new();
+    let mut format = None;
+    let mut seed = None;
+    let mut display = DisplayTransform::default();
+    let mut white = None;
+    let mut aovs = false;
+    let mut denoise = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| match inline_value {
+            Some(value) => Ok(value.to_string()),
+            None => args.next().ok_or_else(|| format!("{} needs a value", name)),
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-l" | "--list-scenes" => return Ok(Command::ListScenes),
+            "-s" | "--scene" => scene = Some(SceneSource::Builtin(value(&flag)?)),
+            "-f" | "--file" => scene = Some(SceneSource::File(value(&flag)?.into())),
+            "-w" | "--width" => width = Some(positive(&flag, &value(&flag)?)?),
+            "-n" | "--samples" => samples_per_pixel = Some(positive(&flag, &value(&flag)?)?),
+            "-d" | "--max-depth" => max_depth = Some(positive(&flag, &value(&flag)?)?),
+            "--roulette-depth" => roulette_depth = Some(number(&flag, &value(&flag)?)?),
+            "-i" | "--integrator" => {
+                let name = value(&flag)?;
+                if name == "bvh_cost" && !DebugView::BVH_COST_AVAILABLE {
+                    return Err(DebugView::BVH_COST_UNAVAILABLE.into());
+                }
+                integrator = Some(
+                    integrators::from_name(&name)
+                        .ok_or_else(|| format!("unknown integrator '{}'", name))?,
+                );
+            }
+            "-t" | "--threads" => threads = Some(positive(&flag, &value(&flag)?)?),
+            "--adaptive" => threshold = Some(number::<f64>(&flag, &value(&flag)?)?),
+            "--min-samples" => {
+                let n = positive(&flag, &value(&flag)?)?;
+                if n < 2 {
+                    return Err(format!("{} must be at least 2", flag));
+                }
+                min_samples = Some(n);
+            }
+            "--max-samples" => max_samples = Some(positive(&flag, &value(&flag)?)?),
+            "--passes" => passes = positive(&flag, &value(&flag)?)?,
+            "--checkpoint" => checkpoint = Some(value(&flag)?.into()),
+            "--resume" => resume.push(value(&flag)?.into()),
+            "-o" | "--output" => outputs.push(value(&flag)?.into()),
+            "--format" => {
+                let name = value(&flag)?;
+                format = Some(
+                    OutputFormat::from_extension(&name)
+                        .ok_or_else(|| format!("unknown image format '{}'", name))?,
+                );
+            }
+            "--exposure" => display.exposure = number(&flag, &value(&flag)?)?,
+            "--tonemap" => {
+                let name = value(&flag)?;
+                display.tone_mapper = ToneMapper::from_name(&name)
+                    .ok_or_else(|| format!("unknown tone mapper '{}'", name))?;
+            }
+            "--white" => white = Some(number::<f64>(&flag, &value(&flag)?)?),
+            "--aovs" => aovs = true,
+            "--denoise" => denoise = true,
+            "--seed" => seed = Some(number(&flag, &value(&flag)?)?),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if let Some(white) = white {
+        match &mut display.tone_mapper {
+            ToneMapper::ExtendedReinhard { white: w } if white > 0.0 => *w = white,
+            ToneMapper::ExtendedReinhard { .. } => return Err("--white must be positive".into()),
+            _ => return Err("--white needs --tonemap reinhard_extended".into()),
+        }
+    }
+    let adaptive = match threshold {
+        Some(threshold) if threshold <= 0.0 => return Err("--adaptive must be positive".into()),
+        Some(threshold) => Some(AdaptiveSampling {
+            threshold,
+            min_samples: min_samples.unwrap_or(16),
+            max_samples,
+        }),
+        None if min_samples.is_some() => return Err("--min-samples needs --adaptive".into()),
+        None if max_samples.is_some() => return Err("--max-samples needs --adaptive".into()),
+        None => None,
+    };
+    if outputs.is_empty() {
+        outputs.push("image.png".into());
+    }
+    let outputs = outputs
+        .into_iter()
+        .map(
+            |output| match format.or_else(|| OutputFormat::from_path(&output)) {
+                Some(format) => Ok((output, format)),
+                None => Err(format!(
+                    "can't tell the image format of '{}'",
+                    output.display()
+                )),
+            },
+        )
+        .collect::<Result<_, _>>()?;
+
+    Ok(Command::Render(Box::new(Options {
+        scene: scene.unwrap_or_else(|| SceneSource::Builtin("cornell_aluminum_glass".into())),
+        width,
+        samples_per_pixel,
+        max_depth,
+        roulette_depth,
+        integrator,
+        threads,
+        adaptive,
+        passes,
+        checkpoint,
+        resume,
+        outputs,
+        display,
+        aovs,
+        denoise,
+        seed,
+    })))
+}
+
+fn number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn positive(flag: &str, value: &str) -> Result<usize, String> {
+    match number(flag, value)? {
+        0 => Err(format!("{} must be at least 1", flag)),
+        n => Ok(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::ImageFormat;
+
+    use super::*;
+
+    fn render(args: &[&str]) -> Result<Options, String> {
+        match parse(args.iter().map(|s| s.to_string()))? {
+            Command::Render(options) => Ok(*options),
+            _ => panic!("expected a render command"),
+        }
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let options = render(&[
+            "--scene",
+            "cornell_box",
+            "-w",
+            "200",
+            "--samples=16",
+            "-o",
+            "out/frame.jpg",
+            "--seed",
+            "7",
+            "--roulette-depth",
+            "0",
+            "-i",
+            "normal",
+        ])
+        .unwrap();
+        assert!(matches!(options.scene, SceneSource::Builtin(name) if name == "cornell_box"));
+        assert_eq!(options.width, Some(200));
+        assert_eq!(options.samples_per_pixel, Some(16));
+        assert_eq!(options.max_depth, None);
+        assert_eq!(options.roulette_depth, Some(0));
+        assert!(options.integrator.is_some());
+        assert_eq!(options.adaptive, None);
+        assert_eq!(options.passes, 1);
+        assert_eq!(
+            options.outputs,
+            [("out/frame.jpg".into(), OutputFormat::Ldr(ImageFormat::Jpeg))]
+        );
+        assert_eq!(options.seed, Some(7));
+    }
+
+    #[test]
+    fn format_overrides_the_extension() {
+        let options = render(&["-f", "scene.toml", "-o", "out.img", "--format", "bmp"]).unwrap();
+        assert!(matches!(options.scene, SceneSource::File(_)));
+        assert_eq!(options.outputs[0].1, OutputFormat::Ldr(ImageFormat::Bmp));
+    }
+
+    #[test]
+    fn writes_several_outputs() {
+        let options = render(&[
+            "-o",
+            "beauty.png",
+            "-o",
+            "beauty.exr",
+            "--output=raw.pfm",
+            "--aovs",
+        ])
+        .unwrap();
+        let formats: Vec<_> = options.outputs.iter().map(|(_, format)| *format).collect();
+        assert_eq!(
+            formats,
+            [
+                OutputFormat::Ldr(ImageFormat::Png),
+                OutputFormat::OpenExr,
+                OutputFormat::Pfm
+            ]
+        );
+        assert!(options.aovs);
+    }
+
+    #[test]
+    fn parses_display_settings() {
+        let options = render(&[
+            "--tonemap",
+            "reinhard_extended",
+            "--white=8",
+            "--exposure",
+            "-1.5",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.display,
+            DisplayTransform {
+                exposure: -1.5,
+                tone_mapper: ToneMapper::ExtendedReinhard { white: 8.0 },
+            }
+        );
+        assert!(render(&["--tonemap", "aces", "--white", "8"]).is_err());
+        assert!(render(&["--tonemap", "filmic"]).is_err());
+    }
+
+    #[test]
+    fn parses_adaptive_sampling() {
+        let options = render(&["-n", "1024", "--adaptive", "0.02", "--min-samples", "32"]).unwrap();
+        assert_eq!(
+            options.adaptive,
+            Some(AdaptiveSampling {
+                threshold: 0.02,
+                min_samples: 32,
+                max_samples: None,
+            })
+        );
+        let options = render(&["--adaptive", "0.02", "--max-samples", "4096"]).unwrap();
+        assert_eq!(options.adaptive.unwrap().max_samples, Some(4096));
+        assert!(render(&["--min-samples", "32"]).is_err());
+        assert!(render(&["--max-samples", "4096"]).is_err());
+        assert!(render(&["--adaptive", "0.02", "--min-samples", "1"]).is_err());
+    }
+
+    #[test]
+    fn parses_progressive_rendering() {
+        let options = render(&[
+            "--passes",
+            "8",
+            "--checkpoint",
+            "render.ckpt",
+            "--resume",
+            "a.ckpt",
+            "--resume=b.ckpt",
+        ])
+        .unwrap();
+        assert_eq!(options.passes, 8);
+        assert_eq!(options.checkpoint, Some("render.ckpt".into()));
+        assert_eq!(
+            options.resume,
+            [PathBuf::from("a.ckpt"), PathBuf::from("b.ckpt")]
+        );
+        assert!(render(&["--passes", "0"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(render(&["--width", "0"]).is_err());
+        assert!(render(&["--samples", "many"]).is_err());
+        assert!(render(&["--threads"]).is_err());
+        assert!(render(&["--bogus"]).is_err());
+        assert!(render(&["--integrator", "whitted"]).is_err());
+        assert_eq!(
+            render(&["-i", "bvh_cost"]).is_ok(),
+            DebugView::BVH_COST_AVAILABLE
+        );
+        assert!(render(&["-o", "image.unknown"]).is_err());
+    }
+}
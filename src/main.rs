@@ -1,14 +1,24 @@
+use cli::{Command, Options, SceneSource};
+use indicatif::{ProgressBar, ProgressStyle};
 use ray_tarcing_in_one_weekend::rt::{
     camera::Camera,
+    checkpoint,
     color::{self, Color},
+    denoise::{denoise, DenoiseSettings},
+    framebuffer::Framebuffer,
+    integrators::{path_tracer::PathTracing, PathSettings},
+    mat4::Mat4,
     materials::{
         dielectric::Dielectric, diffuse_light::DiffuseLight, lambertian::Lambertian, metal::Metal,
         Material,
     },
-    random_f64, random_f64_between, random_vec3, random_vec3_between, render_pixel,
+    random_f64, random_f64_between, random_vec3, random_vec3_between,
+    renderer::{RenderSettings, Renderer},
+    scene::{self, Scene},
     shapes::{
-        bbox::Bbox, bvh_node::BvhNode, constant_volume::ConstantVolume, flip_face::FlipFace,
-        hittable_list::HittableList, mooving_sphere::MovingSphere, rotate_y::RotateY,
+        bbox::Bbox, bvh_build::BvhOptions, bvh_node::BvhNode, constant_volume::ConstantVolume,
+        flip_face::FlipFace, hittable_list::HittableList, importance_target::ImportanceTarget,
+        instance::Instance, linear_bvh::LinearBvh, mooving_sphere::MovingSphere, rotate_y::RotateY,
         sphere::Sphere, translate::Translate, xy_rect::XyRect, xz_rect::XzRect, yz_rect::YzRect,
         Hittable,
     },
@@ -18,62 +28,221 @@ use ray_tarcing_in_one_weekend::rt::{
     vec3::Vec3,
     Point3,
 };
-use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
-use std::{sync::Arc, time::Instant};
-
-use image::{ImageBuffer, Rgb, RgbImage};
-
-const PARALLEL: bool = true;
+use std::{process, sync::Arc, time::Instant};
+
+mod cli;
+
+type SceneFn = fn() -> (HittableList, Camera, Color);
+
+const SCENES: &[(&str, SceneFn, &str)] = &[
+    ("random_scene", random_scene, "the cover of the first book"),
+    ("two_spheres", two_spheres, "two checkered spheres"),
+    (
+        "two_perlin_spheres",
+        two_perlin_spheres,
+        "two spheres with Perlin noise",
+    ),
+    ("earth", earth, "an image textured globe"),
+    (
+        "simple_light",
+        simple_light,
+        "Perlin spheres lit by a rectangle light",
+    ),
+    (
+        "cornell_box",
+        cornell_box,
+        "the Cornell box with two white blocks",
+    ),
+    (
+        "cornell_aluminum_glass",
+        cornell_aluminum_glass,
+        "the Cornell box with an aluminum block and a glass sphere",
+    ),
+    (
+        "cornell_smoke",
+        cornell_smoke,
+        "the Cornell box with two blocks of smoke",
+    ),
+    ("bvh_test", bvh_test, "a column of 10000 spheres in a BVH"),
+    ("final_scene", final_scene, "the cover of the second book"),
+    (
+        "instanced_clusters",
+        instanced_clusters,
+        "400 instances of one sphere cluster",
+    ),
+];
 
 fn main() {
-    // World, Camera
-    let (world, camera, background) = _cornell_aluminum_glass();
-    let light: Arc<dyn Material> = Arc::new(DiffuseLight::from_color(Color::new(15.0, 15.0, 15.0)));
-    let mut lights = HittableList::default();
-    lights.add(Arc::new(XzRect::new(
-        213.0,
-        343.0,
-        227.0,
-        332.0,
-        554.0,
-        light.clone(),
-    )));
-    lights.add(Arc::new(Sphere::new(
-        Point3::new(190.0, 90.0, 190.0),
-        90.0,
-        light.clone(),
-    )));
+    let mut options = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Render(options)) => *options,
+        Ok(Command::ListScenes) => {
+            for (name, _, description) in SCENES {
+                println!("{:<24}{}", name, description);
+            }
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
 
-    // IMAGE
-    let mut buffer: RgbImage = ImageBuffer::new(camera.width as u32, camera.height as u32);
+    if let Some(seed) = options.seed {
+        fastrand::seed(seed);
+    }
 
-    // PROGRESS BAR
-    let bar = ProgressBar::new((buffer.width() * buffer.height()) as u64);
-    bar.set_style(ProgressStyle::with_template("{wide_bar} {percent}%").unwrap());
+    let mut scene = match load_scene(&options) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+    let camera = &mut scene.camera;
+    if let Some(width) = options.width {
+        camera.set_width(width);
+    }
+    if let Some(samples_per_pixel) = options.samples_per_pixel {
+        camera.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(max_depth) = options.max_depth {
+        camera.max_depth = max_depth;
+    }
+    if let Some(roulette_depth) = options.roulette_depth {
+        scene.path_settings.roulette_depth = roulette_depth;
+    }
+    if let Some(integrator) = options.integrator.take() {
+        scene.integrator = integrator;
+    }
+    if let Err(e) = scene.camera.validate() {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 
-    let start = Instant::now();
-    if PARALLEL {
-        buffer.enumerate_pixels_mut().par_bridge().for_each(|arg| {
-            iterate_pixel(arg, &camera, background, &world, &lights);
-            bar.inc(1);
+    let renderer = Renderer::new(
+        &scene,
+        RenderSettings {
+            threads: options.threads,
+            seed: options.seed,
+            // The denoiser is guided by the AOVs.
+            aovs: options.aovs || options.denoise,
+            adaptive: options.adaptive,
+        },
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("error: can't start the render threads: {}", e);
+        process::exit(1);
+    });
+
+    let mut framebuffer = renderer.framebuffer();
+    for path in &options.resume {
+        let resumed = checkpoint::load(path).unwrap_or_else(|e| {
+            eprintln!("Error reading {}: {}", path.display(), e);
+            process::exit(1);
         });
-    } else {
-        buffer.enumerate_pixels_mut().for_each(|arg| {
-            iterate_pixel(arg, &camera, background, &world, &lights);
-            bar.inc(1);
+        let size = (resumed.width(), resumed.height());
+        if size != (framebuffer.width(), framebuffer.height()) {
+            eprintln!(
+                "error: {} is {}x{}, the image is {}x{}",
+                path.display(),
+                size.0,
+                size.1,
+                framebuffer.width(),
+                framebuffer.height()
+            );
+            process::exit(1);
+        }
+        framebuffer.merge(&resumed);
+    }
+
+    // Adaptive sampling moves samples between pixels, but the passes keep their total.
+    let samples_per_pixel = scene.camera.samples_per_pixel;
+    let mut done = framebuffer.mean_samples();
+    let per_pass = samples_per_pixel.div_ceil(options.passes);
+    while done < samples_per_pixel {
+        let samples = per_pass.min(samples_per_pixel - done);
+
+        // PROGRESS BAR
+        let bar = ProgressBar::new((scene.camera.width * scene.camera.height) as u64);
+        bar.set_style(ProgressStyle::with_template("{msg} {wide_bar} {percent}%").unwrap());
+        bar.set_message(format!("{}/{} spp", done + samples, samples_per_pixel));
+
+        let stats = renderer.render_pass(&mut framebuffer, samples, |progress| {
+            bar.set_length(progress.total_pixels as u64);
+            bar.set_position(progress.completed_pixels as u64)
         });
+        bar.finish();
+        done += samples;
+        println!(
+            "Time elapsed in drawing is: {:?} ({:.0} samples/s, {:.1} samples per pixel)",
+            stats.elapsed,
+            stats.samples_per_second(),
+            stats.samples as f64 / stats.pixels.max(1) as f64
+        );
+
+        if let Some(path) = &options.checkpoint {
+            if let Err(e) = checkpoint::save(&framebuffer, path) {
+                eprintln!("Error writing {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        if done < samples_per_pixel {
+            write_outputs(&framebuffer, &options);
+        }
     }
-    let duration = start.elapsed();
-    println!("Time elapsed in drawing is: {:?}", duration);
 
-    match buffer.save("image.png") {
-        Err(e) => eprintln!("Error writing file: {}", e),
-        Ok(()) => println!("Done."),
-    };
+    if options.denoise {
+        let start = Instant::now();
+        denoise(&mut framebuffer, &DenoiseSettings::default());
+        println!("Time elapsed in denoising is: {:?}", start.elapsed());
+    }
+    write_outputs(&framebuffer, &options);
+    println!("Done.");
+}
+
+fn write_outputs(framebuffer: &Framebuffer, options: &Options) {
+    for (output, format) in &options.outputs {
+        if let Err(e) = framebuffer.save(output, *format, &options.display) {
+            eprintln!("Error writing {}: {}", output.display(), e);
+            process::exit(1);
+        }
+    }
+    if let Some(aovs) = framebuffer.aovs() {
+        let (output, _) = &options.outputs[0];
+        if let Err(e) = aovs.save(&output.with_extension("")) {
+            eprintln!("Error writing AOVs: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn load_scene(options: &Options) -> Result<Scene, String> {
+    match &options.scene {
+        SceneSource::File(path) => scene::load(path).map_err(|e| e.to_string()),
+        SceneSource::Builtin(name) => {
+            let (_, scene_fn, _) = SCENES
+                .iter()
+                .find(|(scene, _, _)| scene == name)
+                .ok_or_else(|| format!("unknown scene '{}', see --list-scenes", name))?;
+            let (world, camera, background) = scene_fn();
+            Ok(Scene {
+                lights: world.lights(),
+                world,
+                camera,
+                background,
+                light_groups: vec![scene::DEFAULT_LIGHT_GROUP.to_string()],
+                integrator: Box::new(PathTracing),
+                path_settings: PathSettings::default(),
+            })
+        }
+    }
 }
 
-fn _random_scene() -> (HittableList, Camera, Color) {
+fn random_scene() -> (HittableList, Camera, Color) {
     let mut world = HittableList::new_from_object(Arc::new(Sphere::new(
         Point3::new(0.0, -1000.0, 0.0),
         1000.0,
@@ -158,7 +327,7 @@ fn _random_scene() -> (HittableList, Camera, Color) {
     (world, camera, Color::new(0.7, 0.8, 1.0))
 }
 
-fn _two_spheres() -> (HittableList, Camera, Color) {
+fn two_spheres() -> (HittableList, Camera, Color) {
     let checker = Arc::new(CheckerTexture::from_colors(
         Color::new(0.2, 0.3, 0.1),
         Color::new(0.9, 0.9, 0.9),
@@ -207,7 +376,7 @@ fn _two_spheres() -> (HittableList, Camera, Color) {
     (world, camera, Color::new(0.7, 0.8, 1.0))
 }
 
-fn _two_perlin_spheres() -> (HittableList, Camera, Color) {
+fn two_perlin_spheres() -> (HittableList, Camera, Color) {
     let pertext = Arc::new(NoiseTexture::new(4.0));
     let material: Arc<dyn Material> = Arc::new(Lambertian::from_texture(pertext));
     let mut world = HittableList::default();
@@ -253,7 +422,7 @@ fn _two_perlin_spheres() -> (HittableList, Camera, Color) {
     (world, camera, Color::new(0.7, 0.8, 1.0))
 }
 
-fn _earth() -> (HittableList, Camera, Color) {
+fn earth() -> (HittableList, Camera, Color) {
     let earth_texture = Arc::new(ImageTexture::from_file(
         "C:\\my_space\\Code\\rust\\ray_tarcing_in_one_weekend\\textures\\earthmap.jpg",
     ));
@@ -295,7 +464,7 @@ fn _earth() -> (HittableList, Camera, Color) {
     (world, camera, Color::new(0.7, 0.8, 1.0))
 }
 
-fn _simple_light() -> (HittableList, Camera, Color) {
+fn simple_light() -> (HittableList, Camera, Color) {
     let pertext = Arc::new(NoiseTexture::new(4.0));
     let material: Arc<dyn Material> = Arc::new(Lambertian::from_texture(pertext));
     let mut world = HittableList::default();
@@ -345,7 +514,7 @@ fn _simple_light() -> (HittableList, Camera, Color) {
     (world, camera, color::BLACK)
 }
 
-fn _cornell_box() -> (HittableList, Camera, Color) {
+fn cornell_box() -> (HittableList, Camera, Color) {
     let red: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.65, 0.05, 0.05)));
     let white: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.73, 0.73, 0.73)));
     let green: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.12, 0.45, 0.15)));
@@ -423,7 +592,7 @@ fn _cornell_box() -> (HittableList, Camera, Color) {
     (world, camera, color::BLACK)
 }
 
-fn _cornell_aluminum_glass() -> (HittableList, Camera, Color) {
+fn cornell_aluminum_glass() -> (HittableList, Camera, Color) {
     let red: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.65, 0.05, 0.05)));
     let white: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.73, 0.73, 0.73)));
     let green: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.12, 0.45, 0.15)));
@@ -462,11 +631,12 @@ fn _cornell_aluminum_glass() -> (HittableList, Camera, Color) {
     let box1 = RotateY::new(Arc::new(box1), 15.0);
     let box1 = Translate::new(Arc::new(box1), Vec3::new(265.0, 0.0, 295.0));
     world.add(Arc::new(box1));
-    world.add(Arc::new(Sphere::new(
+    // Sampled like a light to resolve the caustic under it.
+    world.add(Arc::new(ImportanceTarget::new(Arc::new(Sphere::new(
         Point3::new(190.0, 90.0, 190.0),
         90.0,
         Arc::new(Dielectric::new(1.5)),
-    )));
+    )))));
 
     let lookfrom = Point3::new(278.0, 278.0, -800.0);
     let lookat = Point3::new(278.0, 278.0, 0.0);
@@ -498,7 +668,7 @@ fn _cornell_aluminum_glass() -> (HittableList, Camera, Color) {
     (world, camera, color::BLACK)
 }
 
-fn _cornell_smoke() -> (HittableList, Camera, Color) {
+fn cornell_smoke() -> (HittableList, Camera, Color) {
     let red: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.65, 0.05, 0.05)));
     let white: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.73, 0.73, 0.73)));
     let green: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.12, 0.45, 0.15)));
@@ -578,7 +748,7 @@ fn _cornell_smoke() -> (HittableList, Camera, Color) {
     (world, camera, color::BLACK)
 }
 
-fn _bvh_test() -> (HittableList, Camera, Color) {
+fn bvh_test() -> (HittableList, Camera, Color) {
     let material: Arc<dyn Material> =
         Arc::new(Lambertian::from_color(Color::new(0.65, 0.05, 0.05)));
     let mut world = HittableList::default();
@@ -627,7 +797,7 @@ fn _bvh_test() -> (HittableList, Camera, Color) {
     (world, camera, Color::new(0.7, 0.8, 1.0))
 }
 
-fn _final_scene() -> (HittableList, Camera, Color) {
+fn final_scene() -> (HittableList, Camera, Color) {
     let mut boxes1 = HittableList::default();
     let ground: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.48, 0.83, 0.53)));
 
@@ -766,26 +936,82 @@ fn _final_scene() -> (HittableList, Camera, Color) {
     (objects, camera, color::BLACK)
 }
 
-fn iterate_pixel(
-    arg: (u32, u32, &mut Rgb<u8>),
-    camera: &Camera,
-    background: Color,
-    world: &dyn Hittable,
-    lights: &dyn Hittable,
-) {
-    let (x, y, pixel) = arg;
-    // flip y to match results in the book
-    let y = camera.height as u32 - 1 - y;
-    let color = render_pixel(x, y, camera, background, world, lights);
-    write_color(pixel, color, camera.samples_per_pixel);
-}
+fn instanced_clusters() -> (HittableList, Camera, Color) {
+    let white: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.73, 0.73, 0.73)));
+    let mut spheres = HittableList::default();
+    for _ in 0..1000 {
+        spheres.add(Arc::new(Sphere::new(
+            random_vec3_between(0.0, 165.0),
+            10.0,
+            white.clone(),
+        )))
+    }
+    let cluster: Arc<dyn Hittable> = Arc::new(LinearBvh::from_list(
+        &spheres,
+        0.0,
+        1.0,
+        BvhOptions::default(),
+    ));
+
+    // 400 instances share the geometry of a single cluster.
+    let mut instances: Vec<Arc<dyn Hittable>> = Vec::new();
+    for i in 0..20 {
+        for j in 0..20 {
+            let offset = Vec3::new(i as f64 * 250.0 - 2500.0, 0.0, j as f64 * 250.0);
+            let matrix = Mat4::translation(offset)
+                * Mat4::rotation_y(random_f64_between(0.0, 360.0))
+                * Mat4::scaling(Vec3::new(0.5, random_f64_between(0.5, 1.5), 0.5));
+            let material: Arc<dyn Material> =
+                Arc::new(Lambertian::from_color(random_vec3() * random_vec3()));
+            // The scales stay positive, so every placement is invertible.
+            if let Some(instance) = Instance::with_material(cluster.clone(), matrix, material) {
+                instances.push(Arc::new(instance));
+            }
+        }
+    }
+
+    let mut world = HittableList::default();
+    world.add(Arc::new(LinearBvh::new(
+        &instances,
+        0.0,
+        1.0,
+        BvhOptions::default(),
+    )));
+    world.add(Arc::new(XzRect::new(
+        -1e5,
+        1e5,
+        -1e5,
+        1e5,
+        0.0,
+        Arc::new(Lambertian::from_color(Color::new(0.48, 0.83, 0.53))),
+    )));
 
-fn write_color(pixel: &mut Rgb<u8>, color: Color, samples_per_pixel: usize) {
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let color = color * (1.0 / samples_per_pixel as f64);
-    let color = color.clamp(0.0, 1.0).sqrt() * 255.999;
-    let ir = color.x as u8;
-    let ig = color.y as u8;
-    let ib = color.z as u8;
-    *pixel = Rgb([ir, ig, ib]);
+    let lookfrom = Point3::new(0.0, 600.0, -900.0);
+    let lookat = Point3::new(0.0, 0.0, 1500.0);
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    let dist_to_focus = 10.0;
+    let aperture = 0.0;
+    let vfov = 40.0;
+    let time0 = 0.0;
+    let time1 = 1.0;
+    let aspect_ratio = 16.0 / 9.0;
+    let width = 800;
+    let samples_per_pixel = 100;
+    let max_depth = 50;
+    let camera = Camera::new(
+        lookfrom,
+        lookat,
+        vup,
+        vfov,
+        aperture,
+        dist_to_focus,
+        time0,
+        time1,
+        aspect_ratio,
+        width,
+        samples_per_pixel,
+        max_depth,
+    );
+
+    (world, camera, Color::new(0.7, 0.8, 1.0))
 }
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, path::Path};
+
+use image::{DynamicImage, ImageBuffer, ImageFormat, ImageResult, Rgba32FImage};
+
+use super::{
+    color::Color, materials::Material, ray::Ray, shapes::hit_record::HitRecord, vec3::Vec3,
+};
+
+/// Surface data of the first hits of one pixel's camera rays. Albedo, normal, position, UV
+/// and depth are averaged over the rays that hit something, the IDs come from the first of
+/// them. Object IDs number the top-level objects of the world from 1, so everything a
+/// scene groups into one BVH or instance shares an ID.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AovPixel {
+    samples: u32,
+    hits: u32,
+    albedo: [f32; 3],
+    normal: [f32; 3],
+    position: [f32; 3],
+    uv: [f32; 2],
+    depth: f32,
+    /// Address of the material, numbered only when the images are made so the IDs don't
+    /// depend on the order the threads ran in.
+    material: usize,
+    object_id: u32,
+}
+
+impl AovPixel {
+    /// Adds a camera ray and what it hit, with the index of the hit top-level object.
+    pub(crate) fn add(&mut self, r: &Ray, hit: Option<(usize, &HitRecord)>) {
+        self.samples += 1;
+        let Some((object, rec)) = hit else {
+            return;
+        };
+        if self.hits == 0 {
+            self.material = rec.material as *const dyn Material as *const () as usize;
+            self.object_id = object as u32 + 1;
+        }
+        self.hits += 1;
+        add(&mut self.albedo, rec.material.albedo(rec));
+        add(&mut self.normal, rec.normal);
+        add(&mut self.position, rec.p);
+        self.uv[0] += rec.u as f32;
+        self.uv[1] += rec.v as f32;
+        self.depth += (rec.t * r.direction.length()) as f32;
+    }
+
+    pub fn albedo(&self) -> Color {
+        vec3(self.mean(self.albedo))
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        vec3(self.mean(self.normal))
+    }
+
+    pub fn position(&self) -> Vec3 {
+        vec3(self.mean(self.position))
+    }
+
+    /// The distance to the camera, `None` where the background was seen.
+    pub fn depth(&self) -> Option<f64> {
+        match self.hits {
+            0 => None,
+            _ => Some(self.mean([self.depth])[0] as f64),
+        }
+    }
+
+    fn mean<const N: usize>(&self, sum: [f32; N]) -> [f32; N] {
+        if self.hits == 0 {
+            return [0.0; N];
+        }
+        sum.map(|c| c / self.hits as f32)
+    }
+}
+
+fn vec3([x, y, z]: [f32; 3]) -> Vec3 {
+    Vec3::new(x as f64, y as f64, z as f64)
+}
+
+fn add(sum: &mut [f32; 3], v: Vec3) {
+    sum[0] += v.x as f32;
+    sum[1] += v.y as f32;
+    sum[2] += v.z as f32;
+}
+
+/// Arbitrary output variables rendered alongside the beauty image: first-hit surface data
+/// and the light each light group contributes.
+pub struct Aovs {
+    width: usize,
+    height: usize,
+    pixels: Vec<AovPixel>,
+    light_group_names: Vec<String>,
+    /// Radiance sums, `light_group_names.len()` per pixel.
+    light_groups: Vec<Color>,
+}
+
+impl Aovs {
+    pub fn new(width: usize, height: usize, light_group_names: Vec<String>) -> Aovs {
+        let groups = light_group_names.len().max(1);
+        Aovs {
+            width,
+            height,
+            pixels: vec![AovPixel::default(); width * height],
+            light_group_names,
+            light_groups: vec![Color::default(); width * height * groups],
+        }
+    }
+
+    pub fn pixels(&self) -> &[AovPixel] {
+        &self.pixels
+    }
+
+    pub(crate) fn rows_mut(&mut self) -> impl Iterator<Item = (&mut [AovPixel], &mut [Color])> {
+        let width = self.width.max(1);
+        let groups = self.light_group_names.len().max(1);
+        self.pixels
+            .chunks_exact_mut(width)
+            .zip(self.light_groups.chunks_exact_mut(width * groups))
+    }
+
+    fn light_group_count(&self) -> usize {
+        self.light_group_names.len().max(1)
+    }
+
+    /// Every output as a named RGBA image. Pixels without hits get zeros, except for an
+    /// infinite depth. IDs start at 1, materials are numbered in the order they first
+    /// appear in the image.
+    pub fn images(&self) -> Vec<(String, Rgba32FImage)> {
+        let mut material_ids = HashMap::new();
+        let material_ids: Vec<f32> = self
+            .pixels
+            .iter()
+            .map(|pixel| match pixel.hits {
+                0 => 0.0,
+                _ => {
+                    let next = material_ids.len() + 1;
+                    *material_ids.entry(pixel.material).or_insert(next) as f32
+                }
+            })
+            .collect();
+
+        let mut images = vec![
+            self.image("albedo", |_, p| rgb(p.mean(p.albedo))),
+            self.image("normal", |_, p| rgb(p.mean(p.normal))),
+            self.image("position", |_, p| rgb(p.mean(p.position))),
+            self.image("uv", |_, p| {
+                let [u, v] = p.mean(p.uv);
+                [u, v, 0.0, 1.0]
+            }),
+            self.image("depth", |_, p| {
+                let depth = p.depth().map_or(f32::INFINITY, |depth| depth as f32);
+                [depth, depth, depth, 1.0]
+            }),
+            self.image("material_id", |i, _| {
+                let id = material_ids[i];
+                [id, id, id, 1.0]
+            }),
+            self.image("object_id", |_, p| {
+                let id = p.object_id as f32;
+                [id, id, id, 1.0]
+            }),
+        ];
+        let groups = self.light_group_count();
+        for (group, name) in self.light_group_names.iter().enumerate() {
+            images.push(self.image(&format!("light_{}", name), |i, p| {
+                let sum = self.light_groups[i * groups + group];
+                if p.samples == 0 {
+                    return [0.0, 0.0, 0.0, 1.0];
+                }
+                let n = p.samples as f64;
+                rgb([sum.x / n, sum.y / n, sum.z / n].map(|c| c as f32))
+            }));
+        }
+        images
+    }
+
+    fn image(
+        &self,
+        name: &str,
+        f: impl Fn(usize, &AovPixel) -> [f32; 4],
+    ) -> (String, Rgba32FImage) {
+        let data = self
+            .pixels
+            .iter()
+            .enumerate()
+            .flat_map(|(i, pixel)| f(i, pixel))
+            .collect();
+        let image = ImageBuffer::from_raw(self.width as u32, self.height as u32, data)
+            .expect("the buffer has four channels per pixel");
+        (name.to_string(), image)
+    }
+
+    /// Writes every output to `<stem>.<name>.exr`.
+    pub fn save(&self, stem: &Path) -> ImageResult<()> {
+        for (name, image) in self.images() {
+            let mut path = stem.as_os_str().to_owned();
+            path.push(format!(".{}.exr", name));
+            DynamicImage::ImageRgba32F(image).save_with_format(path, ImageFormat::OpenExr)?;
+        }
+        Ok(())
+    }
+}
+
+fn rgb([r, g, b]: [f32; 3]) -> [f32; 4] {
+    [r, g, b, 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::rt::{
+        materials::{diffuse_light::DiffuseLight, lambertian::Lambertian},
+        Point3,
+    };
+
+    use super::*;
+
+    fn channel(images: &[(String, Rgba32FImage)], name: &str, x: u32) -> f32 {
+        let (_, image) = images.iter().find(|(n, _)| n == name).unwrap();
+        image.get_pixel(x, 0).0[0]
+    }
+
+    #[test]
+    fn averages_hits_and_numbers_materials_by_appearance() {
+        let red: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.8, 0.1, 0.1)));
+        let light: Arc<dyn Material> =
+            Arc::new(DiffuseLight::from_color(Color::new(4.0, 4.0, 4.0)));
+        let r = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, 2.0), 0.0);
+        let rec = |t: f64, material| {
+            HitRecord::new(
+                r.at(t),
+                t,
+                0.5,
+                0.25,
+                &r,
+                Vec3::new(0.0, 0.0, -1.0),
+                material,
+            )
+        };
+
+        let mut aovs = Aovs::new(3, 1, vec!["default".into()]);
+        let (row, _) = aovs.rows_mut().next().unwrap();
+        row[0].add(&r, Some((2, &rec(1.0, light.as_ref()))));
+        row[1].add(&r, None);
+        row[1].add(&r, Some((0, &rec(1.0, red.as_ref()))));
+        row[1].add(&r, Some((1, &rec(2.0, light.as_ref()))));
+        let images = aovs.images();
+
+        assert_eq!(channel(&images, "albedo", 1), 0.4);
+        assert_eq!(channel(&images, "depth", 1), 3.0);
+        assert_eq!(channel(&images, "depth", 2), f32::INFINITY);
+        assert_eq!(channel(&images, "material_id", 0), 1.0);
+        assert_eq!(channel(&images, "material_id", 1), 2.0);
+        assert_eq!(channel(&images, "material_id", 2), 0.0);
+        assert_eq!(channel(&images, "object_id", 0), 3.0);
+        assert_eq!(channel(&images, "object_id", 1), 1.0);
+        assert!(images.iter().any(|(name, _)| name == "light_default"));
+    }
+}
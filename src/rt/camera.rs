@@ -1,3 +1,8 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
 use super::{
     degrees_to_radians, random_f64_between, random_in_unit_disk, ray::Ray, vec3::Vec3, Point3,
 };
@@ -22,6 +27,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -71,14 +77,162 @@ impl Camera {
         }
     }
 
+    /// Changes the image width, the height follows the aspect ratio.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width;
+        self.height = (width as f64 / self.aspect_ratio) as usize;
+    }
+
+    /// Checks that the camera makes an image that can be rendered. Pixels are placed by
+    /// dividing by the width and height less one, so both need at least 2 pixels.
+    pub fn validate(&self) -> Result<(), CameraError> {
+        if self.width < 2 {
+            Err(CameraError::Width(self.width))
+        } else if self.height < 2 {
+            Err(CameraError::Height(self.height))
+        } else if self.samples_per_pixel == 0 {
+            Err(CameraError::NoSamples)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
+        let lens = self.sample_lens();
 
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            lens,
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - lens,
             random_f64_between(self.time0, self.time1),
         )
     }
+
+    /// A point on the lens, where `get_ray` starts its rays.
+    pub fn sample_lens(&self) -> Point3 {
+        let rd = self.lens_radius * random_in_unit_disk();
+        self.origin + self.u * rd.x + self.v * rd.y
+    }
+
+    /// The film coordinates `get_ray` takes to send a ray from `lens` through `p`, `None`
+    /// when `p` isn't in front of the camera. They can be outside the image.
+    pub fn project(&self, lens: Point3, p: Point3) -> Option<(f64, f64)> {
+        let d = p - lens;
+        let depth = Vec3::dot(d, -self.w);
+        if depth <= 0.0 {
+            return None;
+        }
+        let on_film = lens + d * (self.focus_dist() / depth) - self.lower_left_corner;
+        Some((
+            Vec3::dot(on_film, self.horizontal) / self.horizontal.length_squared(),
+            Vec3::dot(on_film, self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    /// The solid angle density of `get_ray` sending a ray in `direction`, from whichever
+    /// point of the lens, when its film coordinates are uniform over all the pixels.
+    /// Directions outside the image aren't checked for.
+    pub fn pdf_dir(&self, direction: Vec3) -> f64 {
+        let cosine = Vec3::dot(Vec3::unit_vector(direction), -self.w);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        // Pixels are `horizontal` over the width less one wide, see `render_pixel`.
+        let pixel_area = self.horizontal.length() * self.vertical.length()
+            / ((self.width - 1) * (self.height - 1)) as f64;
+        let image_area = pixel_area * (self.width * self.height) as f64;
+        let focus_dist = self.focus_dist();
+        focus_dist * focus_dist / (image_area * cosine * cosine * cosine)
+    }
+
+    /// The distance from the lens to the plane in focus, where the film coordinates are.
+    fn focus_dist(&self) -> f64 {
+        Vec3::dot(self.origin - self.lower_left_corner, self.w)
+    }
+}
+
+/// Why a camera can't render, see `Camera::validate`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CameraError {
+    /// The image is narrower than 2 pixels.
+    Width(usize),
+    /// The aspect ratio makes the image lower than 2 pixels.
+    Height(usize),
+    NoSamples,
+}
+
+impl Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraError::Width(width) => {
+                write!(f, "the image is {} pixels wide, it needs at least 2", width)
+            }
+            CameraError::Height(height) => write!(
+                f,
+                "the aspect ratio makes the image {} pixels high, it needs at least 2",
+                height
+            ),
+            CameraError::NoSamples => write!(f, "the image needs at least 1 sample per pixel"),
+        }
+    }
+}
+
+impl Error for CameraError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_sizes_set_after_construction() {
+        let mut camera = Camera::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            10.0,
+            0.0,
+            1.0,
+            2.0,
+            100,
+            1,
+            4,
+        );
+        assert_eq!(camera.validate(), Ok(()));
+        camera.set_width(3);
+        assert_eq!(camera.validate(), Err(CameraError::Height(1)));
+        camera.set_width(1);
+        assert_eq!(camera.validate(), Err(CameraError::Width(1)));
+        camera.set_width(4);
+        camera.samples_per_pixel = 0;
+        assert_eq!(camera.validate(), Err(CameraError::NoSamples));
+    }
+
+    #[test]
+    fn projects_points_back_to_film_coordinates() {
+        let camera = Camera::new(
+            Point3::new(1.0, 2.0, 3.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.5,
+            3.0,
+            0.0,
+            1.0,
+            2.0,
+            100,
+            1,
+            4,
+        );
+        fastrand::seed(5);
+        let r = camera.get_ray(0.3, 0.7);
+        let (s, t) = camera.project(r.origin, r.at(7.0)).unwrap();
+        assert!(
+            (s - 0.3).abs() < 1e-9 && (t - 0.7).abs() < 1e-9,
+            "{} {}",
+            s,
+            t
+        );
+        assert_eq!(camera.project(r.origin, r.at(-1.0)), None);
+    }
 }
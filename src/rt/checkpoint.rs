@@ -0,0 +1,170 @@
+//! Saves the samples of a framebuffer so a render can be resumed, or merged with one made
+//! elsewhere. AOVs aren't saved, they restart with the resumed render.
+//!
+//! The format is little-endian: the magic bytes `RTCK`, a version, the width and height
+//! as `u32`s, then for every pixel its RGBA sums as `f32`s, its sample count as a `u32`
+//! and its luminance sum and sum of squares as `f64`s.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use super::framebuffer::{Accumulator, Framebuffer};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+pub fn write(framebuffer: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    for value in [
+        VERSION,
+        framebuffer.width() as u32,
+        framebuffer.height() as u32,
+    ] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    for pixel in framebuffer.pixels() {
+        let (sum, samples, luminance) = pixel.to_raw();
+        for value in sum {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&samples.to_le_bytes())?;
+        for value in luminance {
+            out.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read(input: &mut impl Read) -> io::Result<Framebuffer> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a checkpoint"));
+    }
+    let version = read_u32(input)?;
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported checkpoint version {}",
+            version
+        )));
+    }
+    let width = read_u32(input)? as usize;
+    let height = read_u32(input)? as usize;
+    let count = width
+        .checked_mul(height)
+        .ok_or_else(|| invalid_data(format!("invalid checkpoint size {}x{}", width, height)))?;
+
+    // Grown as pixels are read, so a damaged header can't ask for all the memory there is.
+    let mut pixels = Vec::new();
+    for read in 0..count {
+        let pixel = read_pixel(input).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid_data(format!(
+                "checkpoint ends after {} of its {}x{} pixels",
+                read, width, height
+            )),
+            _ => e,
+        })?;
+        pixels.push(pixel);
+    }
+    Ok(Framebuffer::from_pixels(width, height, pixels))
+}
+
+fn read_pixel(input: &mut impl Read) -> io::Result<Accumulator> {
+    let mut sum = [0.0; 4];
+    for value in &mut sum {
+        *value = f32::from_le_bytes(read_bytes(input)?);
+    }
+    let samples = read_u32(input)?;
+    let luminance = [
+        f64::from_le_bytes(read_bytes(input)?),
+        f64::from_le_bytes(read_bytes(input)?),
+    ];
+    Ok(Accumulator::from_raw(sum, samples, luminance))
+}
+
+/// Writes next to `path` first and renames, so an interrupted write keeps the old one.
+pub fn save(framebuffer: &Framebuffer, path: &Path) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    write(framebuffer, &mut out)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(&partial, path)
+}
+
+pub fn load(path: &Path) -> io::Result<Framebuffer> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(input)?))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::color::Color;
+
+    use super::*;
+
+    fn framebuffer(level: f64) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        for (i, pixel) in framebuffer.pixels_mut().iter_mut().enumerate() {
+            for sample in 0..=i {
+                let color = Color::new(level, sample as f64, i as f64);
+                pixel.add_sample(color, sample % 2 == 0);
+            }
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn round_trips() {
+        let framebuffer = framebuffer(0.5);
+        let mut bytes = Vec::new();
+        write(&framebuffer, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 16 + 6 * 36);
+
+        let read = read(&mut bytes.as_slice()).unwrap();
+        assert_eq!((read.width(), read.height()), (3, 2));
+        assert_eq!(read.pixels(), framebuffer.pixels());
+
+        assert!(super::read(&mut &bytes[..20]).is_err());
+        assert!(super::read(&mut &b"PNG\0\0\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_the_data_doesnt_have() {
+        let mut bytes = Vec::new();
+        write(&framebuffer(0.5), &mut bytes).unwrap();
+        let truncated = read(&mut &bytes[..bytes.len() - 1]).err().unwrap();
+        assert_eq!(truncated.kind(), io::ErrorKind::InvalidData);
+
+        // A header alone, claiming 65535x65535 pixels.
+        let mut header = bytes[..8].to_vec();
+        header.extend_from_slice(&[0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0]);
+        let oversized = read(&mut header.as_slice()).err().unwrap();
+        assert_eq!(oversized.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn merging_matches_sampling_together() {
+        let mut merged = framebuffer(1.0);
+        merged.merge(&framebuffer(3.0));
+        let pixel = merged.pixels()[5];
+        assert_eq!(pixel.samples(), 12);
+        assert_eq!(pixel.color(), Color::new(2.0, 2.5, 5.0));
+    }
+}
@@ -12,3 +12,8 @@ pub const BLACK: Color = Color {
     y: 0.0,
     z: 0.0,
 };
+
+/// Relative luminance of a linear Rec. 709 / sRGB color.
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
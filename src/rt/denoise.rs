@@ -0,0 +1,297 @@
+//! Edge-avoiding à-trous wavelet filtering, as in "Spatiotemporal Variance-Guided
+//! Filtering" (Schied et al. 2017) without the temporal part.
+//!
+//! The radiance is divided by the albedo so texture detail doesn't get blurred, then
+//! filtered with a 5x5 kernel whose taps spread further apart on every pass. Taps count
+//! less the more their luminance differs relative to the noise in it, and, when the
+//! framebuffer has AOVs, the more their normal and depth differ.
+
+use rayon::prelude::*;
+
+use super::{
+    color::{self, Color},
+    framebuffer::Framebuffer,
+    vec3::Vec3,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DenoiseSettings {
+    /// Filter passes, the filter reaches 4 * (2^iterations - 1) pixels across.
+    pub iterations: usize,
+    /// Luminance differences are measured in standard deviations of the noise, times this.
+    pub sigma_luminance: f64,
+    /// Exponent of the cosine between two normals.
+    pub sigma_normal: f64,
+    /// Relative change in depth allowed per pixel of distance.
+    pub sigma_depth: f64,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        DenoiseSettings {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+/// The B3 spline the wavelet transform is built on.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Albedo below this isn't divided out, it would only blow up the noise.
+const MIN_ALBEDO: f64 = 0.01;
+
+#[derive(Copy, Clone)]
+struct Guide {
+    /// Normal and depth of the first hit, `None` for the background or without AOVs.
+    surface: Option<(Vec3, f64)>,
+    /// What the radiance was divided by.
+    albedo: Color,
+}
+
+struct Image {
+    width: usize,
+    height: usize,
+    color: Vec<Color>,
+    variance: Vec<f64>,
+}
+
+/// Replaces the radiance in `framebuffer` with a filtered one, leaving the sample counts,
+/// coverage and AOVs alone.
+pub fn denoise(framebuffer: &mut Framebuffer, settings: &DenoiseSettings) {
+    let (width, height) = (framebuffer.width(), framebuffer.height());
+    let guides: Vec<Guide> = match framebuffer.aovs() {
+        Some(aovs) => aovs
+            .pixels()
+            .iter()
+            .map(|pixel| {
+                let albedo = pixel.albedo();
+                Guide {
+                    surface: pixel.depth().map(|depth| (pixel.normal(), depth)),
+                    albedo: Color::new(
+                        demodulation(albedo.x),
+                        demodulation(albedo.y),
+                        demodulation(albedo.z),
+                    ),
+                }
+            })
+            .collect(),
+        None => vec![
+            Guide {
+                surface: None,
+                albedo: color::WHITE,
+            };
+            width * height
+        ],
+    };
+
+    let (color, variance) = framebuffer
+        .pixels()
+        .iter()
+        .zip(&guides)
+        .map(|(pixel, guide)| {
+            let color = pixel.color();
+            let illumination = divide(color, guide.albedo);
+            let scale = match color::luminance(color) {
+                l if l > 0.0 => color::luminance(illumination) / l,
+                _ => 1.0,
+            };
+            (illumination, pixel.variance() * scale * scale)
+        })
+        .unzip();
+    let mut image = Image {
+        width,
+        height,
+        color,
+        variance,
+    };
+    image.fill_unknown_variance();
+
+    for iteration in 0..settings.iterations {
+        image = image.filter(1 << iteration, &guides, settings);
+    }
+
+    for ((pixel, color), guide) in framebuffer
+        .pixels_mut()
+        .iter_mut()
+        .zip(image.color)
+        .zip(&guides)
+    {
+        if pixel.samples() > 0 {
+            pixel.set_color(color * guide.albedo);
+        }
+    }
+}
+
+fn demodulation(albedo: f64) -> f64 {
+    if albedo < MIN_ALBEDO {
+        1.0
+    } else {
+        albedo
+    }
+}
+
+fn divide(a: Color, b: Color) -> Color {
+    Color::new(a.x / b.x, a.y / b.y, a.z / b.z)
+}
+
+impl Image {
+    /// Pixels with fewer than two samples get the variance of the luminance around them.
+    fn fill_unknown_variance(&mut self) {
+        let estimates: Vec<Option<f64>> = (0..self.color.len())
+            .into_par_iter()
+            .map(|i| {
+                if self.variance[i].is_finite() {
+                    return None;
+                }
+                let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+                self.for_neighbors(i, 1, |j, _, _| {
+                    let l = color::luminance(self.color[j]);
+                    sum += l;
+                    sum_sq += l * l;
+                    n += 1.0;
+                });
+                Some((sum_sq / n - (sum / n) * (sum / n)).max(0.0))
+            })
+            .collect();
+        for (variance, estimate) in self.variance.iter_mut().zip(estimates) {
+            if let Some(estimate) = estimate {
+                *variance = estimate;
+            }
+        }
+    }
+
+    /// Calls `f` with the index and offset of the pixels up to `radius` steps away.
+    fn for_neighbors(&self, i: usize, radius: isize, f: impl FnMut(usize, isize, isize)) {
+        self.for_taps(i, radius, 1, f)
+    }
+
+    /// Like `for_neighbors`, with the taps `step` pixels apart.
+    fn for_taps(
+        &self,
+        i: usize,
+        radius: isize,
+        step: isize,
+        mut f: impl FnMut(usize, isize, isize),
+    ) {
+        let (x, y) = ((i % self.width) as isize, (i / self.width) as isize);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (qx, qy) = (x + dx * step, y + dy * step);
+                if qx >= 0 && qy >= 0 && (qx as usize) < self.width && (qy as usize) < self.height {
+                    f(qy as usize * self.width + qx as usize, dx, dy);
+                }
+            }
+        }
+    }
+
+    /// The variance blurred by a 3x3 Gaussian, which steadies the luminance weights.
+    fn blurred_variance(&self, i: usize) -> f64 {
+        const GAUSSIAN: [f64; 3] = [0.25, 0.5, 0.25];
+        let (mut sum, mut weights) = (0.0, 0.0);
+        self.for_neighbors(i, 1, |j, dx, dy| {
+            let w = GAUSSIAN[(dx + 1) as usize] * GAUSSIAN[(dy + 1) as usize];
+            sum += w * self.variance[j];
+            weights += w;
+        });
+        sum / weights
+    }
+
+    fn filter(&self, step: isize, guides: &[Guide], settings: &DenoiseSettings) -> Image {
+        let (color, variance) = (0..self.color.len())
+            .into_par_iter()
+            .map(|p| {
+                let luminance = color::luminance(self.color[p]);
+                let sigma = settings.sigma_luminance * self.blurred_variance(p).sqrt() + 1e-6;
+                let (mut sum, mut sum_variance, mut weights) = (color::BLACK, 0.0, 0.0);
+                self.for_taps(p, 2, step, |q, dx, dy| {
+                    let distance = ((dx * dx + dy * dy) as f64).sqrt() * step as f64;
+                    let w = KERNEL[(dx + 2) as usize]
+                        * KERNEL[(dy + 2) as usize]
+                        * (-(luminance - color::luminance(self.color[q])).abs() / sigma).exp()
+                        * surface_weight(guides[p], guides[q], distance, settings);
+                    sum = sum + w * self.color[q];
+                    sum_variance += w * w * self.variance[q];
+                    weights += w;
+                });
+                // The center tap always has a weight, so `weights` is positive.
+                (sum / weights, sum_variance / (weights * weights))
+            })
+            .unzip();
+        Image {
+            width: self.width,
+            height: self.height,
+            color,
+            variance,
+        }
+    }
+}
+
+fn surface_weight(p: Guide, q: Guide, distance: f64, settings: &DenoiseSettings) -> f64 {
+    match (p.surface, q.surface) {
+        (Some((normal_p, depth_p)), Some((normal_q, depth_q))) => {
+            let cosine = Vec3::dot(normal_p, normal_q).max(0.0);
+            let depth_scale = settings.sigma_depth * depth_p * distance.max(1.0) + 1e-6;
+            cosine.powf(settings.sigma_normal) * (-(depth_p - depth_q).abs() / depth_scale).exp()
+        }
+        (None, None) => 1.0,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Left half dark, right half bright, with noise on top.
+    fn noisy_step(width: usize, height: usize, samples: usize) -> Framebuffer {
+        fastrand::seed(3);
+        let mut framebuffer = Framebuffer::new(width, height);
+        for (i, pixel) in framebuffer.pixels_mut().iter_mut().enumerate() {
+            let level = if i % width < width / 2 { 0.1 } else { 1.0 };
+            for _ in 0..samples {
+                let noise = level * (fastrand::f64() - 0.5);
+                pixel.add_sample(Color::new(1.0, 1.0, 1.0) * (level + noise), true);
+            }
+        }
+        framebuffer
+    }
+
+    fn error(framebuffer: &Framebuffer) -> f64 {
+        let width = framebuffer.width();
+        framebuffer
+            .pixels()
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let level = if i % width < width / 2 { 0.1 } else { 1.0 };
+                (pixel.color().x - level).abs()
+            })
+            .sum::<f64>()
+            / framebuffer.pixels().len() as f64
+    }
+
+    #[test]
+    fn removes_noise_but_keeps_edges() {
+        let mut framebuffer = noisy_step(32, 16, 4);
+        let before = error(&framebuffer);
+        denoise(&mut framebuffer, &DenoiseSettings::default());
+        let after = error(&framebuffer);
+        assert!(after < before / 3.0, "{} -> {}", before, after);
+
+        // The pixels on either side of the edge stay on their side.
+        assert!((framebuffer.pixel(15, 8).x - 0.1).abs() < 0.05);
+        assert!((framebuffer.pixel(16, 8).x - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn estimates_variance_of_single_samples_from_neighbors() {
+        let mut framebuffer = noisy_step(32, 16, 1);
+        let before = error(&framebuffer);
+        denoise(&mut framebuffer, &DenoiseSettings::default());
+        assert!(error(&framebuffer) < before / 2.0);
+        assert_eq!(framebuffer.pixels()[0].samples(), 1);
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use image::{
+    codecs::hdr::HdrEncoder, DynamicImage, ImageBuffer, ImageFormat, ImageResult, Rgb, RgbImage,
+    Rgba32FImage,
+};
+
+use super::{
+    aov::{AovPixel, Aovs},
+    color::{self, Color},
+    tonemap::DisplayTransform,
+};
+
+/// Running sums of the samples taken for one pixel: linear radiance in RGB and coverage,
+/// the share of camera rays that hit the scene, in alpha. Sums of the luminance and its
+/// square give the variance.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Accumulator {
+    sum: [f32; 4],
+    samples: u32,
+    luminance: [f64; 2],
+}
+
+impl Accumulator {
+    /// Adds the radiance a camera ray brought back and whether it hit the scene.
+    pub fn add_sample(&mut self, color: Color, hit: bool) {
+        self.sum[0] += color.x as f32;
+        self.sum[1] += color.y as f32;
+        self.sum[2] += color.z as f32;
+        self.sum[3] += hit as u32 as f32;
+        self.samples += 1;
+        let luminance = color::luminance(color);
+        self.luminance[0] += luminance;
+        self.luminance[1] += luminance * luminance;
+    }
+
+    /// Adds the samples of `other`, taken independently of these.
+    pub fn merge(&mut self, other: &Accumulator) {
+        for (sum, other) in self.sum.iter_mut().zip(other.sum) {
+            *sum += other;
+        }
+        self.samples += other.samples;
+        self.luminance[0] += other.luminance[0];
+        self.luminance[1] += other.luminance[1];
+    }
+
+    /// Adds `light` to the sum of the samples without taking one, for light found by the
+    /// samples of other pixels. Leaves the variance alone.
+    pub fn add_light(&mut self, light: Color) {
+        self.sum[0] += light.x as f32;
+        self.sum[1] += light.y as f32;
+        self.sum[2] += light.z as f32;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// The RGBA sums, sample count and luminance sums, for checkpoints.
+    pub(crate) fn to_raw(self) -> ([f32; 4], u32, [f64; 2]) {
+        (self.sum, self.samples, self.luminance)
+    }
+
+    pub(crate) fn from_raw(sum: [f32; 4], samples: u32, luminance: [f64; 2]) -> Accumulator {
+        Accumulator {
+            sum,
+            samples,
+            luminance,
+        }
+    }
+
+    /// The estimated variance of the mean luminance, infinite before the second sample.
+    pub fn variance(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let [sum, sum_sq] = self.luminance;
+        let sample_variance = (sum_sq - sum * sum / n).max(0.0) / (n - 1.0);
+        sample_variance / n
+    }
+
+    /// The mean of the samples, black and transparent before the first one.
+    pub fn rgba(&self) -> [f32; 4] {
+        if self.samples == 0 {
+            return [0.0; 4];
+        }
+        let n = self.samples as f32;
+        self.sum.map(|c| c / n)
+    }
+
+    /// Moves the mean radiance to `color`, keeping the number of samples, the coverage
+    /// and the variance.
+    pub fn set_color(&mut self, color: Color) {
+        let n = self.samples as f64;
+        self.sum[0] = (color.x * n) as f32;
+        self.sum[1] = (color.y * n) as f32;
+        self.sum[2] = (color.z * n) as f32;
+        if self.samples > 0 {
+            let [sum, sum_sq] = self.luminance;
+            let new_sum = color::luminance(color) * n;
+            self.luminance = [new_sum, sum_sq - sum * sum / n + new_sum * new_sum / n];
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        let [r, g, b, _] = self.rgba();
+        Color::new(r as f64, g as f64, b as f64)
+    }
+}
+
+/// File formats a framebuffer can be written to. The 8-bit formats of the `image` crate
+/// get pixels through a display transform, the others keep the linear radiance.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Ldr(ImageFormat),
+    /// 32-bit float RGBA OpenEXR.
+    OpenExr,
+    /// Radiance RGBE (.hdr).
+    Radiance,
+    /// Portable float map, RGB.
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_extension(extension: &str) -> Option<OutputFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "exr" => Some(OutputFormat::OpenExr),
+            "hdr" => Some(OutputFormat::Radiance),
+            "pfm" => Some(OutputFormat::Pfm),
+            extension => ImageFormat::from_extension(extension).map(OutputFormat::Ldr),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        OutputFormat::from_extension(path.extension()?.to_str()?)
+    }
+}
+
+/// Accumulated samples of every pixel, and optionally AOVs. Rows are stored top to
+/// bottom, like in the image files written from it.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Accumulator>,
+    aovs: Option<Aovs>,
+}
+
+/// The pixels of one row, with their AOVs if the framebuffer has them.
+pub(crate) struct Row<'a> {
+    pub pixels: &'a mut [Accumulator],
+    pub aovs: Option<(&'a mut [AovPixel], &'a mut [Color])>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Accumulator::default(); width * height],
+            aovs: None,
+        }
+    }
+
+    /// A framebuffer that also collects AOVs, with the given light groups.
+    pub fn with_aovs(width: usize, height: usize, light_groups: Vec<String>) -> Framebuffer {
+        Framebuffer {
+            aovs: Some(Aovs::new(width, height, light_groups)),
+            ..Framebuffer::new(width, height)
+        }
+    }
+
+    pub(crate) fn from_pixels(
+        width: usize,
+        height: usize,
+        pixels: Vec<Accumulator>,
+    ) -> Framebuffer {
+        assert_eq!(pixels.len(), width * height);
+        Framebuffer {
+            width,
+            height,
+            pixels,
+            aovs: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The mean radiance of the pixel.
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x].color()
+    }
+
+    /// Replaces the samples of the pixel with a single opaque one.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let mut pixel = Accumulator::default();
+        pixel.add_sample(color, true);
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    pub fn pixels(&self) -> &[Accumulator] {
+        &self.pixels
+    }
+
+    /// The samples a pixel has on average, rounded down.
+    pub fn mean_samples(&self) -> usize {
+        let samples: usize = self.pixels.iter().map(|p| p.samples() as usize).sum();
+        samples / self.pixels.len().max(1)
+    }
+
+    /// Adds the samples of a framebuffer of the same size, leaving the AOVs alone.
+    pub fn merge(&mut self, other: &Framebuffer) {
+        assert_eq!(
+            (self.width, self.height),
+            (other.width, other.height),
+            "only framebuffers of the same size can be merged"
+        );
+        for (pixel, other) in self.pixels.iter_mut().zip(&other.pixels) {
+            pixel.merge(other);
+        }
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Accumulator] {
+        &mut self.pixels
+    }
+
+    pub fn aovs(&self) -> Option<&Aovs> {
+        self.aovs.as_ref()
+    }
+
+    pub(crate) fn rows_mut(&mut self) -> Vec<Row<'_>> {
+        let rows = self.pixels.chunks_exact_mut(self.width.max(1));
+        match &mut self.aovs {
+            Some(aovs) => rows
+                .zip(aovs.rows_mut())
+                .map(|(pixels, aovs)| Row {
+                    pixels,
+                    aovs: Some(aovs),
+                })
+                .collect(),
+            None => rows.map(|pixels| Row { pixels, aovs: None }).collect(),
+        }
+    }
+
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> RgbImage {
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            Rgb(display.apply(self.pixel(x as usize, y as usize)))
+        })
+    }
+
+    /// Linear radiance with coverage in alpha.
+    pub fn to_rgba32f(&self) -> Rgba32FImage {
+        let data = self.pixels.iter().flat_map(Accumulator::rgba).collect();
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, data)
+            .expect("the buffer has four channels per pixel")
+    }
+
+    /// Writes the image, `display` only applies to 8-bit formats.
+    pub fn save(
+        &self,
+        path: &Path,
+        format: OutputFormat,
+        display: &DisplayTransform,
+    ) -> ImageResult<()> {
+        match format {
+            OutputFormat::Ldr(format) => self.to_rgb8(display).save_with_format(path, format),
+            OutputFormat::OpenExr => DynamicImage::ImageRgba32F(self.to_rgba32f())
+                .save_with_format(path, ImageFormat::OpenExr),
+            OutputFormat::Radiance => {
+                let pixels: Vec<Rgb<f32>> = self
+                    .pixels
+                    .iter()
+                    .map(|pixel| {
+                        let [r, g, b, _] = pixel.rgba();
+                        Rgb([r, g, b])
+                    })
+                    .collect();
+                HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(
+                    &pixels,
+                    self.width,
+                    self.height,
+                )
+            }
+            OutputFormat::Pfm => {
+                let mut file = BufWriter::new(File::create(path)?);
+                self.write_pfm(&mut file)?;
+                Ok(file.flush()?)
+            }
+        }
+    }
+
+    /// PFM stores rows bottom to top; the negative scale marks little-endian floats.
+    fn write_pfm(&self, out: &mut impl Write) -> std::io::Result<()> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks_exact(self.width.max(1)).rev() {
+            for pixel in row {
+                for channel in &pixel.rgba()[..3] {
+                    out.write_all(&channel.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framebuffer() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(2, 2);
+        framebuffer.pixels[0].add_sample(Color::new(3.0, 1.5, 0.0), true);
+        framebuffer.pixels[0].add_sample(color::BLACK, false);
+        framebuffer.set_pixel(1, 1, Color::new(0.25, 0.5, 1.0));
+        framebuffer
+    }
+
+    #[test]
+    fn averages_samples_and_coverage() {
+        let framebuffer = framebuffer();
+        assert_eq!(framebuffer.pixels()[0].rgba(), [1.5, 0.75, 0.0, 0.5]);
+        assert_eq!(framebuffer.pixel(1, 1), Color::new(0.25, 0.5, 1.0));
+        assert_eq!(framebuffer.pixels()[1].rgba(), [0.0; 4]);
+    }
+
+    #[test]
+    fn estimates_the_variance_of_the_mean() {
+        let mut pixel = Accumulator::default();
+        pixel.add_sample(Color::new(1.0, 1.0, 1.0), true);
+        assert_eq!(pixel.variance(), f64::INFINITY);
+        for luminance in [3.0, 1.0, 3.0] {
+            pixel.add_sample(Color::new(luminance, luminance, luminance), true);
+        }
+        // Sample variance 4/3, over 4 samples.
+        assert!((pixel.variance() - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn keeps_radiance_above_one() {
+        let image = framebuffer().to_rgba32f();
+        assert_eq!(image.get_pixel(0, 0).0, [1.5, 0.75, 0.0, 0.5]);
+        let display = DisplayTransform::default();
+        assert_eq!(
+            framebuffer().to_rgb8(&display).get_pixel(0, 0).0,
+            [255, 225, 0]
+        );
+    }
+
+    #[test]
+    fn writes_pfm_bottom_row_first() {
+        let mut out = Vec::new();
+        framebuffer().write_pfm(&mut out).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats.len(), 12);
+        assert_eq!(floats[3..6], [0.25, 0.5, 1.0]);
+        assert_eq!(floats[6..9], [1.5, 0.75, 0.0]);
+    }
+
+    #[test]
+    fn picks_formats_from_extensions() {
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out/render.EXR")),
+            Some(OutputFormat::OpenExr)
+        );
+        assert_eq!(
+            OutputFormat::from_extension("hdr"),
+            Some(OutputFormat::Radiance)
+        );
+        assert_eq!(
+            OutputFormat::from_extension("png"),
+            Some(OutputFormat::Ldr(ImageFormat::Png))
+        );
+        assert_eq!(OutputFormat::from_extension("unknown"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::rt::{
+    color::{self, Color},
+    onb::Onb,
+    random_cosine_direction,
+    ray::Ray,
+    scene::Scene,
+    shapes::{hit_record::HitRecord, Hittable},
+};
+
+use super::Integrator;
+
+/// Shades the first hit by how much of the hemisphere above it is open, weighted by the
+/// cosine: white where nothing is within `distance`, black in closed corners and for the
+/// background. Ignores materials and lights.
+#[derive(Debug)]
+pub struct AmbientOcclusion {
+    /// Occlusion rays per camera ray.
+    pub samples: usize,
+    /// How far occluders count, a tenth of the size of the scene when `None`.
+    pub distance: Option<f64>,
+    /// The bits of the distance used in the current pass, set by `begin_pass` so the
+    /// size of the scene isn't measured for every camera ray. Infinite before the first
+    /// pass when `distance` is `None`.
+    pass_distance: AtomicU64,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion::new(4, None)
+    }
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: usize, distance: Option<f64>) -> Self {
+        AmbientOcclusion {
+            samples,
+            distance,
+            pass_distance: AtomicU64::new(distance.unwrap_or(f64::INFINITY).to_bits()),
+        }
+    }
+
+    fn distance(&self, scene: &Scene) -> f64 {
+        if let Some(distance) = self.distance {
+            return distance;
+        }
+        let camera = &scene.camera;
+        match scene.world.bounding_box(camera.time0, camera.time1) {
+            Some(bbox) => 0.1 * (bbox.max - bbox.min).length(),
+            None => f64::INFINITY,
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn begin_pass(&self, scene: &Scene, _pass: usize) {
+        let distance = self.distance(scene);
+        self.pass_distance
+            .store(distance.to_bits(), Ordering::Relaxed);
+    }
+
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        _light_groups: &mut [Color],
+    ) -> Color {
+        let Some(rec) = hit else {
+            return color::BLACK;
+        };
+        if self.samples == 0 {
+            return color::BLACK;
+        }
+        let distance = f64::from_bits(self.pass_distance.load(Ordering::Relaxed));
+        let uvw = Onb::build_from_w(rec.normal);
+        let open = (0..self.samples)
+            .filter(|_| {
+                let direction = uvw.local(random_cosine_direction());
+                let ray = Ray::new(rec.p, direction, r.time);
+                // The direction is a unit vector, so `t` is the distance.
+                scene.world.hit(&ray, 0.001, distance).is_none()
+            })
+            .count();
+        let visibility = open as f64 / self.samples as f64;
+        Color::new(visibility, visibility, visibility)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{integrators::tests::room, Point3};
+
+    use super::*;
+
+    fn visibility(scene: &Scene, target: Point3) -> f64 {
+        let integrator = AmbientOcclusion::new(256, None);
+        integrator.begin_pass(scene, 0);
+        let origin = Point3::new(0.0, 1.0, -3.0);
+        let r = Ray::new(origin, target - origin, 0.0);
+        let hit = scene.world.hit(&r, 0.001, f64::INFINITY);
+        integrator.radiance(scene, &r, hit, &mut []).x
+    }
+
+    #[test]
+    fn darkens_corners() {
+        // A floor meeting a wall at z = 2, more than the distance from the open floor.
+        let scene = room(0, false);
+        fastrand::seed(5);
+        assert_eq!(visibility(&scene, Point3::new(0.0, 0.0, -5.0)), 1.0);
+        let corner = visibility(&scene, Point3::new(0.0, 0.0, 1.95));
+        assert!(corner > 0.0 && corner < 0.9, "{}", corner);
+    }
+}
@@ -0,0 +1,628 @@
+//! Bidirectional path tracing, after Veach's thesis (1997, chapter 10) and the way pbrt
+//! computes the MIS weights.
+
+use std::sync::{Arc, RwLock};
+
+use crate::rt::{
+    color::{self, Color},
+    materials::scatter_record::ScatterRecord,
+    onb::Onb,
+    random_cosine_direction, random_f64,
+    ray::Ray,
+    scene::Scene,
+    shapes::{hit_record::HitRecord, Hittable},
+    splat::SplatBuffer,
+    vec3::Vec3,
+    Point3, PI,
+};
+
+use super::{russian_roulette, Gather, Integrator};
+
+/// For every camera ray, walks a subpath from the camera and one from the lights, joins
+/// every prefix of the first with every prefix of the second by a shadow ray, and every
+/// prefix of the light subpath with the lens. Each path found is weighted with the power
+/// heuristic over all the ways of splitting it between the two subpaths.
+///
+/// Paths joined to the lens land in whichever pixel they project to, so during a pass they
+/// go into a splat buffer that the renderer adds to the image at the end. Those are the
+/// ones that find caustics seen on diffuse surfaces, light focused by glass onto a floor
+/// say, which the camera subpath only finds by chance. Without a pass, like when
+/// `radiance` is called by itself, they aren't made and the other splits make up for them.
+///
+/// Light subpaths start at a point sampled on `scene.lights` the way the path tracer
+/// samples them, as seen from the camera, so all of them are sampled alike whatever the
+/// camera ray, then leave the light in a cosine distributed direction. Paths hit the scene
+/// up to `camera.max_depth` times, with Russian roulette after `path_settings.roulette_depth`
+/// bounces of either subpath.
+#[derive(Debug, Default)]
+pub struct Bdpt {
+    /// Where the paths joined to the lens go during a pass.
+    splats: RwLock<Option<Arc<SplatBuffer>>>,
+}
+
+impl Integrator for Bdpt {
+    fn begin_pass(&self, scene: &Scene, _pass: usize) {
+        let camera = &scene.camera;
+        let splats = SplatBuffer::new(camera.width, camera.height, scene.light_groups.len());
+        *self.splats.write().unwrap() = Some(Arc::new(splats));
+    }
+
+    fn end_pass(&self) -> Option<SplatBuffer> {
+        let splats = self.splats.write().unwrap().take()?;
+        // The pass is over, nothing else holds the buffer.
+        Arc::try_unwrap(splats).ok()
+    }
+
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        light_groups: &mut [Color],
+    ) -> Color {
+        let max_depth = scene.camera.max_depth;
+        if max_depth == 0 {
+            return color::BLACK;
+        }
+        let splats = self.splats.read().unwrap().clone();
+        let mut gather = Gather::new(light_groups);
+
+        // Paths hit the scene up to `max_depth` times, like the path tracer's, and every
+        // split of them has to be made.
+        let mut camera = Vec::new();
+        if let Some(beta) = walk(scene, *r, hit, color::WHITE, None, max_depth, &mut camera) {
+            // Only the camera finds the background.
+            gather.add(0, beta * scene.background);
+        }
+        if let Some(first) = camera.first_mut() {
+            first.pdf_fwd = area_pdf(scene.camera.pdf_dir(r.direction), r.origin, &first.rec);
+        }
+        let light = light_subpath(scene, r.time, max_depth);
+
+        let light_tracing = splats.is_some();
+        for c in 1..=camera.len() {
+            for s in 0..=light.len().min(max_depth - c) {
+                if let Some((contribution, group)) =
+                    connect(scene, &camera, &light, s, c, r.time, light_tracing)
+                {
+                    gather.add(group, contribution);
+                }
+            }
+        }
+        if let Some(splats) = splats {
+            // The lens point of the camera ray is as good as any other.
+            for s in 1..=light.len() {
+                splat(scene, &light, s, r.origin, r.time, &splats);
+            }
+        }
+        gather.radiance
+    }
+}
+
+struct Vertex<'a> {
+    rec: HitRecord<'a>,
+    /// The ray that found the vertex.
+    r_in: Ray,
+    /// `None` where the path was absorbed, or started on a light.
+    scatter: Option<ScatterRecord>,
+    /// Light sent back along `r_in`.
+    emitted: Color,
+    /// Throughput of the subpath up to the vertex, over the density of sampling it.
+    beta: Color,
+    /// Area density of sampling the vertex from the previous one of its subpath.
+    pdf_fwd: f64,
+    /// Area density of sampling it from the next one, walking the other way.
+    /// Densities of sampling from a specular vertex are 1, they cancel out in the weights.
+    pdf_rev: f64,
+}
+
+impl<'a> Vertex<'a> {
+    fn is_delta(&self) -> bool {
+        matches!(self.scatter, Some(ScatterRecord::Specular { .. }))
+    }
+
+    /// What the vertex scatters towards `w` of the light arriving along `r_in`, including
+    /// the cosine.
+    fn f(&self, w: Vec3) -> Color {
+        match &self.scatter {
+            Some(ScatterRecord::Diffuse { attenuation, .. }) => {
+                let scattered = Ray::new(self.rec.p, w, self.r_in.time);
+                *attenuation
+                    * self
+                        .rec
+                        .material
+                        .scattering_pdf(&self.r_in, &self.rec, &scattered)
+            }
+            _ => color::BLACK,
+        }
+    }
+
+    /// Solid angle density of scattering towards `w`.
+    fn pdf_dir(&self, w: Vec3) -> f64 {
+        match &self.scatter {
+            Some(ScatterRecord::Diffuse { pdf, .. }) => pdf.value(Vec3::unit_vector(w)),
+            _ => 0.0,
+        }
+    }
+
+    /// Converts the solid angle density of sampling `next` from here to an area density.
+    fn to_area(&self, pdf_dir: f64, next: &Vertex) -> f64 {
+        area_pdf(pdf_dir, self.rec.p, &next.rec)
+    }
+}
+
+pub(super) fn area_pdf(pdf_dir: f64, from: Point3, to: &HitRecord) -> f64 {
+    let d = to.p - from;
+    let distance_squared = d.length_squared();
+    if distance_squared == 0.0 {
+        return 0.0;
+    }
+    pdf_dir * Vec3::dot(to.normal, d).abs() / (distance_squared * distance_squared.sqrt())
+}
+
+/// Extends `path` with the vertices found by following `ray`, which hit `hit`, until it
+/// leaves the scene, is absorbed, ended by Russian roulette or `path` has `max_vertices`.
+/// `beta` is the throughput carried by `ray`, `pdf_dir` the density it was sampled with at
+/// the last vertex of `path`, `None` for specular ones. Returns the throughput of a path
+/// that left the scene.
+fn walk<'a>(
+    scene: &'a Scene,
+    mut ray: Ray,
+    mut hit: Option<HitRecord<'a>>,
+    mut beta: Color,
+    mut pdf_dir: Option<f64>,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) -> Option<Color> {
+    let start = path.len();
+    while path.len() < max_vertices {
+        let rec = hit?;
+        let mut vertex = Vertex {
+            rec,
+            r_in: ray,
+            scatter: rec.material.scatter(&ray, &rec),
+            emitted: rec.material.emitted(&ray, &rec, rec.u, rec.v, rec.p),
+            beta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+
+        let mut next = None;
+        // Solid angle density of scattering back along `ray`, `None` for specular ones.
+        let mut pdf_rev = Some(0.0);
+        match &vertex.scatter {
+            None => {}
+            Some(ScatterRecord::Specular {
+                attenuation,
+                ray: scattered,
+            }) => {
+                next = Some((*scattered, *attenuation, None));
+                pdf_rev = None;
+            }
+            Some(ScatterRecord::Diffuse { pdf, .. }) => {
+                let direction = Vec3::unit_vector(pdf.generate());
+                let pdf_value = pdf.value(direction);
+                if pdf_value > 0.0 {
+                    let scattered = Ray::new(rec.p, direction, ray.time);
+                    next = Some((scattered, vertex.f(direction) / pdf_value, Some(pdf_value)));
+                }
+                pdf_rev = Some(vertex.pdf_dir(-ray.direction));
+            }
+        }
+        if let Some(previous) = path.last_mut() {
+            vertex.pdf_fwd = match pdf_dir {
+                Some(pdf_dir) => area_pdf(pdf_dir, previous.rec.p, &vertex.rec),
+                None => 1.0,
+            };
+            previous.pdf_rev = match pdf_rev {
+                Some(pdf_rev) => area_pdf(pdf_rev, vertex.rec.p, &previous.rec),
+                None => 1.0,
+            };
+        }
+        path.push(vertex);
+
+        let (scattered, weight, next_pdf) = next?;
+        beta = beta * weight;
+        if path.len() - start >= scene.path_settings.roulette_depth {
+            beta = russian_roulette(beta)?;
+        }
+        pdf_dir = next_pdf;
+        ray = scattered;
+        hit = scene.world.hit(&ray, 0.001, f64::INFINITY);
+    }
+    None
+}
+
+/// A subpath starting on the lights, sampled as seen from the camera.
+fn light_subpath(scene: &Scene, time: f64, max_vertices: usize) -> Vec<Vertex<'_>> {
+    let reference = scene.camera.origin;
+    let mut path = Vec::new();
+    let lights = &scene.lights;
+    if lights.objects.is_empty() || max_vertices == 0 {
+        return path;
+    }
+    let ray = Ray::new(reference, lights.random(reference, time), time);
+    let Some(rec) = lights.hit(&ray, 0.001, f64::INFINITY) else {
+        return path;
+    };
+    let pdf = light_pdf(lights, reference, &rec, time);
+    if pdf <= 0.0 {
+        return path;
+    }
+    path.push(Vertex {
+        rec,
+        r_in: ray,
+        scatter: None,
+        emitted: color::BLACK,
+        beta: color::WHITE / pdf,
+        pdf_fwd: pdf,
+        pdf_rev: 0.0,
+    });
+
+    let emission = Emission::of(&rec, time);
+    let Some(direction) = emission.sample() else {
+        return path;
+    };
+    let pdf_dir = emission.pdf(direction);
+    let cosine = Vec3::dot(emission.outward, direction).abs();
+    let beta = emitted_towards(&rec, direction, time) * (cosine / (pdf * pdf_dir));
+    if beta == color::BLACK {
+        return path;
+    }
+    let ray = Ray::new(rec.p, direction, time);
+    let hit = scene.world.hit(&ray, 0.001, f64::INFINITY);
+    walk(
+        scene,
+        ray,
+        hit,
+        beta,
+        Some(pdf_dir),
+        max_vertices,
+        &mut path,
+    );
+    path
+}
+
+/// The area density of `light_subpath` starting at `rec`, zero where it can't.
+pub(super) fn light_origin_pdf(
+    lights: &dyn Hittable,
+    reference: Point3,
+    rec: &HitRecord,
+    time: f64,
+) -> f64 {
+    let ray = Ray::new(reference, rec.p - reference, time);
+    match lights.hit(&ray, 0.001, f64::INFINITY) {
+        // The direction is as long as the way there, so the light is at `t` = 1.
+        Some(found) if (found.t - 1.0).abs() < 1e-6 => light_pdf(lights, reference, &found, time),
+        _ => 0.0,
+    }
+}
+
+/// The area density of sampling `rec` on `lights` from `reference`, given it's the first
+/// light that way.
+fn light_pdf(lights: &dyn Hittable, reference: Point3, rec: &HitRecord, time: f64) -> f64 {
+    let pdf = area_pdf(
+        lights.pdf_value(reference, rec.p - reference, time),
+        reference,
+        rec,
+    );
+    if pdf.is_finite() {
+        pdf
+    } else {
+        0.0
+    }
+}
+
+/// Light the surface hit at `rec` sends out in direction `w`.
+pub(super) fn emitted_towards(rec: &HitRecord, w: Vec3, time: f64) -> Color {
+    let outward = if rec.front_face {
+        rec.normal
+    } else {
+        -rec.normal
+    };
+    let r = Ray::new(rec.p + w, -w, time);
+    let mut seen = *rec;
+    seen.set_face_normal(&r, outward);
+    rec.material.emitted(&r, &seen, rec.u, rec.v, rec.p)
+}
+
+/// The sides of a surface that emit light, sampled with a cosine distribution each.
+pub(super) struct Emission {
+    pub outward: Vec3,
+    /// Whether the side `outward` points to emits, and the other side.
+    sides: [bool; 2],
+}
+
+impl Emission {
+    pub fn of(rec: &HitRecord, time: f64) -> Emission {
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        Emission {
+            outward,
+            sides: [outward, -outward].map(|w| emitted_towards(rec, w, time) != color::BLACK),
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.sides.iter().filter(|&&side| side).count()
+    }
+
+    pub fn pdf(&self, w: Vec3) -> f64 {
+        let cosine = Vec3::dot(self.outward, Vec3::unit_vector(w));
+        let side = if cosine > 0.0 { 0 } else { 1 };
+        if self.sides[side] {
+            cosine.abs() / (PI * self.count() as f64)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn sample(&self) -> Option<Vec3> {
+        let normal = match self.sides {
+            [true, true] if random_f64() < 0.5 => -self.outward,
+            [true, _] => self.outward,
+            [false, true] => -self.outward,
+            [false, false] => return None,
+        };
+        Some(Onb::build_from_w(normal).local(random_cosine_direction()))
+    }
+}
+
+/// Reverse densities of the vertices next to a connection, which depend on it.
+#[derive(Default)]
+struct Connection {
+    camera: [Option<f64>; 2],
+    light: [Option<f64>; 2],
+}
+
+/// The path made of the first `c` camera vertices and the first `s` light vertices, MIS
+/// weighted, with the light group it belongs to. `None` if it carries no light.
+fn connect(
+    scene: &Scene,
+    camera: &[Vertex],
+    light: &[Vertex],
+    s: usize,
+    c: usize,
+    time: f64,
+    light_tracing: bool,
+) -> Option<(Color, usize)> {
+    let pt = &camera[c - 1];
+    let mut connection = Connection::default();
+    let (contribution, group) = if s == 0 {
+        // The camera subpath found a light by itself.
+        if pt.emitted == color::BLACK {
+            return None;
+        }
+        let origin = light_origin_pdf(&scene.lights, scene.camera.origin, &pt.rec, time);
+        connection.camera[0] = Some(origin);
+        if c >= 2 {
+            let previous = &camera[c - 2];
+            let emission = Emission::of(&pt.rec, time);
+            let pdf_dir = emission.pdf(previous.rec.p - pt.rec.p);
+            connection.camera[1] = Some(pt.to_area(pdf_dir, previous));
+        }
+        (pt.beta * pt.emitted, pt.rec.material.light_group())
+    } else {
+        let qs = &light[s - 1];
+        let d = qs.rec.p - pt.rec.p;
+        let distance = d.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let w = d / distance;
+        let (f_qs, pdf_to_pt) = if s == 1 {
+            let emission = Emission::of(&qs.rec, time);
+            let cosine = Vec3::dot(emission.outward, w).abs();
+            (
+                emitted_towards(&qs.rec, -w, time) * cosine,
+                emission.pdf(-w),
+            )
+        } else {
+            (qs.f(-w), qs.pdf_dir(-w))
+        };
+        let contribution = pt.beta * pt.f(w) * f_qs * qs.beta / (distance * distance);
+        if contribution == color::BLACK {
+            return None;
+        }
+        let shadow = Ray::new(pt.rec.p, w, time);
+        if scene.world.hit(&shadow, 0.001, distance - 0.001).is_some() {
+            return None;
+        }
+
+        connection.camera[0] = Some(qs.to_area(pdf_to_pt, pt));
+        if c >= 2 {
+            let previous = &camera[c - 2];
+            let pdf_dir = pt.pdf_dir(previous.rec.p - pt.rec.p);
+            connection.camera[1] = Some(pt.to_area(pdf_dir, previous));
+        }
+        connection.light[0] = Some(pt.to_area(pt.pdf_dir(w), qs));
+        if s >= 2 {
+            let previous = &light[s - 2];
+            let pdf_dir = qs.pdf_dir(previous.rec.p - qs.rec.p);
+            connection.light[1] = Some(qs.to_area(pdf_dir, previous));
+        }
+        (contribution, light[0].rec.material.light_group())
+    };
+    Some((
+        contribution * mis_weight(camera, light, s, c, &connection, light_tracing),
+        group,
+    ))
+}
+
+/// Joins the first `s` light vertices to the point `lens` on the lens and adds the MIS
+/// weighted path to the pixel it lands in.
+fn splat(scene: &Scene, light: &[Vertex], s: usize, lens: Point3, time: f64, splats: &SplatBuffer) {
+    let qs = &light[s - 1];
+    let d = lens - qs.rec.p;
+    let distance = d.length();
+    if distance == 0.0 {
+        return;
+    }
+    let w = d / distance;
+    let Some((u, v)) = scene.camera.project(lens, qs.rec.p) else {
+        return;
+    };
+    let f_qs = if s == 1 {
+        let cosine = Vec3::dot(Emission::of(&qs.rec, time).outward, w).abs();
+        emitted_towards(&qs.rec, w, time) * cosine
+    } else {
+        qs.f(w)
+    };
+    // The density of a pixel's camera rays is the one over the whole image times the
+    // number of pixels.
+    let camera = &scene.camera;
+    let pdf_dir = camera.pdf_dir(-w);
+    let importance = pdf_dir * (camera.width * camera.height) as f64;
+    let contribution = f_qs * qs.beta * (importance / (distance * distance));
+    if contribution == color::BLACK {
+        return;
+    }
+    let shadow = Ray::new(qs.rec.p, w, time);
+    if scene.world.hit(&shadow, 0.001, distance - 0.001).is_some() {
+        return;
+    }
+
+    let mut connection = Connection::default();
+    connection.light[0] = Some(area_pdf(pdf_dir, lens, &qs.rec));
+    if s >= 2 {
+        let previous = &light[s - 2];
+        let pdf_dir = qs.pdf_dir(previous.rec.p - qs.rec.p);
+        connection.light[1] = Some(qs.to_area(pdf_dir, previous));
+    }
+    let weight = mis_weight(&[], light, s, 0, &connection, true);
+    splats.add(
+        u,
+        v,
+        light[0].rec.material.light_group(),
+        contribution * weight,
+    );
+}
+
+/// The power heuristic weight of making the path by connecting the first `s` light
+/// vertices to the first `c` camera vertices, over the other splits. Goes through the
+/// splits one vertex at a time, multiplying the ratio of their densities to this one's
+/// by the ratio of the densities of sampling the moved vertex from either side. Joining
+/// light subpaths to the lens counts with `light_tracing`.
+fn mis_weight(
+    camera: &[Vertex],
+    light: &[Vertex],
+    s: usize,
+    c: usize,
+    connection: &Connection,
+    light_tracing: bool,
+) -> f64 {
+    let ratio = |pdf_rev: f64, pdf_fwd: f64| {
+        if pdf_fwd > 0.0 {
+            pdf_rev / pdf_fwd
+        } else {
+            0.0
+        }
+    };
+    let mut sum = 0.0;
+
+    // Handing camera vertices to the light subpath, down to the first one when light
+    // subpaths are joined to the lens.
+    let last = if light_tracing { 0 } else { 1 };
+    let mut r = 1.0;
+    for k in (last..c).rev() {
+        let pdf_rev = match c - 1 - k {
+            0 => connection.camera[0],
+            1 => connection.camera[1],
+            _ => None,
+        };
+        r *= ratio(pdf_rev.unwrap_or(camera[k].pdf_rev), camera[k].pdf_fwd);
+        let previous_delta = k > 0 && camera[k - 1].is_delta();
+        if !camera[k].is_delta() && !previous_delta {
+            sum += r * r;
+        }
+    }
+
+    // Handing light vertices to the camera subpath.
+    let mut r = 1.0;
+    for i in (0..s).rev() {
+        let pdf_rev = match s - 1 - i {
+            0 => connection.light[0],
+            1 => connection.light[1],
+            _ => None,
+        };
+        r *= ratio(pdf_rev.unwrap_or(light[i].pdf_rev), light[i].pdf_fwd);
+        let previous_delta = i > 0 && light[i - 1].is_delta();
+        if !light[i].is_delta() && !previous_delta {
+            sum += r * r;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        framebuffer::Framebuffer,
+        integrators::tests::{mean, room, ReferencePathTracing},
+        renderer::{RenderSettings, Renderer},
+    };
+
+    use super::*;
+
+    /// Renders of `room(1, true)`, where the glass ball focuses the light onto the floor,
+    /// one for every seed.
+    fn caustic(integrator: Box<dyn Integrator>, seeds: u64) -> Vec<Framebuffer> {
+        let mut scene = room(1, true);
+        scene.camera.samples_per_pixel = 16;
+        scene.integrator = integrator;
+        (0..seeds)
+            .map(|seed| {
+                let settings = RenderSettings {
+                    seed: Some(seed),
+                    ..RenderSettings::default()
+                };
+                Renderer::new(&scene, settings).unwrap().render(|_| {}).0
+            })
+            .collect()
+    }
+
+    /// The mean over the images of the red channel, and the mean over the pixels of its
+    /// variance between the images.
+    fn mean_and_variance(images: &[Framebuffer]) -> (f64, f64) {
+        let n = images.len() as f64;
+        let pixels = images[0].pixels().len();
+        let (mut mean, mut variance) = (0.0, 0.0);
+        for i in 0..pixels {
+            let values: Vec<f64> = images
+                .iter()
+                .map(|image| image.pixels()[i].color().x)
+                .collect();
+            let pixel_mean = values.iter().sum::<f64>() / n;
+            mean += pixel_mean;
+            variance += values.iter().map(|v| (v - pixel_mean).powi(2)).sum::<f64>() / (n - 1.0);
+        }
+        (mean / pixels as f64, variance / pixels as f64)
+    }
+
+    #[test]
+    fn finds_caustics_with_less_noise_than_the_path_tracer() {
+        let (bdpt, bdpt_variance) = mean_and_variance(&caustic(Box::<Bdpt>::default(), 16));
+        let (path, path_variance) = mean_and_variance(&caustic(Box::new(ReferencePathTracing), 16));
+        assert!((bdpt - path).abs() < 0.03 * path, "{} vs {}", bdpt, path);
+        assert!(
+            bdpt_variance < 0.5 * path_variance,
+            "{} vs {}",
+            bdpt_variance,
+            path_variance
+        );
+    }
+
+    #[test]
+    fn agrees_with_the_path_tracer() {
+        let scene = room(1, false);
+        // Down onto the floor, behind the light.
+        let r = Ray::new(Point3::new(0.0, 1.0, -3.0), Vec3::new(0.0, -1.0, 3.5), 0.0);
+        fastrand::seed(13);
+        let bdpt = mean(&scene, &Bdpt::default(), &r, 20_000);
+        let path = mean(&scene, &ReferencePathTracing, &r, 100_000);
+        assert!((bdpt - path).abs() < 0.03 * path, "{} vs {}", bdpt, path);
+    }
+}
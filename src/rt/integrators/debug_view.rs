@@ -0,0 +1,97 @@
+use crate::rt::{
+    color::{self, Color},
+    ray::Ray,
+    scene::Scene,
+    shapes::{bvh_build, hit_record::HitRecord, Hittable},
+    vec3::Vec3,
+};
+
+use super::Integrator;
+
+/// False color pictures of what the first hit of every camera ray looks like to the
+/// renderer. The background is black.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    /// The shading normal, facing the ray, with its components mapped from [-1, 1] to
+    /// [0, 1].
+    Normal,
+    /// `u` in red, `v` in green.
+    Uv,
+    /// The barycentric weights of the three vertices in red, green and blue. Black for
+    /// anything that isn't a triangle.
+    Barycentric,
+    /// The BVH nodes visited to find the hit, from blue for none to red for `max` or more.
+    /// Unlike the others this counts for the background too. Only builds with the
+    /// `bvh_stats` feature count the nodes, see `BVH_COST_AVAILABLE`.
+    BvhCost { max: f64 },
+    /// Green for front faces, red for back faces, darker where the surface turns away.
+    Facing,
+}
+
+impl DebugView {
+    pub const BVH_COST_MAX: f64 = 100.0;
+    /// Whether this build can show `BvhCost`, it's all blue otherwise.
+    pub const BVH_COST_AVAILABLE: bool = bvh_build::COUNTS_NODE_VISITS;
+    pub const BVH_COST_UNAVAILABLE: &'static str =
+        "the bvh_cost view needs a build with --features bvh_stats";
+}
+
+impl Integrator for DebugView {
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        _light_groups: &mut [Color],
+    ) -> Color {
+        if let DebugView::BvhCost { max } = *self {
+            // The hit was found outside, trace it again to count the work.
+            let (_, visited) =
+                bvh_build::count_node_visits(|| scene.world.hit(r, 0.001, f64::INFINITY));
+            return heat(visited as f64 / max);
+        }
+        let Some(rec) = hit else {
+            return color::BLACK;
+        };
+        match *self {
+            DebugView::Normal => 0.5 * (rec.normal + Vec3::new(1.0, 1.0, 1.0)),
+            DebugView::Uv => Color::new(rec.u, rec.v, 0.0),
+            DebugView::Barycentric => match rec.barycentrics {
+                Some((b1, b2)) => Color::new(1.0 - b1 - b2, b1, b2),
+                None => color::BLACK,
+            },
+            DebugView::Facing => {
+                let cosine = Vec3::dot(rec.normal, Vec3::unit_vector(r.direction)).abs();
+                let shade = 0.2 + 0.8 * cosine;
+                if rec.front_face {
+                    Color::new(0.0, shade, 0.0)
+                } else {
+                    Color::new(shade, 0.0, 0.0)
+                }
+            }
+            DebugView::BvhCost { .. } => unreachable!("handled above"),
+        }
+    }
+}
+
+/// Blue, cyan, green, yellow and red for `t` going from 0 to 1.
+fn heat(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let ramp = |x: f64| x.clamp(0.0, 1.0);
+    Color::new(ramp(t - 2.0), ramp(t) - ramp(t - 3.0), 1.0 - ramp(t - 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heat_runs_from_blue_to_red() {
+        assert_eq!(heat(0.0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(heat(0.25), Color::new(0.0, 1.0, 1.0));
+        assert_eq!(heat(0.5), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(heat(0.75), Color::new(1.0, 1.0, 0.0));
+        assert_eq!(heat(1.0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(heat(7.0), heat(1.0));
+    }
+}
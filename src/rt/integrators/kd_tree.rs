@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+
+use crate::rt::Point3;
+
+/// Items with a position, for `KdTree`.
+pub trait KdItem {
+    fn position(&self) -> Point3;
+}
+
+/// A balanced kd-tree stored in one array: every range of items has its median on the
+/// splitting plane in the middle, the items before it on the lower side and those after
+/// it on the upper side. Splits go along the longest side of the range's bounds.
+#[derive(Debug, Clone)]
+pub struct KdTree<T> {
+    items: Vec<T>,
+    /// The axis of the split at each median.
+    axes: Vec<u8>,
+}
+
+impl<T> Default for KdTree<T> {
+    fn default() -> Self {
+        KdTree {
+            items: Vec::new(),
+            axes: Vec::new(),
+        }
+    }
+}
+
+impl<T: KdItem + Send> KdTree<T> {
+    /// Ranges at least this long are split on separate threads.
+    const PARALLEL_BUILD: usize = 16 * 1024;
+
+    pub fn build(mut items: Vec<T>) -> KdTree<T> {
+        let mut axes = vec![0; items.len()];
+        build(&mut items, &mut axes);
+        KdTree { items, axes }
+    }
+
+    /// Calls `f` with every item closer than `radius` to `center` and its squared
+    /// distance, in no particular order.
+    pub fn for_each_within(&self, center: Point3, radius: f64, mut f: impl FnMut(&T, f64)) {
+        self.visit(0, self.items.len(), center, radius * radius, &mut f);
+    }
+
+    fn visit(
+        &self,
+        start: usize,
+        end: usize,
+        center: Point3,
+        radius_squared: f64,
+        f: &mut impl FnMut(&T, f64),
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let item = &self.items[mid];
+        let position = item.position();
+        let axis = self.axes[mid] as i32;
+        let offset = center.get(axis) - position.get(axis);
+        let (near, far) = if offset < 0.0 {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.visit(near.0, near.1, center, radius_squared, f);
+        let distance_squared = (position - center).length_squared();
+        if distance_squared < radius_squared {
+            f(item, distance_squared);
+        }
+        if offset * offset < radius_squared {
+            self.visit(far.0, far.1, center, radius_squared, f);
+        }
+    }
+}
+
+fn build<T: KdItem + Send>(items: &mut [T], axes: &mut [u8]) {
+    if items.len() <= 1 {
+        return;
+    }
+    let (mut min, mut max) = (items[0].position(), items[0].position());
+    for item in items.iter() {
+        let p = item.position();
+        for axis in 0..3 {
+            min.set(axis, min.get(axis).min(p.get(axis)));
+            max.set(axis, max.get(axis).max(p.get(axis)));
+        }
+    }
+    let extent = max - min;
+    let axis = (0..3)
+        .max_by(|&a, &b| extent.get(a).total_cmp(&extent.get(b)))
+        .unwrap_or(0);
+
+    let len = items.len();
+    let mid = len / 2;
+    items.select_nth_unstable_by(mid, |a, b| {
+        let (a, b) = (a.position().get(axis), b.position().get(axis));
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    });
+    axes[mid] = axis as u8;
+
+    let (lower, rest) = items.split_at_mut(mid);
+    let (lower_axes, rest_axes) = axes.split_at_mut(mid);
+    let (upper, upper_axes) = (&mut rest[1..], &mut rest_axes[1..]);
+    if len >= KdTree::<T>::PARALLEL_BUILD {
+        rayon::join(|| build(lower, lower_axes), || build(upper, upper_axes));
+    } else {
+        build(lower, lower_axes);
+        build(upper, upper_axes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::random_vec3_between;
+
+    use super::*;
+
+    impl KdItem for Point3 {
+        fn position(&self) -> Point3 {
+            *self
+        }
+    }
+
+    #[test]
+    fn finds_the_same_points_as_a_linear_search() {
+        fastrand::seed(3);
+        let points: Vec<Point3> = (0..2000).map(|_| random_vec3_between(-1.0, 1.0)).collect();
+        let tree = KdTree::build(points.clone());
+        for _ in 0..50 {
+            let center = random_vec3_between(-1.2, 1.2);
+            let radius = 0.3;
+            let mut found = Vec::new();
+            tree.for_each_within(center, radius, |p, distance_squared| {
+                assert!((distance_squared - (*p - center).length_squared()).abs() < 1e-12);
+                found.push(*p);
+            });
+            let mut expected: Vec<Point3> = points
+                .iter()
+                .copied()
+                .filter(|p| (*p - center).length() < radius)
+                .collect();
+            let key = |p: &Point3| (p.x, p.y, p.z);
+            found.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            expected.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Integrators turn a camera ray into the light arriving along it. The path tracer makes
+//! the final image, the others are quicker or show what the renderer sees, for debugging
+//! scenes and materials.
+
+use self::{
+    ambient_occlusion::AmbientOcclusion,
+    bdpt::Bdpt,
+    debug_view::DebugView,
+    path_tracer::{DirectLighting, PathTracer, PathTracing},
+    photon_mapping::PhotonMapping,
+};
+
+use super::{
+    color::{self, Color},
+    pdfs::{hittable_pdf::HittablePdf, Pdf},
+    random_f64,
+    ray::Ray,
+    scene::Scene,
+    shapes::{hit_record::HitRecord, hittable_list::HittableList, Hittable},
+    splat::SplatBuffer,
+};
+
+pub mod ambient_occlusion;
+pub mod bdpt;
+pub mod debug_view;
+mod kd_tree;
+pub mod path_tracer;
+pub mod photon_mapping;
+
+/// Settings shared by the integrators that follow paths through the scene.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathSettings {
+    /// Bounces a path makes before Russian roulette may end it.
+    pub roulette_depth: usize,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            roulette_depth: PathTracer::ROULETTE_DEPTH,
+        }
+    }
+}
+
+pub trait Integrator: Send + Sync {
+    /// Called by the renderer before every pass over the image, for integrators that
+    /// prepare something for the whole image. Passes are numbered by the samples per pixel
+    /// the image already has, in batches of `batch_size`, or of the samples of the pass.
+    fn begin_pass(&self, _scene: &Scene, _pass: usize) {}
+
+    /// The samples per pixel of a pass, for integrators that prepare something for every
+    /// so many of them. The renderer splits longer passes, so `begin_pass` gets a new
+    /// number every `batch_size` samples whatever passes it's asked for.
+    fn batch_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Called by the renderer after every pass, for the light the pass found for pixels
+    /// other than the ones being sampled, which the renderer adds to the image.
+    fn end_pass(&self) -> Option<SplatBuffer> {
+        None
+    }
+
+    /// The light arriving along the camera ray `r`, which first hits the scene at `hit`.
+    /// What every light group contributes goes into `light_groups`, one sum per name in
+    /// `scene.light_groups`. Integrators that don't follow light leave it alone.
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        light_groups: &mut [Color],
+    ) -> Color;
+}
+
+pub const NAMES: &[&str] = &[
+    "path",
+    "direct",
+    "bdpt",
+    "photon_map",
+    "progressive_photon_map",
+    "ambient_occlusion",
+    "normal",
+    "uv",
+    "barycentric",
+    "bvh_cost",
+    "facing",
+];
+
+/// Looks an integrator up by name, with its default settings.
+pub fn from_name(name: &str) -> Option<Box<dyn Integrator>> {
+    let integrator: Box<dyn Integrator> = match name {
+        "path" => Box::new(PathTracing),
+        "direct" => Box::new(DirectLighting),
+        "bdpt" => Box::<Bdpt>::default(),
+        "photon_map" => Box::<PhotonMapping>::default(),
+        "progressive_photon_map" => {
+            Box::new(PhotonMapping::default().progressive(PhotonMapping::ALPHA))
+        }
+        "ambient_occlusion" => Box::<AmbientOcclusion>::default(),
+        "normal" => Box::new(DebugView::Normal),
+        "uv" => Box::new(DebugView::Uv),
+        "barycentric" => Box::new(DebugView::Barycentric),
+        "bvh_cost" => Box::new(DebugView::BvhCost {
+            max: DebugView::BVH_COST_MAX,
+        }),
+        "facing" => Box::new(DebugView::Facing),
+        _ => return None,
+    };
+    Some(integrator)
+}
+
+/// The light found along a camera ray, in total and by light group.
+struct Gather<'a> {
+    radiance: Color,
+    light_groups: &'a mut [Color],
+}
+
+impl<'a> Gather<'a> {
+    fn new(light_groups: &'a mut [Color]) -> Self {
+        Gather {
+            radiance: color::BLACK,
+            light_groups,
+        }
+    }
+
+    /// Adds `light` to the total, and to the sum of light group `group` when there is one.
+    fn add(&mut self, group: usize, light: Color) {
+        self.radiance = self.radiance + light;
+        if let Some(sum) = self.light_groups.get_mut(group) {
+            *sum = *sum + light;
+        }
+    }
+}
+
+/// Light found by a shadow ray towards a point sampled on the lights.
+struct LightSample {
+    ray: Ray,
+    /// Density of sampling the direction of `ray`.
+    pdf: f64,
+    emitted: Color,
+    light_group: usize,
+}
+
+/// Casts a shadow ray from `rec` towards a point sampled on `lights`, `None` when it finds
+/// no light.
+fn sample_light(
+    world: &dyn Hittable,
+    lights: &HittableList,
+    rec: &HitRecord,
+    time: f64,
+) -> Option<LightSample> {
+    if lights.objects.is_empty() {
+        return None;
+    }
+    let lights_pdf = HittablePdf::new(lights, rec.p, time);
+    let ray = Ray::new(rec.p, lights_pdf.generate(), time);
+    let pdf = lights_pdf.value(ray.direction);
+    if pdf <= 0.0 {
+        return None;
+    }
+    let light = world.hit(&ray, 0.001, f64::INFINITY)?;
+    let emitted = light
+        .material
+        .emitted(&ray, &light, light.u, light.v, light.p);
+    (emitted != color::BLACK).then(|| LightSample {
+        ray,
+        pdf,
+        emitted,
+        light_group: light.material.light_group(),
+    })
+}
+
+/// Ends a path with throughput `beta` with a probability that grows as the throughput
+/// falls. Returns the throughput of a path that survives, raised to count for the ones
+/// that didn't, so the expected image doesn't change.
+fn russian_roulette(beta: Color) -> Option<Color> {
+    let survival = beta.x.max(beta.y).max(beta.z).min(1.0);
+    if survival <= 0.0 || random_f64() >= survival {
+        None
+    } else {
+        Some(beta / survival)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::rt::{
+        camera::Camera,
+        materials::{dielectric::Dielectric, diffuse_light::DiffuseLight, lambertian::Lambertian},
+        shapes::{flip_face::FlipFace, sphere::Sphere, xy_rect::XyRect, xz_rect::XzRect},
+        vec3::Vec3,
+        Point3,
+    };
+
+    use super::*;
+
+    /// A white floor and back wall under `lights` small square lights in a row, starting
+    /// above the origin, with a glass ball under the first when `glass`.
+    pub(super) fn room(lights: usize, glass: bool) -> Scene {
+        let white = Arc::new(Lambertian::from_color(Color::new(0.8, 0.8, 0.8)));
+        let light = Arc::new(DiffuseLight::from_color(Color::new(8.0, 8.0, 8.0)));
+        let mut world = HittableList::default();
+        world.add(Arc::new(XzRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            white.clone(),
+        )));
+        world.add(Arc::new(XyRect::new(-10.0, 10.0, 0.0, 10.0, 2.0, white)));
+        for i in 0..lights {
+            let x = 1.5 * i as f64;
+            world.add(Arc::new(FlipFace::new(Arc::new(XzRect::new(
+                x - 0.5,
+                x + 0.5,
+                -0.5,
+                0.5,
+                1.5,
+                light.clone(),
+            )))));
+        }
+        if glass {
+            world.add(Arc::new(Sphere::new(
+                Point3::new(0.0, 0.6, 0.0),
+                0.3,
+                Arc::new(Dielectric::new(1.5)),
+            )));
+        }
+        let camera = Camera::new(
+            Point3::new(0.0, 1.0, -3.0),
+            Point3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            10.0,
+            0.0,
+            1.0,
+            1.0,
+            16,
+            1,
+            4,
+        );
+        Scene {
+            lights: world.lights(),
+            world,
+            camera,
+            background: color::BLACK,
+            light_groups: vec!["default".to_string()],
+            integrator: Box::new(PathTracing),
+            path_settings: PathSettings::default(),
+        }
+    }
+
+    /// The mean red radiance `integrator` finds along `r` over `samples` samples.
+    pub(super) fn mean(scene: &Scene, integrator: &dyn Integrator, r: &Ray, samples: usize) -> f64 {
+        let mut sum = 0.0;
+        for _ in 0..samples {
+            let hit = scene.world.hit(r, 0.001, f64::INFINITY);
+            sum += integrator.radiance(scene, r, hit, &mut []).x;
+        }
+        sum / samples as f64
+    }
+
+    /// The path tracer without Russian roulette, for the other integrators to agree with.
+    pub(super) struct ReferencePathTracing;
+
+    impl Integrator for ReferencePathTracing {
+        fn radiance(
+            &self,
+            scene: &Scene,
+            r: &Ray,
+            hit: Option<HitRecord>,
+            light_groups: &mut [Color],
+        ) -> Color {
+            let tracer = path_tracer::PathTracer::for_scene(scene).with_roulette_depth(usize::MAX);
+            tracer.shade(r, hit, light_groups)
+        }
+    }
+
+    /// Lets a test look into an integrator while a scene renders with it.
+    impl<T: Integrator> Integrator for Arc<T> {
+        fn begin_pass(&self, scene: &Scene, pass: usize) {
+            self.as_ref().begin_pass(scene, pass)
+        }
+
+        fn batch_size(&self) -> Option<usize> {
+            self.as_ref().batch_size()
+        }
+
+        fn end_pass(&self) -> Option<SplatBuffer> {
+            self.as_ref().end_pass()
+        }
+
+        fn radiance(
+            &self,
+            scene: &Scene,
+            r: &Ray,
+            hit: Option<HitRecord>,
+            light_groups: &mut [Color],
+        ) -> Color {
+            self.as_ref().radiance(scene, r, hit, light_groups)
+        }
+    }
+
+    #[test]
+    fn every_name_has_an_integrator() {
+        for name in NAMES {
+            assert!(from_name(name).is_some(), "{}", name);
+        }
+        assert!(from_name("whitted").is_none());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::rt::{
+    color::{self, Color},
+    materials::scatter_record::ScatterRecord,
+    pdfs::{hittable_pdf::HittablePdf, mixture_pdf::MixturePdf, Pdf},
+    ray::Ray,
+    scene::Scene,
+    shapes::{hit_record::HitRecord, hittable_list::HittableList, Hittable},
+};
+
+use super::{russian_roulette, sample_light, Gather, Integrator};
+
+/// The full path tracer, see `PathTracer`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PathTracing;
+
+impl Integrator for PathTracing {
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        light_groups: &mut [Color],
+    ) -> Color {
+        PathTracer::for_scene(scene).shade(r, hit, light_groups)
+    }
+}
+
+/// Only the light that reaches the first diffuse surface straight from an emitter or the
+/// background, seen directly or through mirrors and glass.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        light_groups: &mut [Color],
+    ) -> Color {
+        let tracer = PathTracer {
+            direct_only: true,
+            ..PathTracer::for_scene(scene)
+        };
+        tracer.shade(r, hit, light_groups)
+    }
+}
+
+/// Follows paths from the camera, adding the light found at every diffuse vertex through
+/// a shadow ray to a point sampled on `lights` (next event estimation).
+///
+/// The path continues in a direction drawn from the material's pdf, mixed half and half
+/// with `lights` when there are any, so importance targets like glass spheres still get
+/// rays. Light reaching a diffuse vertex can be found both ways, so both estimates are
+/// weighted with the power heuristic: emission found by the shadow ray by
+/// `p_light² / (p_light² + p_continue²)`, emission the path runs into by the opposite.
+///
+/// Paths are followed in a loop that carries their throughput. After `roulette_depth`
+/// bounces, Russian roulette ends them with a probability that grows as the throughput
+/// falls, and the survivors count for the ones it ended, so dim paths stop early without
+/// changing the expected image.
+pub struct PathTracer<'a> {
+    pub background: Color,
+    pub world: &'a dyn Hittable,
+    pub lights: &'a HittableList,
+    /// Bounces after which no more light is gathered.
+    pub max_depth: usize,
+    /// Bounces a path makes before Russian roulette may end it.
+    pub roulette_depth: usize,
+    /// End paths at the first diffuse surface, after gathering the light reaching it.
+    pub direct_only: bool,
+}
+
+impl<'a> PathTracer<'a> {
+    pub const ROULETTE_DEPTH: usize = 3;
+
+    pub fn new(
+        background: Color,
+        world: &'a dyn Hittable,
+        lights: &'a HittableList,
+        max_depth: usize,
+    ) -> Self {
+        PathTracer {
+            background,
+            world,
+            lights,
+            max_depth,
+            roulette_depth: Self::ROULETTE_DEPTH,
+            direct_only: false,
+        }
+    }
+
+    /// Traces `scene` with the bounce limit of its camera and its path settings.
+    pub fn for_scene(scene: &'a Scene) -> Self {
+        PathTracer::new(
+            scene.background,
+            &scene.world,
+            &scene.lights,
+            scene.camera.max_depth,
+        )
+        .with_roulette_depth(scene.path_settings.roulette_depth)
+    }
+
+    pub fn with_roulette_depth(mut self, roulette_depth: usize) -> Self {
+        self.roulette_depth = roulette_depth;
+        self
+    }
+
+    /// The light arriving along `r`. What every light group contributes is added to
+    /// `light_groups`.
+    pub fn trace(&self, r: &Ray, light_groups: &mut [Color]) -> Color {
+        if self.max_depth == 0 {
+            return color::BLACK;
+        }
+        let hit = self.world.hit(r, 0.001, f64::INFINITY);
+        self.shade(r, hit, light_groups)
+    }
+
+    /// `trace` for a ray that is known to hit the scene at `hit`. The background counts
+    /// towards light group 0.
+    pub fn shade(&self, r: &Ray, hit: Option<HitRecord>, light_groups: &mut [Color]) -> Color {
+        let mut gather = Gather::new(light_groups);
+
+        let mut ray = *r;
+        let mut hit = hit;
+        // Throughput of the path up to `ray`.
+        let mut beta = color::WHITE;
+        // How much emission found along `ray` counts, after MIS.
+        let mut emission_weight = 1.0;
+        let mut diffuse = false;
+        for bounce in 0..self.max_depth {
+            let Some(rec) = hit else {
+                gather.add(0, beta * self.background);
+                break;
+            };
+            let emitted = rec.material.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+            gather.add(rec.material.light_group(), emission_weight * beta * emitted);
+            if self.direct_only && diffuse {
+                break;
+            }
+
+            match rec.material.scatter(&ray, &rec) {
+                None => break,
+                Some(ScatterRecord::Specular {
+                    attenuation,
+                    ray: scattered,
+                }) => {
+                    beta = beta * attenuation;
+                    // A mirror direction can't be sampled on a light, the emission it
+                    // finds is all there is.
+                    emission_weight = 1.0;
+                    ray = scattered;
+                }
+                Some(ScatterRecord::Diffuse { attenuation, pdf }) => {
+                    diffuse = true;
+                    let lights_pdf = HittablePdf::new(self.lights, rec.p, ray.time);
+                    let mixture_pdf;
+                    let continue_pdf: &dyn Pdf = if self.lights.objects.is_empty() {
+                        pdf.as_ref()
+                    } else {
+                        mixture_pdf = MixturePdf::new(&lights_pdf, pdf.as_ref());
+                        &mixture_pdf
+                    };
+
+                    // The continuation finds the other half of the light the shadow ray
+                    // finds, so both end at the bounce limit.
+                    if bounce + 1 == self.max_depth {
+                        break;
+                    }
+                    if let Some(light) = sample_light(self.world, self.lights, &rec, ray.time) {
+                        let f = attenuation * rec.material.scattering_pdf(&ray, &rec, &light.ray);
+                        let weight =
+                            power_heuristic(light.pdf, continue_pdf.value(light.ray.direction));
+                        gather.add(
+                            light.light_group,
+                            beta * f * light.emitted * (weight / light.pdf),
+                        );
+                    }
+
+                    let scattered = Ray::new(rec.p, continue_pdf.generate(), ray.time);
+                    let pdf_value = continue_pdf.value(scattered.direction);
+                    if pdf_value <= 0.0 {
+                        break;
+                    }
+                    let weight = attenuation * rec.material.scattering_pdf(&ray, &rec, &scattered)
+                        / pdf_value;
+                    beta = beta * weight;
+                    emission_weight =
+                        power_heuristic(pdf_value, self.light_pdf(&lights_pdf, &scattered));
+                    ray = scattered;
+                }
+            }
+
+            if bounce + 1 >= self.roulette_depth {
+                let Some(survivor) = russian_roulette(beta) else {
+                    break;
+                };
+                beta = survivor;
+            }
+            hit = self.world.hit(&ray, 0.001, f64::INFINITY);
+        }
+        gather.radiance
+    }
+
+    /// The density of sampling the direction of `scattered` on the lights.
+    fn light_pdf(&self, lights_pdf: &HittablePdf, scattered: &Ray) -> f64 {
+        if self.lights.objects.is_empty() {
+            0.0
+        } else {
+            lights_pdf.value(scattered.direction)
+        }
+    }
+}
+
+/// The weight of a sample drawn with density `f` that another strategy could also have
+/// drawn with density `g`.
+fn power_heuristic(f: f64, g: f64) -> f64 {
+    let (f2, g2) = (f * f, g * g);
+    if f2 + g2 == 0.0 {
+        0.0
+    } else {
+        f2 / (f2 + g2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::rt::{
+        materials::{diffuse_light::DiffuseLight, lambertian::Lambertian},
+        shapes::{flip_face::FlipFace, xz_rect::XzRect},
+        vec3::Vec3,
+        Point3,
+    };
+
+    use super::*;
+
+    /// A white floor under a big square light.
+    fn scene() -> (HittableList, HittableList) {
+        let white = Arc::new(Lambertian::from_color(Color::new(0.8, 0.8, 0.8)));
+        let light = Arc::new(DiffuseLight::from_color(Color::new(4.0, 4.0, 4.0)));
+        let mut world = HittableList::default();
+        world.add(Arc::new(XzRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, white)));
+        world.add(Arc::new(FlipFace::new(Arc::new(XzRect::new(
+            -1.0, 1.0, -1.0, 1.0, 1.0, light,
+        )))));
+        let lights = world.lights();
+        (world, lights)
+    }
+
+    fn mean(tracer: &PathTracer, samples: usize) -> f64 {
+        let r = Ray::new(Point3::new(0.0, 0.5, -0.5), Vec3::new(0.0, -1.0, 1.0), 0.0);
+        let mut sum = 0.0;
+        for _ in 0..samples {
+            sum += tracer.trace(&r, &mut []).x;
+        }
+        sum / samples as f64
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() < tolerance * b, "{} vs {}", a, b);
+    }
+
+    #[test]
+    fn light_sampling_agrees_with_bsdf_sampling() {
+        let (world, lights) = scene();
+        assert_eq!(lights.objects.len(), 1);
+        fastrand::seed(11);
+        let with_lights = PathTracer::new(color::BLACK, &world, &lights, 2);
+        let no_lights = HittableList::default();
+        let without = PathTracer::new(color::BLACK, &world, &no_lights, 2);
+        assert_close(mean(&with_lights, 20_000), mean(&without, 200_000), 0.03);
+    }
+
+    #[test]
+    fn russian_roulette_keeps_the_expected_radiance() {
+        let (world, lights) = scene();
+        fastrand::seed(5);
+        let tracer = PathTracer::new(color::BLACK, &world, &lights, 8);
+        let roulette = tracer.with_roulette_depth(1);
+        let full = PathTracer::new(color::BLACK, &world, &lights, 8).with_roulette_depth(8);
+        assert_close(mean(&roulette, 50_000), mean(&full, 50_000), 0.03);
+    }
+
+    #[test]
+    fn direct_lighting_keeps_all_light_of_a_single_bounce() {
+        // Light only ever bounces once under the light, so nothing is indirect.
+        let (world, lights) = scene();
+        fastrand::seed(7);
+        let full = PathTracer::new(color::BLACK, &world, &lights, 8);
+        let direct = PathTracer {
+            direct_only: true,
+            ..PathTracer::new(color::BLACK, &world, &lights, 8)
+        };
+        assert_close(mean(&direct, 20_000), mean(&full, 20_000), 0.03);
+    }
+
+    #[test]
+    fn a_single_bounce_only_sees_emitters() {
+        // The floor is lit, but only through a second bounce.
+        let (world, lights) = scene();
+        fastrand::seed(3);
+        let tracer = PathTracer::new(color::BLACK, &world, &lights, 1);
+        assert_eq!(mean(&tracer, 1_000), 0.0);
+        let r = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert_eq!(tracer.trace(&r, &mut []), Color::new(4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn power_heuristic_weights_add_up_to_one() {
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert!((power_heuristic(2.0, 1.0) + power_heuristic(1.0, 2.0) - 1.0).abs() < 1e-12);
+        assert_eq!(power_heuristic(3.0, 1.0), 0.9);
+    }
+}
@@ -0,0 +1,471 @@
+use std::sync::{Arc, RwLock};
+
+use rayon::prelude::*;
+
+use crate::rt::{
+    color::{self, Color},
+    materials::scatter_record::ScatterRecord,
+    random_f64,
+    ray::Ray,
+    scene::Scene,
+    shapes::{hit_record::HitRecord, Hittable},
+    vec3::Vec3,
+    Point3, PI,
+};
+
+use super::{
+    bdpt::{emitted_towards, light_origin_pdf, Emission},
+    kd_tree::{KdItem, KdTree},
+    russian_roulette, sample_light, Gather, Integrator,
+};
+
+/// Photon mapping (Jensen, 1996). Before rendering, photons are shot from the emissive
+/// surfaces among `scene.lights` and stored in a kd-tree wherever they land on a diffuse
+/// surface after their first bounce. Camera rays follow mirrors and glass to the first
+/// diffuse surface, add the light reaching it straight from the lights through a shadow
+/// ray and estimate everything else, caustics included, from the density of the photons
+/// within `radius` of the hit.
+///
+/// The estimate blurs lighting over the radius. The progressive variant (Knaus and
+/// Zwicker, 2011) shoots a new map for every `samples_per_map` samples per pixel with a
+/// radius shrinking by `(i + alpha) / (i + 1)` in area after map `i`, so averaging them
+/// converges to the right image. Maps are numbered by the samples the image has, so
+/// resumed renders carry on shrinking it. Light from the background is only found by the camera rays and one
+/// ray leaving the diffuse surface, it isn't carried by photons.
+#[derive(Debug)]
+pub struct PhotonMapping {
+    /// Photons shot for every map.
+    pub photons: usize,
+    /// The radius of the estimate, of the first one for the progressive variant. A
+    /// hundredth of the size of the scene when `None`.
+    pub radius: Option<f64>,
+    /// Shoots a map for every `samples_per_map` samples per pixel, shrinking the radius by
+    /// `alpha`, rather than keeping the first.
+    pub alpha: Option<f64>,
+    pub samples_per_map: usize,
+    map: RwLock<Option<PhotonMap>>,
+}
+
+impl Default for PhotonMapping {
+    fn default() -> Self {
+        PhotonMapping::new(Self::PHOTONS, None)
+    }
+}
+
+impl PhotonMapping {
+    pub const PHOTONS: usize = 200_000;
+    pub const ALPHA: f64 = 2.0 / 3.0;
+    pub const SAMPLES_PER_MAP: usize = 4;
+
+    pub fn new(photons: usize, radius: Option<f64>) -> Self {
+        PhotonMapping {
+            photons,
+            radius,
+            alpha: None,
+            samples_per_map: Self::SAMPLES_PER_MAP,
+            map: RwLock::new(None),
+        }
+    }
+
+    pub fn progressive(mut self, alpha: f64) -> Self {
+        self.alpha = Some(alpha);
+        self
+    }
+
+    /// The radius of the estimate of map `pass`.
+    fn radius(&self, scene: &Scene, pass: usize) -> f64 {
+        let camera = &scene.camera;
+        let radius = self.radius.unwrap_or_else(|| {
+            match scene.world.bounding_box(camera.time0, camera.time1) {
+                Some(bbox) => 0.01 * (bbox.max - bbox.min).length(),
+                None => 1.0,
+            }
+        });
+        let alpha = self.alpha.unwrap_or(1.0);
+        let area = (1..=pass).fold(1.0, |area, i| area * (i as f64 + alpha) / (i as f64 + 1.0));
+        radius * area.sqrt()
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn begin_pass(&self, scene: &Scene, pass: usize) {
+        let mut map = self.map.write().unwrap();
+        if let Some(map) = map.as_ref() {
+            if self.alpha.is_none() || map.pass == pass {
+                return;
+            }
+        }
+        *map = Some(PhotonMap::shoot(
+            scene,
+            self.photons,
+            self.radius(scene, pass),
+            pass,
+        ));
+    }
+
+    fn batch_size(&self) -> Option<usize> {
+        self.alpha.map(|_| self.samples_per_map)
+    }
+
+    fn radiance(
+        &self,
+        scene: &Scene,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        light_groups: &mut [Color],
+    ) -> Color {
+        let mut gather = Gather::new(light_groups);
+
+        let mut ray = *r;
+        let mut hit = hit;
+        let mut beta = color::WHITE;
+        for _ in 0..scene.camera.max_depth {
+            let Some(rec) = hit else {
+                gather.add(0, beta * scene.background);
+                break;
+            };
+            let emitted = rec.material.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+            gather.add(rec.material.light_group(), beta * emitted);
+
+            match rec.material.scatter(&ray, &rec) {
+                None => break,
+                Some(ScatterRecord::Specular {
+                    attenuation,
+                    ray: scattered,
+                }) => {
+                    beta = beta * attenuation;
+                    ray = scattered;
+                    hit = scene.world.hit(&ray, 0.001, f64::INFINITY);
+                }
+                Some(ScatterRecord::Diffuse { attenuation, pdf }) => {
+                    let f = |w: Vec3| {
+                        let scattered = Ray::new(rec.p, w, ray.time);
+                        attenuation * rec.material.scattering_pdf(&ray, &rec, &scattered)
+                    };
+
+                    if let Some(light) = sample_light(&scene.world, &scene.lights, &rec, ray.time) {
+                        let direct = f(light.ray.direction) * light.emitted / light.pdf;
+                        gather.add(light.light_group, beta * direct);
+                    }
+
+                    let direction = pdf.generate();
+                    let pdf_value = pdf.value(direction);
+                    let escaped = Ray::new(rec.p, direction, ray.time);
+                    let missed = || scene.world.hit(&escaped, 0.001, f64::INFINITY).is_none();
+                    if pdf_value > 0.0 && missed() {
+                        gather.add(0, beta * f(direction) * scene.background / pdf_value);
+                    }
+
+                    if let Some(map) = self.map.read().unwrap().as_ref() {
+                        map.estimate(&rec, f, |group, light| gather.add(group, beta * light));
+                    }
+                    break;
+                }
+            }
+        }
+        gather.radiance
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Photon {
+    p: Point3,
+    /// Where the photon was going, a unit vector.
+    direction: Vec3,
+    /// The share of the emitted power it carries.
+    power: Color,
+    light_group: usize,
+}
+
+impl KdItem for Photon {
+    fn position(&self) -> Point3 {
+        self.p
+    }
+}
+
+#[derive(Debug)]
+struct PhotonMap {
+    photons: KdTree<Photon>,
+    radius: f64,
+    /// The pass it was shot for.
+    pass: usize,
+}
+
+impl PhotonMap {
+    /// Photons are traced in batches of this many, each with its own seed.
+    const BATCH: usize = 4096;
+
+    fn shoot(scene: &Scene, photons: usize, radius: f64, pass: usize) -> PhotonMap {
+        let camera = &scene.camera;
+        let emitters: Vec<Emitter> = scene
+            .lights
+            .objects
+            .iter()
+            .filter_map(|light| Emitter::new(light, camera.time0, camera.time1))
+            .collect();
+        if emitters.is_empty() || photons == 0 {
+            return PhotonMap {
+                photons: KdTree::default(),
+                radius,
+                pass,
+            };
+        }
+
+        // Drawn up front on this thread, so seeded renders shoot the same photons.
+        let seeds: Vec<u64> = (0..photons.div_ceil(Self::BATCH))
+            .map(|_| fastrand::u64(..))
+            .collect();
+        let stored = seeds
+            .into_par_iter()
+            .enumerate()
+            .flat_map_iter(|(batch, seed)| {
+                fastrand::seed(seed);
+                let count = Self::BATCH.min(photons - batch * Self::BATCH);
+                let mut stored = Vec::new();
+                for _ in 0..count {
+                    let time = camera.time0 + random_f64() * (camera.time1 - camera.time0);
+                    let emitter = &emitters[fastrand::usize(..emitters.len())];
+                    // Every emitter gets a share of the photons, each stands for the rest.
+                    let scale = emitters.len() as f64 / photons as f64;
+                    trace_photon(scene, emitter, time, scale, &mut stored);
+                }
+                stored
+            })
+            .collect();
+        PhotonMap {
+            photons: KdTree::build(stored),
+            radius,
+            pass,
+        }
+    }
+
+    /// Passes what the photons around `rec` send towards the camera, scattered by `f`,
+    /// to `gather` by light group.
+    fn estimate(
+        &self,
+        rec: &HitRecord,
+        f: impl Fn(Vec3) -> Color,
+        mut gather: impl FnMut(usize, Color),
+    ) {
+        let area = PI * self.radius * self.radius;
+        self.photons
+            .for_each_within(rec.p, self.radius, |photon, _| {
+                let incoming = -photon.direction;
+                let cosine = Vec3::dot(rec.normal, incoming);
+                if cosine > 0.0 {
+                    // The photon's power is already per area, `f` includes the cosine.
+                    gather(
+                        photon.light_group,
+                        f(incoming) * photon.power / (cosine * area),
+                    );
+                }
+            });
+    }
+}
+
+/// Follows a photon leaving `emitter` until it's absorbed, leaves the scene or Russian
+/// roulette ends it, storing it in `stored` at every diffuse surface after its first
+/// bounce. Its power is scaled by `scale`.
+fn trace_photon(scene: &Scene, emitter: &Emitter, time: f64, scale: f64, stored: &mut Vec<Photon>) {
+    let Some((rec, pdf)) = emitter.sample(time) else {
+        return;
+    };
+    let emission = Emission::of(&rec, time);
+    let Some(direction) = emission.sample() else {
+        return;
+    };
+    let pdf = pdf * emission.pdf(direction);
+    if pdf <= 0.0 {
+        return;
+    }
+    let cosine = Vec3::dot(emission.outward, direction).abs();
+    let power = emitted_towards(&rec, direction, time) * (scale * cosine / pdf);
+    let light_group = rec.material.light_group();
+
+    let mut ray = Ray::new(rec.p, direction, time);
+    let mut beta = color::WHITE;
+    for bounce in 0..scene.camera.max_depth {
+        let Some(rec) = scene.world.hit(&ray, 0.001, f64::INFINITY) else {
+            break;
+        };
+        match rec.material.scatter(&ray, &rec) {
+            None => break,
+            Some(ScatterRecord::Specular {
+                attenuation,
+                ray: scattered,
+            }) => {
+                beta = beta * attenuation;
+                ray = scattered;
+            }
+            Some(ScatterRecord::Diffuse { attenuation, pdf }) => {
+                // Light straight from the lights is left to shadow rays.
+                if bounce > 0 {
+                    stored.push(Photon {
+                        p: rec.p,
+                        direction: Vec3::unit_vector(ray.direction),
+                        power: beta * power,
+                        light_group,
+                    });
+                }
+                let scattered = Ray::new(rec.p, pdf.generate(), time);
+                let pdf_value = pdf.value(scattered.direction);
+                if pdf_value <= 0.0 {
+                    break;
+                }
+                let f = attenuation * rec.material.scattering_pdf(&ray, &rec, &scattered);
+                beta = beta * f / pdf_value;
+                ray = scattered;
+            }
+        }
+        if bounce + 1 >= scene.path_settings.roulette_depth {
+            let Some(survivor) = russian_roulette(beta) else {
+                break;
+            };
+            beta = survivor;
+        }
+    }
+}
+
+/// An emissive light, sampled with `Hittable::random` as seen from the corners of a box
+/// around it, one picked at random for every photon. The corners are far enough out to
+/// see every side of a convex light between them.
+struct Emitter<'a> {
+    light: &'a dyn Hittable,
+    corners: [Point3; 8],
+}
+
+impl<'a> Emitter<'a> {
+    /// Tries to hit the light to tell whether it's emissive, `None` if it isn't.
+    const PROBES: usize = 16;
+
+    fn new(light: &'a Arc<dyn Hittable>, time0: f64, time1: f64) -> Option<Emitter<'a>> {
+        let bbox = light.bounding_box(time0, time1)?;
+        let center = 0.5 * (bbox.min + bbox.max);
+        let margin = 0.1 * (bbox.max - bbox.min).length() + 1e-3;
+        let half = 0.5 * (bbox.max - bbox.min) + Vec3::new(margin, margin, margin);
+        let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+            center + Vec3::new(sign(1) * half.x, sign(2) * half.y, sign(4) * half.z)
+        });
+        let emitter = Emitter {
+            light: light.as_ref(),
+            corners,
+        };
+        let emissive = (0..Self::PROBES)
+            .filter_map(|_| emitter.sample(time0))
+            .any(|(rec, _)| rec.material.is_emissive());
+        emissive.then_some(emitter)
+    }
+
+    /// A point on the light and its area density.
+    fn sample(&self, time: f64) -> Option<(HitRecord<'a>, f64)> {
+        let corner = self.corners[fastrand::usize(..self.corners.len())];
+        let ray = Ray::new(corner, self.light.random(corner, time), time);
+        let rec = self.light.hit(&ray, 0.001, f64::INFINITY)?;
+        let pdf = self
+            .corners
+            .iter()
+            .map(|&corner| light_origin_pdf(self.light, corner, &rec, time))
+            .sum::<f64>()
+            / self.corners.len() as f64;
+        (pdf > 0.0).then_some((rec, pdf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        checkpoint,
+        integrators::tests::{mean, room, ReferencePathTracing},
+        renderer::{RenderSettings, Renderer},
+    };
+
+    use super::*;
+
+    #[test]
+    fn agrees_with_the_path_tracer() {
+        // Up onto the wall above the lights, which only light off the floor reaches.
+        let r = Ray::new(Point3::new(0.0, 1.0, -3.0), Vec3::new(0.0, 2.0, 5.0), 0.0);
+        fastrand::seed(17);
+        for lights in [1, 2] {
+            let scene = room(lights, false);
+            let photon_mapping = PhotonMapping::new(400_000, Some(0.25));
+            photon_mapping.begin_pass(&scene, 0);
+            let estimate = mean(&scene, &photon_mapping, &r, 2_000);
+            let expected = mean(&scene, &ReferencePathTracing, &r, 100_000);
+            assert!(
+                (estimate - expected).abs() < 0.1 * expected,
+                "{} lights: {} vs {}",
+                lights,
+                estimate,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_caustic_under_glass() {
+        let scene = room(1, true);
+        // Down onto the floor right under the ball, which the light can't reach directly.
+        let r = Ray::new(Point3::new(0.0, 0.1, -1.0), Vec3::new(0.0, -0.1, 1.0), 0.0);
+        fastrand::seed(19);
+        let photon_mapping = PhotonMapping::new(100_000, Some(0.05));
+        let before = mean(&scene, &photon_mapping, &r, 100);
+        photon_mapping.begin_pass(&scene, 0);
+        let after = mean(&scene, &photon_mapping, &r, 100);
+        assert!(after > 2.0 * before, "{} vs {}", after, before);
+    }
+
+    #[test]
+    fn progressive_radius_shrinks_every_pass() {
+        let scene = room(1, false);
+        let fixed = PhotonMapping::new(0, Some(1.0));
+        assert_eq!(fixed.radius(&scene, 5), 1.0);
+        let progressive = PhotonMapping::new(0, Some(1.0)).progressive(PhotonMapping::ALPHA);
+        assert_eq!(progressive.radius(&scene, 0), 1.0);
+        let radii: Vec<f64> = (1..10)
+            .map(|pass| progressive.radius(&scene, pass))
+            .collect();
+        assert!(radii.windows(2).all(|pair| pair[1] < pair[0]));
+        let second = ((1.0 + PhotonMapping::ALPHA) / 2.0).sqrt();
+        assert!((radii[0] - second).abs() < 1e-12);
+    }
+
+    #[test]
+    fn progressive_maps_follow_the_samples_in_the_image() {
+        let progressive = |scene: &mut Scene| {
+            let mut photon_mapping =
+                PhotonMapping::new(100, Some(1.0)).progressive(PhotonMapping::ALPHA);
+            photon_mapping.samples_per_map = 2;
+            let photon_mapping = Arc::new(photon_mapping);
+            scene.integrator = Box::new(photon_mapping.clone());
+            photon_mapping
+        };
+        let map_pass = |photon_mapping: &PhotonMapping| {
+            let map = photon_mapping.map.read().unwrap();
+            map.as_ref().map(|map| (map.pass, map.radius))
+        };
+
+        let mut scene = room(1, false);
+        let photon_mapping = progressive(&mut scene);
+        let renderer = Renderer::new(&scene, RenderSettings::default()).unwrap();
+        let mut framebuffer = renderer.framebuffer();
+        // A single pass still gets a map for every 2 samples.
+        renderer.render_pass(&mut framebuffer, 5, |_| {});
+        let expected = (2, photon_mapping.radius(&scene, 2));
+        assert_eq!(map_pass(&photon_mapping), Some(expected));
+
+        // Resuming from a checkpoint finishes the third map's samples with a new one, then
+        // goes on to the fourth.
+        let mut saved = Vec::new();
+        checkpoint::write(&framebuffer, &mut saved).unwrap();
+        let mut resumed = checkpoint::read(&mut saved.as_slice()).unwrap();
+        let mut scene = room(1, false);
+        let photon_mapping = progressive(&mut scene);
+        let renderer = Renderer::new(&scene, RenderSettings::default()).unwrap();
+        renderer.render_pass(&mut resumed, 2, |_| {});
+        let expected = (3, photon_mapping.radius(&scene, 3));
+        assert_eq!(map_pass(&photon_mapping), Some(expected));
+        assert_eq!(resumed.mean_samples(), 7);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use super::shapes::triangle_mesh::MeshError;
+
+pub mod mtl;
+pub mod obj;
+pub mod ply;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// Malformed data with no line to point at.
+    Format {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            LoadError::Image { path, source } => write!(f, "{}: {}", path.display(), source),
+            LoadError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            LoadError::Format { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::Image { source, .. } => Some(source),
+            LoadError::Parse { .. } | LoadError::Format { .. } => None,
+        }
+    }
+}
+
+/// Position of the line being parsed, used to build `LoadError::Parse` values.
+struct LineContext<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> LineContext<'a> {
+    fn error(&self, message: impl Into<String>) -> LoadError {
+        LoadError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn parse<T: FromStr>(&self, token: Option<&str>, what: &str) -> Result<T, LoadError> {
+        let token = token.ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} '{}'", what, token)))
+    }
+}
+
+fn io_error(path: &Path, source: io::Error) -> LoadError {
+    LoadError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn mesh_error(path: &Path, error: MeshError) -> LoadError {
+    LoadError::Format {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    }
+}
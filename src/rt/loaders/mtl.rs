@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::rt::{
+    color::{self, Color},
+    materials::{
+        dielectric::Dielectric, diffuse_light::DiffuseLight, lambertian::Lambertian, metal::Metal,
+        Material,
+    },
+    textures::image_texture::ImageTexture,
+};
+
+use super::{io_error, LineContext, LoadError};
+
+/// A material definition as read from a `.mtl` file, before it is mapped onto one of the
+/// renderer's materials.
+#[derive(Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub kd: Color,
+    pub ks: Color,
+    pub ke: Color,
+    pub ns: f64,
+    pub ni: f64,
+    pub dissolve: f64,
+    pub illum: i32,
+    pub map_kd: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    pub fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name,
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: color::BLACK,
+            ke: color::BLACK,
+            ns: 0.0,
+            ni: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+
+    /// Emissive materials become `DiffuseLight`, transparent ones (mostly see-through with
+    /// `d < 0.5`, or a refractive illumination model) `Dielectric`, mirror-like ones `Metal`
+    /// with a fuzz derived from `Ns`, and everything else `Lambertian`, textured when
+    /// `map_Kd` is set. A slightly transparent diffuse material stays diffuse, there's no
+    /// partial transparency to give it.
+    pub fn to_material(&self) -> Result<Arc<dyn Material>, LoadError> {
+        if !is_black(self.ke) {
+            return Ok(Arc::new(DiffuseLight::from_color(self.ke)));
+        }
+
+        if self.dissolve < 0.5 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let ior = if self.ni > 1.0 { self.ni } else { 1.5 };
+            return Ok(Arc::new(Dielectric::new(ior)));
+        }
+
+        let reflective = matches!(self.illum, 3 | 5 | 8);
+        if !is_black(self.ks) && (reflective || (is_black(self.kd) && self.map_kd.is_none())) {
+            // Map the Phong exponent to a roughness in the usual Blinn-Phong fashion.
+            let fuzz = f64::sqrt(2.0 / (self.ns.max(0.0) + 2.0));
+            return Ok(Arc::new(Metal::new(self.ks, fuzz)));
+        }
+
+        match &self.map_kd {
+            Some(path) => {
+                let texture = ImageTexture::open(path).map_err(|source| LoadError::Image {
+                    path: path.clone(),
+                    source,
+                })?;
+                Ok(Arc::new(Lambertian::from_texture(Arc::new(texture))))
+            }
+            None => Ok(Arc::new(Lambertian::from_color(self.kd))),
+        }
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<HashMap<String, Arc<dyn Material>>, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| io_error(path, e))?;
+    let mut materials = HashMap::new();
+    for mtl in parse(BufReader::new(file), path)? {
+        materials.insert(mtl.name.clone(), mtl.to_material()?);
+    }
+    Ok(materials)
+}
+
+/// Parses `.mtl` statements. `path` is used for error messages and to resolve texture
+/// file names relative to the material library.
+pub fn parse(reader: impl BufRead, path: &Path) -> Result<Vec<MtlMaterial>, LoadError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| io_error(path, e))?;
+        let ctx = LineContext {
+            path,
+            line: index + 1,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(ctx.error("newmtl without a name"));
+            }
+            materials.push(MtlMaterial::new(name));
+            continue;
+        }
+
+        let current = match materials.last_mut() {
+            Some(current) => current,
+            None => return Err(ctx.error(format!("'{}' before any newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => current.kd = parse_color(&ctx, &mut tokens)?,
+            "Ks" => current.ks = parse_color(&ctx, &mut tokens)?,
+            "Ke" => current.ke = parse_color(&ctx, &mut tokens)?,
+            "Ns" => current.ns = ctx.parse(tokens.next(), "Ns value")?,
+            "Ni" => current.ni = ctx.parse(tokens.next(), "Ni value")?,
+            "d" => current.dissolve = ctx.parse(tokens.next(), "dissolve value")?,
+            "Tr" => current.dissolve = 1.0 - ctx.parse::<f64>(tokens.next(), "Tr value")?,
+            "illum" => current.illum = ctx.parse(tokens.next(), "illum value")?,
+            "map_Kd" => {
+                // Texture options such as `-s 1 1 1` precede the file name.
+                let file_name = tokens
+                    .last()
+                    .ok_or_else(|| ctx.error("map_Kd without a file name"))?;
+                current.map_kd = Some(base_dir.join(file_name.replace('\\', "/")));
+            }
+            // Ka, Tf, bump maps and the other statements have no equivalent here.
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+fn parse_color<'a>(
+    ctx: &LineContext,
+    tokens: &mut impl Iterator<Item = &'a str>,
+) -> Result<Color, LoadError> {
+    let r = ctx.parse(tokens.next(), "red component")?;
+    // A single value is shorthand for a grey.
+    match tokens.next() {
+        None => Ok(Color::new(r, r, r)),
+        g => Ok(Color::new(
+            r,
+            ctx.parse(g, "green component")?,
+            ctx.parse(tokens.next(), "blue component")?,
+        )),
+    }
+}
+
+fn is_black(c: Color) -> bool {
+    c.x <= 0.0 && c.y <= 0.0 && c.z <= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        materials::scatter_record::ScatterRecord, ray::Ray, shapes::hit_record::HitRecord,
+        vec3::Vec3,
+    };
+
+    use super::*;
+
+    fn parse_str(text: &str) -> Vec<MtlMaterial> {
+        parse(text.as_bytes(), Path::new("test.mtl")).unwrap()
+    }
+
+    /// Whether the material made from `text` scatters like glass or a mirror.
+    fn is_specular(text: &str) -> bool {
+        let material = parse_str(text)[0].to_material().unwrap();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = HitRecord::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            0.0,
+            0.0,
+            &ray,
+            Vec3::new(0.0, 0.0, 1.0),
+            material.as_ref(),
+        );
+        matches!(
+            material.scatter(&ray, &rec),
+            Some(ScatterRecord::Specular { .. })
+        )
+    }
+
+    #[test]
+    fn reads_statements() {
+        let materials = parse_str(
+            "newmtl red\nKd 1 0 0\nNs 10\nTr 0.25\nillum 2\nmap_Kd -s 1 1 1 tex\\red.png\n\
+             newmtl lamp\nKe 4 4 4\n",
+        );
+        assert_eq!(materials.len(), 2);
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert_eq!(red.kd, Color::new(1.0, 0.0, 0.0));
+        assert_eq!(red.ns, 10.0);
+        assert_eq!(red.dissolve, 0.75);
+        assert_eq!(red.map_kd, Some(PathBuf::from("tex/red.png")));
+        assert_eq!(materials[1].ke, Color::new(4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn statements_need_a_material() {
+        match parse("Kd 1 1 1\n".as_bytes(), Path::new("test.mtl")) {
+            Err(LoadError::Parse { line: 1, .. }) => {}
+            _ => panic!("Kd before newmtl was accepted"),
+        }
+    }
+
+    #[test]
+    fn only_see_through_or_refractive_materials_become_glass() {
+        assert!(!is_specular("newmtl a\nKd 0.5 0.5 0.5\nd 0.99\n"));
+        assert!(is_specular("newmtl a\nKd 0.5 0.5 0.5\nd 0.2\n"));
+        assert!(is_specular("newmtl a\nKd 0.5 0.5 0.5\nillum 7\n"));
+        assert!(!is_specular("newmtl a\nKd 0.5 0.5 0.5\n"));
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::rt::{
+    materials::Material,
+    shapes::{
+        hittable_list::HittableList,
+        triangle_mesh::{MeshData, TriangleMesh},
+    },
+    vec3::Vec3,
+    Point3,
+};
+
+use super::{io_error, mesh_error, mtl, LineContext, LoadError};
+
+/// Loads a Wavefront `.obj` file into one `TriangleMesh` per group and material. Material
+/// libraries referenced with `mtllib` are resolved relative to the `.obj` file; faces
+/// without a known material use `default_material`.
+pub fn load(
+    path: impl AsRef<Path>,
+    default_material: Arc<dyn Material>,
+) -> Result<HittableList, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| io_error(path, e))?;
+    parse(BufReader::new(file), path, default_material)
+}
+
+pub fn parse(
+    reader: impl BufRead,
+    path: &Path,
+    default_material: Arc<dyn Material>,
+) -> Result<HittableList, LoadError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+
+    let mut meshes: Vec<MeshBuilder> = Vec::new();
+    let mut mesh_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group = String::new();
+    let mut material_name: Option<String> = None;
+    let mut face: Vec<VertexKey> = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| io_error(path, e))?;
+        let ctx = LineContext {
+            path,
+            line: index + 1,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+
+        match keyword {
+            "v" => {
+                let x = ctx.parse(tokens.next(), "vertex x")?;
+                let y = ctx.parse(tokens.next(), "vertex y")?;
+                let z = ctx.parse(tokens.next(), "vertex z")?;
+                positions.push(Point3::new(x, y, z));
+            }
+            "vt" => {
+                let u = ctx.parse(tokens.next(), "texture u")?;
+                let v = match tokens.next() {
+                    None => 0.0,
+                    v => ctx.parse(v, "texture v")?,
+                };
+                uvs.push((u, v));
+            }
+            "vn" => {
+                let x = ctx.parse(tokens.next(), "normal x")?;
+                let y = ctx.parse(tokens.next(), "normal y")?;
+                let z = ctx.parse(tokens.next(), "normal z")?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            "f" => {
+                face.clear();
+                for token in tokens {
+                    face.push(parse_vertex(
+                        &ctx,
+                        token,
+                        positions.len(),
+                        uvs.len(),
+                        normals.len(),
+                    )?);
+                }
+                if face.len() < 3 {
+                    return Err(ctx.error("face with fewer than three vertices"));
+                }
+
+                let key = (group.clone(), material_name.clone());
+                let mesh_index = *mesh_lookup.entry(key).or_insert_with(|| {
+                    let material = material_name
+                        .as_ref()
+                        .and_then(|name| materials.get(name))
+                        .unwrap_or(&default_material)
+                        .clone();
+                    meshes.push(MeshBuilder::new(material));
+                    meshes.len() - 1
+                });
+                let mesh = &mut meshes[mesh_index];
+                let indices: Vec<usize> = face
+                    .iter()
+                    .map(|&key| mesh.vertex(key, &positions, &uvs, &normals))
+                    .collect();
+                // Fan triangulation, fine for the convex polygons OBJ exporters emit.
+                for i in 1..indices.len() - 1 {
+                    mesh.data
+                        .indices
+                        .push([indices[0], indices[i], indices[i + 1]]);
+                }
+            }
+            "g" | "o" => group = tokens.collect::<Vec<_>>().join(" "),
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    return Err(ctx.error("usemtl without a material name"));
+                }
+                material_name = Some(name);
+            }
+            "mtllib" => {
+                for file_name in tokens {
+                    materials.extend(mtl::load(base_dir.join(file_name))?);
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are not rendered.
+            _ => {}
+        }
+    }
+
+    let mut list = HittableList::default();
+    for mesh in meshes {
+        list.add(Arc::new(mesh.build(path)?));
+    }
+    Ok(list)
+}
+
+/// Zero-based position, texture coordinate and normal indices of one face vertex.
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+fn parse_vertex(
+    ctx: &LineContext,
+    token: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<VertexKey, LoadError> {
+    let mut parts = token.split('/');
+    let position = resolve_index(ctx, parts.next(), position_count, "vertex")?
+        .ok_or_else(|| ctx.error(format!("face vertex '{}' has no position", token)))?;
+    let uv = resolve_index(ctx, parts.next(), uv_count, "texture coordinate")?;
+    let normal = resolve_index(ctx, parts.next(), normal_count, "normal")?;
+    if parts.next().is_some() {
+        return Err(ctx.error(format!("malformed face vertex '{}'", token)));
+    }
+    Ok((position, uv, normal))
+}
+
+/// Converts a one-based OBJ index, or a negative one relative to the end of the list, to a
+/// zero-based index.
+fn resolve_index(
+    ctx: &LineContext,
+    token: Option<&str>,
+    count: usize,
+    what: &str,
+) -> Result<Option<usize>, LoadError> {
+    let token = match token {
+        None | Some("") => return Ok(None),
+        Some(token) => token,
+    };
+    let index: i64 = ctx.parse(Some(token), &format!("{} index", what))?;
+    if index == 0 {
+        return Err(ctx.error(format!("{} index 0, indices start at 1", what)));
+    }
+    let resolved = match index {
+        i if i > 0 => Some(i as usize - 1),
+        i => (count as i64).checked_add(i).map(|i| i as usize),
+    };
+    match resolved {
+        Some(i) if i < count => Ok(Some(i)),
+        _ => Err(ctx.error(format!("{} index {} out of range", what, index))),
+    }
+}
+
+struct MeshBuilder {
+    data: MeshData,
+    material: Arc<dyn Material>,
+    lookup: HashMap<VertexKey, usize>,
+    has_uvs: bool,
+    has_normals: bool,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<dyn Material>) -> MeshBuilder {
+        MeshBuilder {
+            data: MeshData::default(),
+            material,
+            lookup: HashMap::new(),
+            has_uvs: true,
+            has_normals: true,
+        }
+    }
+
+    fn vertex(
+        &mut self,
+        key: VertexKey,
+        positions: &[Point3],
+        uvs: &[(f64, f64)],
+        normals: &[Vec3],
+    ) -> usize {
+        if let Some(&index) = self.lookup.get(&key) {
+            return index;
+        }
+
+        let (position, uv, normal) = key;
+        self.has_uvs &= uv.is_some();
+        self.has_normals &= normal.is_some();
+        self.data.positions.push(positions[position]);
+        self.data.uvs.push(uv.map(|i| uvs[i]).unwrap_or_default());
+        self.data
+            .normals
+            .push(normal.map(|i| normals[i]).unwrap_or_default());
+
+        let index = self.data.positions.len() - 1;
+        self.lookup.insert(key, index);
+        index
+    }
+
+    fn build(mut self, path: &Path) -> Result<TriangleMesh, LoadError> {
+        // Attributes only make sense when every vertex of the mesh provides them.
+        if !self.has_uvs {
+            self.data.uvs.clear();
+        }
+        if !self.has_normals {
+            self.data.normals.clear();
+        }
+        TriangleMesh::new(self.data, self.material).map_err(|e| mesh_error(path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{color::Color, materials::lambertian::Lambertian};
+
+    use super::*;
+
+    fn parse_str(text: &str) -> Result<HittableList, LoadError> {
+        let material = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+        parse(text.as_bytes(), Path::new("test.obj"), material)
+    }
+
+    fn error_line(result: Result<HittableList, LoadError>) -> (usize, String) {
+        match result {
+            Err(LoadError::Parse { line, message, .. }) => (line, message),
+            Err(other) => panic!("unexpected error {}", other),
+            Ok(_) => panic!("malformed obj loaded"),
+        }
+    }
+
+    #[test]
+    fn loads_faces_per_group_and_material() {
+        let list = parse_str(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\n\
+             g a\nf 1/1/1 2/1/1 3/1/1 4/1/1\ng b\nf -4 -3 -2\n",
+        )
+        .unwrap();
+        assert_eq!(list.objects.len(), 2);
+    }
+
+    #[test]
+    fn rejects_index_zero() {
+        let vertices = "v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0 0\nvn 0 0 1\n";
+        let (line, message) = error_line(parse_str(&format!("{}f 0 1 2\n", vertices)));
+        assert_eq!(line, 6);
+        assert!(message.contains("vertex index 0"), "{}", message);
+
+        let (_, message) = error_line(parse_str(&format!("{}f 1/0 2/1 3/1\n", vertices)));
+        assert!(
+            message.contains("texture coordinate index 0"),
+            "{}",
+            message
+        );
+
+        let (_, message) = error_line(parse_str(&format!("{}f 1//0 2//1 3//1\n", vertices)));
+        assert!(message.contains("normal index 0"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_out_of_range_and_short_faces() {
+        let (line, message) = error_line(parse_str("v 0 0 0\nv 1 0 0\nf 1 2 3\n"));
+        assert_eq!(line, 3);
+        assert!(message.contains("out of range"), "{}", message);
+
+        let (_, message) = error_line(parse_str("v 0 0 0\nv 1 0 0\nf 1 2\n"));
+        assert!(message.contains("fewer than three"), "{}", message);
+
+        let (line, _) = error_line(parse_str("v 0 0\n"));
+        assert_eq!(line, 1);
+    }
+}
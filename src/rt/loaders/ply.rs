@@ -0,0 +1,602 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::rt::{
+    color::Color,
+    materials::Material,
+    shapes::triangle_mesh::{MeshData, TriangleMesh},
+    vec3::Vec3,
+    Point3,
+};
+
+use super::{io_error, mesh_error, LineContext, LoadError};
+
+/// Loads a `.ply` file as a single mesh using `material` for every face.
+pub fn load(
+    path: impl AsRef<Path>,
+    material: Arc<dyn Material>,
+) -> Result<TriangleMesh, LoadError> {
+    let path = path.as_ref();
+    TriangleMesh::new(read(path)?, material).map_err(|e| mesh_error(path, e))
+}
+
+/// Reads the vertex and face elements of a `.ply` file, including normals, texture
+/// coordinates and vertex colors when present. Use a `VertexColorTexture` to render the
+/// colors.
+pub fn read(path: impl AsRef<Path>) -> Result<MeshData, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| io_error(path, e))?;
+    parse(BufReader::new(file), path)
+}
+
+/// Parses ascii, binary_little_endian and binary_big_endian files. `path` is only used in
+/// error messages.
+pub fn parse(mut reader: impl BufRead, path: &Path) -> Result<MeshData, LoadError> {
+    let header = parse_header(&mut reader, path)?;
+    let mut body = match header.format {
+        Format::Ascii => Body::Ascii {
+            reader,
+            tokens: Vec::new(),
+            line: header.lines,
+        },
+        Format::BinaryLittleEndian => Body::Binary {
+            reader,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Body::Binary {
+            reader,
+            big_endian: true,
+        },
+    };
+
+    let mut data = MeshData::default();
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, path, element, &mut data)?,
+            "face" => read_faces(&mut body, path, element, &mut data)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        body.skip(path, property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    let vertex_count = data.positions.len();
+    if let Some(&index) = data.indices.iter().flatten().find(|&&i| i >= vertex_count) {
+        return Err(LoadError::Format {
+            path: path.to_path_buf(),
+            message: format!("face references vertex {} of {}", index, vertex_count),
+        });
+    }
+    Ok(data)
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::I8),
+            "uchar" | "uint8" => Some(ScalarType::U8),
+            "short" | "int16" => Some(ScalarType::I16),
+            "ushort" | "uint16" => Some(ScalarType::U16),
+            "int" | "int32" => Some(ScalarType::I32),
+            "uint" | "uint32" => Some(ScalarType::U32),
+            "float" | "float32" => Some(ScalarType::F32),
+            "double" | "float64" => Some(ScalarType::F64),
+            _ => None,
+        }
+    }
+
+    /// Scale that maps an integer color channel to [0,1], `None` for signed integers, which
+    /// have no such mapping.
+    fn color_scale(self) -> Option<f64> {
+        match self {
+            ScalarType::U8 => Some(1.0 / 255.0),
+            ScalarType::U16 => Some(1.0 / 65535.0),
+            ScalarType::U32 => Some(1.0 / u32::MAX as f64),
+            ScalarType::F32 | ScalarType::F64 => Some(1.0),
+            ScalarType::I8 | ScalarType::I16 | ScalarType::I32 => None,
+        }
+    }
+}
+
+enum Property {
+    Scalar {
+        name: String,
+        ty: ScalarType,
+    },
+    List {
+        name: String,
+        count_ty: ScalarType,
+        item_ty: ScalarType,
+    },
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar { name, .. } | Property::List { name, .. } => name,
+        }
+    }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    lines: usize,
+}
+
+fn parse_header(reader: &mut impl BufRead, path: &Path) -> Result<Header, LoadError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line = String::new();
+    let mut line_number = 0;
+
+    loop {
+        line.clear();
+        // The header is ascii even in binary files, so it can be read line by line as
+        // long as the body is left untouched.
+        let read = reader.read_line(&mut line).map_err(|e| io_error(path, e))?;
+        line_number += 1;
+        let ctx = LineContext {
+            path,
+            line: line_number,
+        };
+        if read == 0 {
+            return Err(ctx.error("missing end_header"));
+        }
+
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap_or_default();
+        if line_number == 1 {
+            if keyword != "ply" {
+                return Err(ctx.error("not a ply file"));
+            }
+            continue;
+        }
+
+        match keyword {
+            "format" => {
+                format = match tokens.next() {
+                    Some("ascii") => Some(Format::Ascii),
+                    Some("binary_little_endian") => Some(Format::BinaryLittleEndian),
+                    Some("binary_big_endian") => Some(Format::BinaryBigEndian),
+                    Some(other) => return Err(ctx.error(format!("unknown format '{}'", other))),
+                    None => return Err(ctx.error("missing format")),
+                }
+            }
+            "element" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| ctx.error("missing element name"))?
+                    .to_string();
+                let count = ctx.parse(tokens.next(), "element count")?;
+                elements.push(Element {
+                    name,
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            "property" => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| ctx.error("property before any element"))?;
+                let property = match tokens.next() {
+                    Some("list") => {
+                        let count_ty = parse_type(&ctx, tokens.next())?;
+                        let item_ty = parse_type(&ctx, tokens.next())?;
+                        let name = tokens
+                            .next()
+                            .ok_or_else(|| ctx.error("missing property name"))?;
+                        Property::List {
+                            name: name.to_string(),
+                            count_ty,
+                            item_ty,
+                        }
+                    }
+                    ty => {
+                        let ty = parse_type(&ctx, ty)?;
+                        let name = tokens
+                            .next()
+                            .ok_or_else(|| ctx.error("missing property name"))?;
+                        Property::Scalar {
+                            name: name.to_string(),
+                            ty,
+                        }
+                    }
+                };
+                element.properties.push(property);
+            }
+            "end_header" => break,
+            "comment" | "obj_info" | "" => {}
+            other => return Err(ctx.error(format!("unknown header keyword '{}'", other))),
+        }
+    }
+
+    let format = format.ok_or_else(|| LoadError::Parse {
+        path: path.to_path_buf(),
+        line: line_number,
+        message: "missing format".to_string(),
+    })?;
+    Ok(Header {
+        format,
+        elements,
+        lines: line_number,
+    })
+}
+
+fn parse_type(ctx: &LineContext, token: Option<&str>) -> Result<ScalarType, LoadError> {
+    let token = token.ok_or_else(|| ctx.error("missing property type"))?;
+    ScalarType::from_name(token).ok_or_else(|| ctx.error(format!("unknown type '{}'", token)))
+}
+
+fn read_vertices(
+    body: &mut Body<impl BufRead>,
+    path: &Path,
+    element: &Element,
+    data: &mut MeshData,
+) -> Result<(), LoadError> {
+    let slot = |names: &[&str]| {
+        element.properties.iter().position(|p| match p {
+            Property::Scalar { name, .. } => names.contains(&name.as_str()),
+            Property::List { .. } => false,
+        })
+    };
+    let color_scale = |index: usize| match &element.properties[index] {
+        Property::Scalar { name, ty } => ty.color_scale().ok_or_else(|| LoadError::Format {
+            path: path.to_path_buf(),
+            message: format!("vertex color '{}' is a signed integer", name),
+        }),
+        Property::List { .. } => Ok(1.0),
+    };
+
+    let position = [slot(&["x"]), slot(&["y"]), slot(&["z"])];
+    let normal = [slot(&["nx"]), slot(&["ny"]), slot(&["nz"])];
+    let uv = [
+        slot(&["u", "s", "texture_u", "texture_s"]),
+        slot(&["v", "t", "texture_v", "texture_t"]),
+    ];
+    let color = [
+        slot(&["red", "r"]),
+        slot(&["green", "g"]),
+        slot(&["blue", "b"]),
+    ];
+
+    let [Some(x), Some(y), Some(z)] = position else {
+        return Err(LoadError::Format {
+            path: path.to_path_buf(),
+            message: "vertex element without x, y and z".to_string(),
+        });
+    };
+    let normal = match normal {
+        [Some(nx), Some(ny), Some(nz)] => Some([nx, ny, nz]),
+        _ => None,
+    };
+    let uv = match uv {
+        [Some(u), Some(v)] => Some([u, v]),
+        _ => None,
+    };
+    let color = match color {
+        [Some(r), Some(g), Some(b)] => Some([
+            (r, color_scale(r)?),
+            (g, color_scale(g)?),
+            (b, color_scale(b)?),
+        ]),
+        _ => None,
+    };
+
+    let mut values = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, property) in values.iter_mut().zip(&element.properties) {
+            match property {
+                Property::Scalar { ty, .. } => *value = body.read(path, *ty)?,
+                Property::List { .. } => body.skip(path, property)?,
+            }
+        }
+
+        data.positions
+            .push(Point3::new(values[x], values[y], values[z]));
+        if let Some([nx, ny, nz]) = normal {
+            data.normals
+                .push(Vec3::new(values[nx], values[ny], values[nz]));
+        }
+        if let Some([u, v]) = uv {
+            data.uvs.push((values[u], values[v]));
+        }
+        if let Some([(r, sr), (g, sg), (b, sb)]) = color {
+            data.colors
+                .push(Color::new(values[r] * sr, values[g] * sg, values[b] * sb));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(
+    body: &mut Body<impl BufRead>,
+    path: &Path,
+    element: &Element,
+    data: &mut MeshData,
+) -> Result<(), LoadError> {
+    let mut polygon: Vec<usize> = Vec::new();
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property {
+                Property::List {
+                    name,
+                    count_ty,
+                    item_ty,
+                } if name == "vertex_indices" || name == "vertex_index" => {
+                    let count = body.read(path, *count_ty)? as usize;
+                    polygon.clear();
+                    for _ in 0..count {
+                        let index = body.read(path, *item_ty)?;
+                        if index < 0.0 {
+                            return Err(
+                                body.error(path, format!("negative vertex index {}", index))
+                            );
+                        }
+                        polygon.push(index as usize);
+                    }
+                    // Fan triangulation; points and lines are dropped.
+                    for i in 1..polygon.len().saturating_sub(1) {
+                        data.indices.push([polygon[0], polygon[i], polygon[i + 1]]);
+                    }
+                }
+                _ => body.skip(path, property)?,
+            }
+        }
+    }
+
+    if element
+        .properties
+        .iter()
+        .all(|p| p.name() != "vertex_indices" && p.name() != "vertex_index")
+    {
+        return Err(LoadError::Format {
+            path: path.to_path_buf(),
+            message: "face element without vertex_indices".to_string(),
+        });
+    }
+    Ok(())
+}
+
+enum Body<R: BufRead> {
+    Ascii {
+        reader: R,
+        /// Remaining tokens of the current line, in reverse order.
+        tokens: Vec<String>,
+        line: usize,
+    },
+    Binary {
+        reader: R,
+        big_endian: bool,
+    },
+}
+
+impl<R: BufRead> Body<R> {
+    fn error(&self, path: &Path, message: String) -> LoadError {
+        match self {
+            Body::Ascii { line, .. } => LoadError::Parse {
+                path: path.to_path_buf(),
+                line: *line,
+                message,
+            },
+            Body::Binary { .. } => LoadError::Format {
+                path: path.to_path_buf(),
+                message,
+            },
+        }
+    }
+
+    fn read(&mut self, path: &Path, ty: ScalarType) -> Result<f64, LoadError> {
+        match self {
+            Body::Ascii {
+                reader,
+                tokens,
+                line,
+            } => {
+                while tokens.is_empty() {
+                    let mut text = String::new();
+                    let read = reader.read_line(&mut text).map_err(|e| io_error(path, e))?;
+                    *line += 1;
+                    if read == 0 {
+                        return Err(self.error(path, "unexpected end of file".to_string()));
+                    }
+                    tokens.extend(text.split_whitespace().rev().map(str::to_string));
+                }
+                let token = tokens.pop().unwrap_or_default();
+                let ctx = LineContext { path, line: *line };
+                ctx.parse(Some(&token), "number")
+            }
+            Body::Binary { reader, big_endian } => {
+                let big_endian = *big_endian;
+                let mut bytes = [0u8; 8];
+                let size = match ty {
+                    ScalarType::I8 | ScalarType::U8 => 1,
+                    ScalarType::I16 | ScalarType::U16 => 2,
+                    ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+                    ScalarType::F64 => 8,
+                };
+                let bytes = &mut bytes[..size];
+                if let Err(e) = reader.read_exact(bytes) {
+                    return Err(if e.kind() == ErrorKind::UnexpectedEof {
+                        self.error(path, "unexpected end of file".to_string())
+                    } else {
+                        io_error(path, e)
+                    });
+                }
+                if big_endian != cfg!(target_endian = "big") {
+                    bytes.reverse();
+                }
+                let value = match ty {
+                    ScalarType::I8 => i8::from_ne_bytes([bytes[0]]) as f64,
+                    ScalarType::U8 => bytes[0] as f64,
+                    ScalarType::I16 => i16::from_ne_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::U16 => u16::from_ne_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::I32 => {
+                        i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::U32 => {
+                        u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::F32 => {
+                        f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::F64 => f64::from_ne_bytes([
+                        bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6],
+                        bytes[7],
+                    ]),
+                };
+                Ok(value)
+            }
+        }
+    }
+
+    fn skip(&mut self, path: &Path, property: &Property) -> Result<(), LoadError> {
+        match property {
+            Property::Scalar { ty, .. } => {
+                self.read(path, *ty)?;
+            }
+            Property::List {
+                count_ty, item_ty, ..
+            } => {
+                let count = self.read(path, *count_ty)? as usize;
+                for _ in 0..count {
+                    self.read(path, *item_ty)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "3 0 1 2\n";
+
+    fn header(format: &str, color_type: &str) -> String {
+        format!(
+            "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nproperty {} red\nproperty {} green\nproperty {} blue\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format, color_type, color_type, color_type
+        )
+    }
+
+    #[test]
+    fn reads_ascii_with_colors() {
+        let text = format!(
+            "{}0 0 0 255 0 0\n1 0 0 0 255 0\n0 1 0 0 0 51\n{}",
+            header("ascii", "uchar"),
+            TRIANGLE
+        );
+        let data = parse(text.as_bytes(), Path::new("test.ply")).unwrap();
+        assert_eq!(data.positions.len(), 3);
+        assert_eq!(data.indices, vec![[0, 1, 2]]);
+        assert_eq!(data.colors[0], Color::new(1.0, 0.0, 0.0));
+        assert_eq!(data.colors[2], Color::new(0.0, 0.0, 0.2));
+    }
+
+    #[test]
+    fn reads_binary_little_endian() {
+        let mut bytes = header("binary_little_endian", "float").into_bytes();
+        for vertex in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for value in vertex.iter().chain(&[0.5, 0.5, 0.5]) {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.push(3);
+        for index in [0i32, 1, 2] {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        let data = parse(bytes.as_slice(), Path::new("test.ply")).unwrap();
+        assert_eq!(data.positions[1], Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(data.colors[0], Color::new(0.5, 0.5, 0.5));
+        assert_eq!(data.indices, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn reads_binary_big_endian() {
+        let mut bytes = header("binary_big_endian", "ushort").into_bytes();
+        for (vertex, color) in [
+            ([0.0f32, 0.0, 0.0], [65535u16, 0, 0]),
+            ([1.0, 0.0, 0.0], [0, 65535, 0]),
+            ([0.0, 1.0, 0.0], [0, 0, 65535]),
+        ] {
+            for value in vertex {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+            for value in color {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        bytes.push(3);
+        for index in [0i32, 1, 2] {
+            bytes.extend_from_slice(&index.to_be_bytes());
+        }
+        let data = parse(bytes.as_slice(), Path::new("test.ply")).unwrap();
+        assert_eq!(data.positions[1], Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(data.positions[2], Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(data.colors[0], Color::new(1.0, 0.0, 0.0));
+        assert_eq!(data.colors[2], Color::new(0.0, 0.0, 1.0));
+        assert_eq!(data.indices, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn rejects_signed_colors() {
+        let text = format!(
+            "{}0 0 0 -1 0 0\n1 0 0 0 1 0\n0 1 0 0 0 1\n{}",
+            header("ascii", "char"),
+            TRIANGLE
+        );
+        match parse(text.as_bytes(), Path::new("test.ply")) {
+            Err(LoadError::Format { message, .. }) => assert!(message.contains("red")),
+            _ => panic!("signed colors were accepted"),
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_faces() {
+        let text = format!(
+            "{}0 0 0 1 1 1\n1 0 0 1 1 1\n0 1 0 1 1 1\n3 0 1 3\n",
+            header("ascii", "uchar")
+        );
+        assert!(matches!(
+            parse(text.as_bytes(), Path::new("test.ply")),
+            Err(LoadError::Format { .. })
+        ));
+    }
+}
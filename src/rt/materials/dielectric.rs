@@ -1,5 +1,11 @@
 use super::{scatter_record::ScatterRecord, Material};
-use crate::rt::{color, random_f64, ray::Ray, shapes::hit_record::HitRecord, vec3::Vec3};
+use crate::rt::{
+    color::{self, Color},
+    random_f64,
+    ray::Ray,
+    shapes::hit_record::HitRecord,
+    vec3::Vec3,
+};
 
 pub struct Dielectric {
     ior: f64,
@@ -36,6 +42,10 @@ impl Material for Dielectric {
             ray: Ray::new(rec.p, direction, r_in.time),
         })
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        color::WHITE
+    }
 }
 
 fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -12,6 +12,7 @@ use super::{scatter_record::ScatterRecord, Material};
 
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    light_group: usize,
 }
 
 impl DiffuseLight {
@@ -20,7 +21,17 @@ impl DiffuseLight {
     }
 
     pub fn from_texture(emit: Arc<dyn Texture>) -> DiffuseLight {
-        DiffuseLight { emit }
+        DiffuseLight {
+            emit,
+            light_group: 0,
+        }
+    }
+
+    pub fn with_light_group(self, light_group: usize) -> DiffuseLight {
+        DiffuseLight {
+            light_group,
+            ..self
+        }
     }
 }
 
@@ -36,4 +47,12 @@ impl Material for DiffuseLight {
             color::BLACK
         }
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn light_group(&self) -> usize {
+        self.light_group
+    }
 }
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use crate::rt::{
     color::Color,
+    shapes::hit_record::HitRecord,
     textures::{solid_color::SolidColor, Texture},
 };
 
@@ -22,6 +23,10 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value_at(rec)
+    }
+
     // fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray, f64)> {
     //     Some((
     //         self.albedo.value(rec.u, rec.v, rec.p),
@@ -28,11 +28,15 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         Some(ScatterRecord::Diffuse {
-            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            attenuation: self.albedo.value_at(rec),
             pdf: Box::new(CosinePdf::new(rec.normal)),
         })
     }
 
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value_at(rec)
+    }
+
     fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
         let cosine = Vec3::dot(rec.normal, Vec3::unit_vector(scattered.direction));
         if cosine < 0.0 {
@@ -27,4 +27,8 @@ impl Material for Metal {
             ),
         })
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
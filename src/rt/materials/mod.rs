@@ -26,4 +26,20 @@ pub trait Material: Sync + Send {
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: Point3) -> Color {
         color::BLACK
     }
+
+    /// Whether `emitted` can return anything but black, which makes surfaces using the
+    /// material worth sampling directly.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// The surface color at the hit, for the albedo output.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        color::BLACK
+    }
+
+    /// Index of the light group `emitted` is accounted to, see `Scene::light_groups`.
+    fn light_group(&self) -> usize {
+        0
+    }
 }
@@ -1,22 +1,35 @@
 use self::{
-    camera::Camera,
+    aov::AovPixel,
     color::Color,
-    materials::scatter_record::ScatterRecord,
-    pdfs::{hittable_pdf::HittablePdf, mixture_pdf::MixturePdf, Pdf},
+    framebuffer::Accumulator,
+    integrators::path_tracer::PathTracer,
     ray::Ray,
-    shapes::Hittable,
+    scene::Scene,
+    shapes::{hittable_list::HittableList, Hittable},
     vec3::Vec3,
 };
 
+pub mod aov;
 pub mod camera;
+pub mod checkpoint;
 pub mod color;
+pub mod denoise;
+pub mod framebuffer;
+pub mod integrators;
+pub mod loaders;
+pub mod mat4;
 pub mod materials;
 pub mod noise;
 mod onb;
 mod pdfs;
+pub mod quaternion;
 mod ray;
+pub mod renderer;
+pub mod scene;
 pub mod shapes;
+pub mod splat;
 pub mod textures;
+pub mod tonemap;
 pub mod vec3;
 
 pub type Point3 = Vec3;
@@ -25,60 +38,49 @@ pub fn ray_color(
     r: &Ray,
     background: Color,
     world: &dyn Hittable,
-    lights: &dyn Hittable,
+    lights: &HittableList,
     depth: usize,
 ) -> Color {
-    // If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth <= 0 {
-        return color::BLACK;
-    }
+    PathTracer::new(background, world, lights, depth).trace(r, &mut [])
+}
 
-    match world.hit(r, 0.001, f64::INFINITY) {
-        None => background, // If the ray hits nothing, return the background color.
-        Some(rec) => {
-            let emitted = rec.material.emitted(r, &rec, rec.u, rec.v, rec.p);
-            match rec.material.scatter(r, &rec) {
-                None => emitted,
-                Some(srec) => match srec {
-                    ScatterRecord::Specular { attenuation, ray } => {
-                        attenuation * ray_color(&ray, background, world, lights, depth - 1)
-                    }
-                    ScatterRecord::Diffuse { attenuation, pdf } => {
-                        let lights_pdf = HittablePdf::new(lights, rec.p);
-                        let p = MixturePdf::new(&lights_pdf, pdf.as_ref());
-                        let scattered = Ray::new(rec.p, p.generate(), r.time);
-                        let pdf_value = p.value(scattered.direction);
-                        emitted
-                            + attenuation
-                                * rec.material.scattering_pdf(r, &rec, &scattered)
-                                * ray_color(&scattered, background, world, lights, depth - 1)
-                                / pdf_value
-                    }
-                },
-            }
-        }
-    }
+/// Where `render_pixel` puts the AOVs of a pixel.
+pub struct PixelAovs<'a> {
+    pub pixel: &'a mut AovPixel,
+    /// One sum per light group.
+    pub light_groups: &'a mut [Color],
 }
 
+/// Adds `samples` samples of the pixel, made by the scene's integrator, to `pixel`.
 pub fn render_pixel(
     x: u32,
     y: u32,
-    camera: &Camera,
-    background: Color,
-    world: &dyn Hittable,
-    lights: &dyn Hittable,
-) -> Color {
-    let mut color = color::BLACK;
-    for _ in 0..camera.samples_per_pixel {
+    scene: &Scene,
+    samples: usize,
+    pixel: &mut Accumulator,
+    mut aovs: Option<PixelAovs>,
+) {
+    let camera = &scene.camera;
+    for _ in 0..samples {
         let u = (x as f64 + random_f64()) / (camera.width - 1) as f64;
         let v = (y as f64 + random_f64()) / (camera.height - 1) as f64;
         let r = camera.get_ray(u, v);
-        color = color + ray_color(&r, background, world, lights, camera.max_depth);
+        let hit = scene.world.closest_hit(&r, 0.001, f64::INFINITY);
+        let is_hit = hit.is_some();
+        let light_groups = match &mut aovs {
+            Some(aovs) => {
+                aovs.pixel.add(&r, hit.as_ref().map(|(i, rec)| (*i, rec)));
+                &mut *aovs.light_groups
+            }
+            None => &mut [],
+        };
+        let hit = hit.map(|(_, rec)| rec);
+        let color = scene.integrator.radiance(scene, &r, hit, light_groups);
+        pixel.add_sample(color, is_hit);
     }
-    color
 }
 
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
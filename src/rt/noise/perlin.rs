@@ -13,20 +13,23 @@ pub struct Perlin {
 
 const POINT_COUNT: i32 = 256;
 
+impl Default for Perlin {
+    fn default() -> Self {
+        Perlin::new()
+    }
+}
+
 impl Perlin {
     fn perlin_generate_perm() -> Vec<i32> {
-        let mut p = (0..POINT_COUNT).collect();
+        let mut p: Vec<i32> = (0..POINT_COUNT).collect();
         Perlin::permute(&mut p, POINT_COUNT);
         p
     }
 
-    fn permute(p: &mut Vec<i32>, n: i32) {
+    fn permute(p: &mut [i32], n: i32) {
         for i in (0..n).rev() {
-            let iu = i as usize;
             let target = random_i32_between(0, i) as usize;
-            let tmp = p[iu];
-            p[iu] = p[target];
-            p[target] = tmp;
+            p.swap(i as usize, target);
         }
     }
 
@@ -35,10 +38,9 @@ impl Perlin {
         let vv = v * v * (3.0 - 2.0 * v);
         let ww = w * w * (3.0 - 2.0 * w);
         let mut accum = 0.0;
-        for i in 0..c.len() {
-            for j in 0..c[i].len() {
-                for k in 0..c[i][j].len() {
-                    let cijk = c[i][j][k];
+        for (i, ci) in c.iter().enumerate() {
+            for (j, cij) in ci.iter().enumerate() {
+                for (k, &cijk) in cij.iter().enumerate() {
                     let i = i as f64;
                     let j = j as f64;
                     let k = k as f64;
@@ -63,14 +65,14 @@ impl Perlin {
         let k = p.z.floor() as i32;
 
         let mut c = [[[vec3::ZERO; 2]; 2]; 2];
-        for di in 0..c.len() {
-            for dj in 0..c[di].len() {
-                for dk in 0..c[di][dj].len() {
+        for (di, ci) in c.iter_mut().enumerate() {
+            for (dj, cij) in ci.iter_mut().enumerate() {
+                for (dk, cijk) in cij.iter_mut().enumerate() {
                     let i = ((i + di as i32) & 255) as usize;
                     let j = ((j + dj as i32) & 255) as usize;
                     let k = ((k + dk as i32) & 255) as usize;
                     let index = (self.perm_x[i] ^ self.perm_y[j] ^ self.perm_z[k]) as usize;
-                    c[di as usize][dj as usize][dk as usize] = self.ranvec[index];
+                    *cijk = self.ranvec[index];
                 }
             }
         }
@@ -5,20 +5,21 @@ use super::Pdf;
 pub struct HittablePdf<'a> {
     hittable: &'a dyn Hittable,
     o: Point3,
+    time: f64,
 }
 
 impl<'a> HittablePdf<'a> {
-    pub fn new(hittable: &'a dyn Hittable, o: Point3) -> HittablePdf<'a> {
-        HittablePdf { hittable, o }
+    pub fn new(hittable: &'a dyn Hittable, o: Point3, time: f64) -> HittablePdf<'a> {
+        HittablePdf { hittable, o, time }
     }
 }
 
 impl<'a> Pdf for HittablePdf<'a> {
     fn value(&self, direction: Vec3) -> f64 {
-        self.hittable.pdf_value(self.o, direction)
+        self.hittable.pdf_value(self.o, direction, self.time)
     }
 
     fn generate(&self) -> Vec3 {
-        self.hittable.random(self.o)
+        self.hittable.random(self.o, self.time)
     }
 }
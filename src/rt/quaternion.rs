@@ -0,0 +1,115 @@
+use super::{degrees_to_radians, mat4::Mat4, vec3::Vec3};
+
+/// Unit quaternion describing a rotation, `w + xi + yj + zk`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+pub const IDENTITY: Quaternion = Quaternion {
+    w: 1.0,
+    x: 0.0,
+    y: 0.0,
+    z: 0.0,
+};
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        IDENTITY
+    }
+}
+
+impl Quaternion {
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quaternion {
+        Quaternion { w, x, y, z }
+    }
+
+    /// Counter-clockwise rotation around `axis`, matching `Mat4::rotation`.
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Quaternion {
+        let a = Vec3::unit_vector(axis);
+        let (sin, cos) = (degrees_to_radians(degrees) / 2.0).sin_cos();
+        Quaternion::new(cos, a.x * sin, a.y * sin, a.z * sin)
+    }
+
+    pub fn dot(a: Quaternion, b: Quaternion) -> f64 {
+        a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let length = Quaternion::dot(*self, *self).sqrt();
+        Quaternion::new(
+            self.w / length,
+            self.x / length,
+            self.y / length,
+            self.z / length,
+        )
+    }
+
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Rotation angle, in radians, needed to go from `a` to `b`.
+    pub fn angle_between(a: Quaternion, b: Quaternion) -> f64 {
+        2.0 * Quaternion::dot(a, b).abs().min(1.0).acos()
+    }
+
+    /// Spherical linear interpolation along the shortest arc.
+    pub fn slerp(a: Quaternion, b: Quaternion, t: f64) -> Quaternion {
+        let mut cos_theta = Quaternion::dot(a, b);
+        let b = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Quaternion::new(-b.w, -b.x, -b.y, -b.z)
+        } else {
+            b
+        };
+
+        let (wa, wb) = if cos_theta > 0.9995 {
+            // Nearly parallel, plain linear interpolation is accurate and stable.
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+
+        Quaternion::new(
+            wa * a.w + wb * b.w,
+            wa * a.x + wb * b.x,
+            wa * a.y + wb * b.y,
+            wa * a.z + wb * b.z,
+        )
+        .normalize()
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let Quaternion { w, x, y, z } = *self;
+        Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
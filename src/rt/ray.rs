@@ -1,5 +1,6 @@
 use super::{vec3::Vec3, Point3};
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
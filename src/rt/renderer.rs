@@ -0,0 +1,610 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use super::{
+    camera::Camera,
+    color,
+    framebuffer::{Accumulator, Framebuffer, Row},
+    render_pixel,
+    scene::Scene,
+    PixelAovs,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderSettings {
+    /// Worker threads, rayon's global pool when `None`.
+    pub threads: Option<usize>,
+    /// Seeds every pixel from this, its position and the samples it already has, so a
+    /// render is reproducible no matter which thread gets which pixel, and later passes
+    /// don't repeat earlier ones. Renders meant to be merged need different seeds.
+    /// Splats from integrators like BDPT are summed in whatever order the threads find
+    /// them, which can change the last bits.
+    pub seed: Option<u64>,
+    /// Collect AOVs into the framebuffers made by `framebuffer`.
+    pub aovs: bool,
+    /// Stop sampling pixels once they are clean enough and spend what they leave of the
+    /// samples per pixel on the noisy ones.
+    pub adaptive: Option<AdaptiveSampling>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+    /// The standard error a pixel may have, relative to its luminance.
+    pub threshold: f64,
+    /// Samples every pixel gets before its error is trusted, at least 2.
+    /// Pixels that already have them from earlier passes go straight to the error check.
+    pub min_samples: usize,
+    /// The most samples a pixel may have, counting earlier passes. `None` for
+    /// `MAX_SAMPLES_FACTOR` times `camera.samples_per_pixel`.
+    pub max_samples: Option<usize>,
+}
+
+impl AdaptiveSampling {
+    /// Samples taken between two looks at the error.
+    const BATCH: usize = 8;
+
+    /// Luminance below this counts as this, so black pixels don't need endless samples.
+    const MIN_LUMINANCE: f64 = 0.01;
+
+    /// How many times the samples per pixel a noisy pixel may get by default.
+    pub const MAX_SAMPLES_FACTOR: usize = 4;
+
+    pub fn converged(&self, pixel: &Accumulator) -> bool {
+        self.error(pixel) <= 1.0
+    }
+
+    /// The standard error of the pixel relative to the one it may have, infinite before
+    /// its second sample.
+    pub fn error(&self, pixel: &Accumulator) -> f64 {
+        let luminance = color::luminance(pixel.color()).max(Self::MIN_LUMINANCE);
+        pixel.variance().sqrt() / (self.threshold * luminance)
+    }
+
+    fn max_samples(&self, camera: &Camera) -> usize {
+        self.max_samples
+            .unwrap_or(Self::MAX_SAMPLES_FACTOR * camera.samples_per_pixel)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    pub completed_pixels: usize,
+    pub total_pixels: usize,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_pixels == 0 {
+            1.0
+        } else {
+            self.completed_pixels as f64 / self.total_pixels as f64
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RenderStats {
+    pub elapsed: Duration,
+    pub pixels: usize,
+    /// Camera rays traced, each followed through all its bounces.
+    /// `samples / pixels` is the average number of samples per pixel.
+    pub samples: usize,
+    /// Set when the render was stopped before every pixel was done.
+    pub cancelled: bool,
+}
+
+impl RenderStats {
+    pub fn samples_per_second(&self) -> f64 {
+        self.samples as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Renders a scene with the camera's resolution, sample count and bounce limit.
+pub struct Renderer<'a> {
+    scene: &'a Scene,
+    settings: RenderSettings,
+    cancelled: Arc<AtomicBool>,
+    /// The pool for `settings.threads`, kept across passes.
+    pool: Option<ThreadPool>,
+}
+
+impl<'a> Renderer<'a> {
+    /// Fails when the worker threads asked for by `settings.threads` can't be started.
+    pub fn new(
+        scene: &'a Scene,
+        settings: RenderSettings,
+    ) -> Result<Renderer<'a>, ThreadPoolBuildError> {
+        let pool = match settings.threads {
+            Some(threads) => Some(ThreadPoolBuilder::new().num_threads(threads).build()?),
+            None => None,
+        };
+        Ok(Renderer {
+            scene,
+            settings,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            pool,
+        })
+    }
+
+    /// Setting the flag, from any thread, stops the render after the rows in flight. It
+    /// stays set, so later renders return at once too, until `reset_cancel` clears it.
+    /// A cancellation that comes just before a render starts isn't lost that way.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Lets renders run again after a cancellation.
+    pub fn reset_cancel(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    /// A black framebuffer the size of the image, with AOVs if the settings ask for them.
+    pub fn framebuffer(&self) -> Framebuffer {
+        let camera = &self.scene.camera;
+        if self.settings.aovs {
+            let light_groups = self.scene.light_groups.clone();
+            Framebuffer::with_aovs(camera.width, camera.height, light_groups)
+        } else {
+            Framebuffer::new(camera.width, camera.height)
+        }
+    }
+
+    pub fn render(&self, progress: impl Fn(Progress) + Sync) -> (Framebuffer, RenderStats) {
+        let mut framebuffer = self.framebuffer();
+        let stats = self.render_into(&mut framebuffer, progress);
+        (framebuffer, stats)
+    }
+
+    /// Renders `camera.samples_per_pixel` samples per pixel into `framebuffer`, see
+    /// `render_pass`.
+    pub fn render_into(
+        &self,
+        framebuffer: &mut Framebuffer,
+        progress: impl Fn(Progress) + Sync,
+    ) -> RenderStats {
+        let samples_per_pixel = self.scene.camera.samples_per_pixel;
+        self.render_pass(framebuffer, samples_per_pixel, progress)
+    }
+
+    /// Renders row by row, adding `samples_per_pixel` samples to those already in
+    /// `framebuffer`, which must have the size of the image. With adaptive sampling that's
+    /// a budget for the whole image instead: pixels that converge stop early, then what
+    /// they left is spent on the rest, noisiest first, see `spend_leftover`. `progress` is
+    /// called from the worker threads as rows complete, counting the pixels of every
+    /// batch. Rows skipped after a cancellation keep their previous content.
+    /// The pass is split into the integrator's batches, see `Integrator::batch_size`. Each
+    /// gets `begin_pass` first, numbered by the samples the framebuffer already has, and
+    /// `end_pass` last, whose splats are added to the pixels that took samples.
+    pub fn render_pass(
+        &self,
+        framebuffer: &mut Framebuffer,
+        samples_per_pixel: usize,
+        progress: impl Fn(Progress) + Sync,
+    ) -> RenderStats {
+        let camera = &self.scene.camera;
+        assert_eq!(
+            (framebuffer.width(), framebuffer.height()),
+            (camera.width, camera.height),
+            "the framebuffer must match the camera resolution"
+        );
+
+        let start = Instant::now();
+        let total_pixels = camera.width * camera.height;
+        let batches = self.batches(framebuffer, samples_per_pixel);
+        let batch_size = self.scene.integrator.batch_size();
+        let completed = AtomicUsize::new(0);
+        let row_done = |width: usize| {
+            progress(Progress {
+                completed_pixels: completed.fetch_add(width, Ordering::Relaxed) + width,
+                total_pixels: total_pixels * batches.len(),
+            });
+        };
+        let mut render_batches = || {
+            let mut samples = 0;
+            for &batch in &batches {
+                if self.cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+                let pass = framebuffer.mean_samples() / batch_size.unwrap_or(batch).max(1);
+                samples += self.render_batch(framebuffer, batch, pass, &row_done);
+            }
+            samples
+        };
+        let samples = match &self.pool {
+            Some(pool) => pool.install(render_batches),
+            None => render_batches(),
+        };
+
+        let completed = completed.into_inner();
+        RenderStats {
+            elapsed: start.elapsed(),
+            pixels: completed / batches.len(),
+            samples,
+            cancelled: completed < total_pixels * batches.len(),
+        }
+    }
+
+    /// The samples per pixel of the batches `render_pass` splits `samples_per_pixel` into:
+    /// up to the end of each of the integrator's batches, counting those the framebuffer
+    /// already has, or all of them at once.
+    fn batches(&self, framebuffer: &Framebuffer, samples_per_pixel: usize) -> Vec<usize> {
+        let Some(size) = self.scene.integrator.batch_size() else {
+            return vec![samples_per_pixel];
+        };
+        let size = size.max(1);
+        let mut done = framebuffer.mean_samples();
+        let mut left = samples_per_pixel;
+        let mut batches = Vec::new();
+        loop {
+            let samples = left.min(size - done % size);
+            batches.push(samples);
+            done += samples;
+            left -= samples;
+            if left == 0 {
+                return batches;
+            }
+        }
+    }
+
+    /// Takes `samples_per_pixel` samples of every pixel, or that many on average with
+    /// adaptive sampling, in a pass numbered `pass`. `row_done` gets the width of every
+    /// row as it completes. Returns the number of samples taken.
+    fn render_batch(
+        &self,
+        framebuffer: &mut Framebuffer,
+        samples_per_pixel: usize,
+        pass: usize,
+        row_done: &(dyn Fn(usize) + Sync),
+    ) -> usize {
+        if let Some(seed) = self.settings.seed {
+            fastrand::seed(seed ^ (pass as u64 + 1).wrapping_mul(0x94d0_49bb_1331_11eb));
+        }
+        self.scene.integrator.begin_pass(self.scene, pass);
+        let before: Vec<u32> = framebuffer.pixels().iter().map(|p| p.samples()).collect();
+        let taken = AtomicUsize::new(0);
+        framebuffer
+            .rows_mut()
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(y, row)| {
+                if self.cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let width = row.pixels.len();
+                let samples = self.render_row(y, row, |pixel, sample| {
+                    self.first_samples(pixel, samples_per_pixel, sample)
+                });
+                taken.fetch_add(samples, Ordering::Relaxed);
+                row_done(width);
+            });
+        let mut taken = taken.into_inner();
+        let budget = framebuffer.pixels().len() * samples_per_pixel;
+        taken += self.spend_leftover(framebuffer, budget.saturating_sub(taken));
+        if let Some(splats) = self.scene.integrator.end_pass() {
+            splats.merge_into(framebuffer, &before, taken);
+        }
+        taken
+    }
+
+    /// The samples a pixel gets in the first sweep over the image: all `samples_per_pixel`
+    /// of them, or with adaptive sampling until it converges.
+    fn first_samples(
+        &self,
+        pixel: &mut Accumulator,
+        samples_per_pixel: usize,
+        sample: &mut dyn FnMut(&mut Accumulator, usize),
+    ) {
+        let Some(adaptive) = self.settings.adaptive else {
+            sample(pixel, samples_per_pixel);
+            return;
+        };
+        let before = pixel.samples() as usize;
+        let max_samples = adaptive.max_samples(&self.scene.camera);
+        let budget = samples_per_pixel.min(max_samples.saturating_sub(before));
+        let missing = adaptive.min_samples.max(2).saturating_sub(before);
+        sample(pixel, missing.min(budget));
+        loop {
+            let left = budget - (pixel.samples() as usize - before);
+            if left == 0 || adaptive.converged(pixel) {
+                break;
+            }
+            sample(pixel, AdaptiveSampling::BATCH.min(left));
+        }
+    }
+
+    /// Spends the `left` samples that converged pixels didn't need on those that haven't
+    /// converged, a batch at a time to the noisiest first, until they run out, every pixel
+    /// converges or reaches `max_samples`. Returns the number of samples taken.
+    fn spend_leftover(&self, framebuffer: &mut Framebuffer, mut left: usize) -> usize {
+        let Some(adaptive) = self.settings.adaptive else {
+            return 0;
+        };
+        let max_samples = adaptive.max_samples(&self.scene.camera);
+        let width = framebuffer.width();
+        let mut taken = 0;
+        while left > 0 && !self.cancelled.load(Ordering::Relaxed) {
+            let mut noisy: Vec<(f64, usize)> = framebuffer
+                .pixels()
+                .iter()
+                .enumerate()
+                .filter(|(_, pixel)| (pixel.samples() as usize) < max_samples)
+                .map(|(i, pixel)| (adaptive.error(pixel), i))
+                .filter(|&(error, _)| error > 1.0)
+                .collect();
+            if noisy.is_empty() {
+                break;
+            }
+            noisy.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+            let mut extra = vec![0; framebuffer.pixels().len()];
+            for (_, i) in noisy {
+                let room = max_samples - framebuffer.pixels()[i].samples() as usize;
+                extra[i] = AdaptiveSampling::BATCH.min(room).min(left);
+                left -= extra[i];
+                if left == 0 {
+                    break;
+                }
+            }
+            taken += framebuffer
+                .rows_mut()
+                .into_par_iter()
+                .enumerate()
+                .map(|(y, row)| {
+                    if self.cancelled.load(Ordering::Relaxed) {
+                        return 0;
+                    }
+                    let extra = &extra[y * width..(y + 1) * width];
+                    let mut x = 0;
+                    self.render_row(y, row, |pixel, sample| {
+                        sample(pixel, extra[x]);
+                        x += 1;
+                    })
+                })
+                .sum::<usize>();
+        }
+        taken
+    }
+
+    /// Samples the pixels of a row left to right, `samples` is given each of them and a
+    /// function that adds samples to it. Returns the number of samples taken.
+    fn render_row(
+        &self,
+        row: usize,
+        Row { pixels, mut aovs }: Row,
+        mut samples: impl FnMut(&mut Accumulator, &mut dyn FnMut(&mut Accumulator, usize)),
+    ) -> usize {
+        let camera = &self.scene.camera;
+        // flip y to match results in the book
+        let y = camera.height - 1 - row;
+        let mut taken = 0;
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            if let Some(seed) = self.settings.seed {
+                let index = (y * camera.width + x) as u64;
+                let previous = pixel.samples() as u64;
+                fastrand::seed(
+                    seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                        ^ previous.wrapping_mul(0xc2b2_ae3d_27d4_eb4f),
+                );
+            }
+            let mut sample = |pixel: &mut Accumulator, samples: usize| {
+                if samples == 0 {
+                    return;
+                }
+                let pixel_aovs = aovs.as_mut().map(|(aov_pixels, light_groups)| {
+                    let groups = light_groups.len() / aov_pixels.len();
+                    PixelAovs {
+                        pixel: &mut aov_pixels[x],
+                        light_groups: &mut light_groups[x * groups..(x + 1) * groups],
+                    }
+                });
+                render_pixel(x as u32, y as u32, self.scene, samples, pixel, pixel_aovs);
+            };
+
+            let before = pixel.samples() as usize;
+            samples(pixel, &mut sample);
+            taken += pixel.samples() as usize - before;
+        }
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        camera::Camera,
+        color::Color,
+        integrators::{path_tracer::PathTracing, PathSettings},
+        materials::diffuse_light::DiffuseLight,
+        shapes::{hittable_list::HittableList, sphere::Sphere},
+        vec3::Vec3,
+        Point3,
+    };
+
+    use super::*;
+
+    fn scene(width: usize) -> Scene {
+        let light = Arc::new(DiffuseLight::from_color(Color::new(2.0, 2.0, 2.0)));
+        let world =
+            HittableList::new_from_object(Arc::new(Sphere::new(Point3::default(), 1.0, light)));
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, -5.0),
+            Point3::default(),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            10.0,
+            0.0,
+            1.0,
+            1.0,
+            width,
+            4,
+            8,
+        );
+        Scene {
+            lights: world.lights(),
+            world,
+            camera,
+            background: color::BLACK,
+            light_groups: vec!["default".to_string()],
+            integrator: Box::new(PathTracing),
+            path_settings: PathSettings::default(),
+        }
+    }
+
+    #[test]
+    fn renders_every_pixel() {
+        let scene = scene(16);
+        let renderer = Renderer::new(
+            &scene,
+            RenderSettings {
+                threads: Some(2),
+                seed: Some(1),
+                aovs: true,
+                adaptive: None,
+            },
+        )
+        .unwrap();
+        let reported = AtomicUsize::new(0);
+        let (framebuffer, stats) = renderer.render(|progress| {
+            reported.fetch_max(progress.completed_pixels, Ordering::Relaxed);
+        });
+
+        assert_eq!(reported.into_inner(), 16 * 16);
+        assert_eq!(stats.pixels, 16 * 16);
+        assert_eq!(stats.samples, 16 * 16 * 4);
+        assert!(!stats.cancelled);
+        // The light fills the middle of the image, the background the corners.
+        assert_eq!(framebuffer.pixel(8, 8), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(framebuffer.pixel(0, 0), color::BLACK);
+        assert_eq!(framebuffer.pixels()[8 * 16 + 8].rgba()[3], 1.0);
+        assert_eq!(framebuffer.pixels()[0].rgba()[3], 0.0);
+        let images = framebuffer.aovs().unwrap().images();
+        let (_, light) = images
+            .iter()
+            .find(|(name, _)| name == "light_default")
+            .unwrap();
+        assert_eq!(light.get_pixel(8, 8).0, [2.0, 2.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn seeded_renders_repeat() {
+        let scene = scene(8);
+        let settings = RenderSettings {
+            threads: None,
+            seed: Some(7),
+            aovs: false,
+            adaptive: None,
+        };
+        let (a, _) = Renderer::new(&scene, settings.clone())
+            .unwrap()
+            .render(|_| {});
+        let (b, _) = Renderer::new(&scene, settings).unwrap().render(|_| {});
+        assert_eq!(a.pixels(), b.pixels());
+    }
+
+    #[test]
+    fn adaptive_sampling_spends_samples_on_edges() {
+        let mut scene = scene(16);
+        scene.camera.samples_per_pixel = 64;
+        let settings = RenderSettings {
+            seed: Some(3),
+            adaptive: Some(AdaptiveSampling {
+                threshold: 0.05,
+                min_samples: 4,
+                max_samples: None,
+            }),
+            ..RenderSettings::default()
+        };
+        let (framebuffer, stats) = Renderer::new(&scene, settings).unwrap().render(|_| {});
+
+        let samples = |x: usize, y: usize| framebuffer.pixels()[y * 16 + x].samples();
+        // Flat light and flat background converge right away, the silhouette doesn't and
+        // gets what they left, past the samples per pixel.
+        assert_eq!(samples(0, 0), 4);
+        assert_eq!(samples(8, 8), 4);
+        let most = framebuffer.pixels().iter().map(Accumulator::samples).max();
+        assert_eq!(most, Some(4 * 64));
+        let total: u32 = framebuffer.pixels().iter().map(Accumulator::samples).sum();
+        assert_eq!(stats.samples, total as usize);
+        assert!(stats.samples <= 16 * 16 * 64);
+    }
+
+    #[test]
+    fn adaptive_sampling_keeps_to_the_budget() {
+        let mut scene = scene(16);
+        scene.camera.samples_per_pixel = 8;
+        let settings = RenderSettings {
+            seed: Some(3),
+            adaptive: Some(AdaptiveSampling {
+                threshold: 0.05,
+                min_samples: 4,
+                max_samples: Some(1000),
+            }),
+            ..RenderSettings::default()
+        };
+        let (framebuffer, stats) = Renderer::new(&scene, settings).unwrap().render(|_| {});
+
+        // The silhouette could use more than the rest leaves, so all of it is spent there.
+        assert_eq!(stats.samples, 16 * 16 * 8);
+        assert!(framebuffer.pixels().iter().any(|pixel| pixel.samples() > 8));
+    }
+
+    #[test]
+    fn later_passes_take_new_samples() {
+        let scene = scene(16);
+        let settings = RenderSettings {
+            seed: Some(5),
+            ..RenderSettings::default()
+        };
+        let renderer = Renderer::new(&scene, settings).unwrap();
+        let mut framebuffer = renderer.framebuffer();
+        renderer.render_pass(&mut framebuffer, 2, |_| {});
+        let first: Vec<Color> = framebuffer.pixels().iter().map(|p| p.color()).collect();
+        let stats = renderer.render_pass(&mut framebuffer, 2, |_| {});
+
+        assert_eq!(stats.samples, 16 * 16 * 2);
+        assert!(framebuffer
+            .pixels()
+            .iter()
+            .all(|pixel| pixel.samples() == 4));
+        // Repeating the first pass would leave every mean as it was.
+        assert!(framebuffer
+            .pixels()
+            .iter()
+            .zip(first)
+            .any(|(pixel, first)| pixel.color() != first));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let scene = scene(8);
+        let renderer = Renderer::new(&scene, RenderSettings::default()).unwrap();
+        renderer.cancel_flag().store(true, Ordering::Relaxed);
+        let (_, stats) = renderer.render(|_| panic!("no row should complete"));
+        assert_eq!(stats.pixels, 0);
+        assert!(stats.cancelled);
+    }
+
+    #[test]
+    fn renders_again_after_a_reset() {
+        let scene = scene(8);
+        let renderer = Renderer::new(&scene, RenderSettings::default()).unwrap();
+        let (_, stats) = renderer.render(|_| {});
+        assert!(!stats.cancelled);
+
+        renderer.cancel_flag().store(true, Ordering::Relaxed);
+        let (_, stats) = renderer.render(|_| {});
+        assert!(stats.cancelled);
+
+        renderer.reset_cancel();
+        let (_, stats) = renderer.render(|_| {});
+        assert!(!stats.cancelled);
+        assert_eq!(stats.pixels, 8 * 8);
+    }
+}
@@ -0,0 +1,1044 @@
+//! Scenes described in a TOML file instead of Rust code.
+//!
+//! ```toml
+//! background = [0, 0, 0]
+//!
+//! [camera]
+//! lookfrom = [278, 278, -800]
+//! lookat = [278, 278, 0]
+//! vfov = 40
+//! aspect_ratio = 1
+//! width = 600
+//!
+//! [materials.light]
+//! type = "diffuse_light"
+//! emit = [15, 15, 15]
+//!
+//! [[shapes]]
+//! type = "box"
+//! min = [0, 0, 0]
+//! max = [165, 330, 165]
+//! material = { type = "metal", albedo = [0.8, 0.85, 0.88], fuzz = 0 }
+//! transform = [{ rotate_y = 15 }, { translate = [265, 0, 295] }]
+//!
+//! [[shapes]]
+//! type = "xz_rect"
+//! x0 = 213
+//! x1 = 343
+//! z0 = 227
+//! z1 = 332
+//! k = 554
+//! material = "light"
+//! flip_face = true
+//!
+//! [[shapes]]
+//! type = "sphere"
+//! center = [190, 90, 190]
+//! radius = 90
+//! material = { type = "dielectric", ior = 1.5 }
+//! importance = true
+//! ```
+//!
+//! Textures and materials are declared in `[textures.<name>]` and `[materials.<name>]`
+//! tables and referenced by name, or written inline. A texture parameter also accepts a
+//! plain `[r, g, b]` color. Shapes go into the world in the order of their `[[shapes]]`
+//! tables. Shapes with an emissive material are importance sampled, as are those with
+//! `importance = true`. `[[lights]]` are sampled without being part of the world. A
+//! `diffuse_light` can name a `light_group` for the light group AOVs. An `[integrator]`
+//! table picks how camera rays are shaded, the path tracer by default, and for those that
+//! follow paths the `roulette_depth` at which Russian roulette starts. Relative paths are
+//! resolved against the directory of the scene file.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use self::toml::{Kind, Pos, Table, Value};
+
+use super::{
+    camera::{Camera, CameraError},
+    color::{self, Color},
+    integrators::{
+        self, ambient_occlusion::AmbientOcclusion, debug_view::DebugView, path_tracer::PathTracing,
+        photon_mapping::PhotonMapping, Integrator, PathSettings,
+    },
+    loaders::{obj, ply},
+    mat4::Mat4,
+    materials::{
+        dielectric::Dielectric, diffuse_light::DiffuseLight, isotropic::Isotropic,
+        lambertian::Lambertian, metal::Metal, Material,
+    },
+    shapes::{
+        bbox::Bbox, bvh_build::BvhOptions, constant_volume::ConstantVolume, flip_face::FlipFace,
+        hittable_list::HittableList, importance_target::ImportanceTarget, linear_bvh::LinearBvh,
+        mooving_sphere::MovingSphere, sphere::Sphere, transform::Transform, triangle::Triangle,
+        xy_rect::XyRect, xz_rect::XzRect, yz_rect::YzRect, Hittable,
+    },
+    textures::{
+        checker_texture::CheckerTexture, image_texture::ImageTexture, noise_texture::NoiseTexture,
+        solid_color::SolidColor, Texture,
+    },
+    vec3::Vec3,
+};
+
+mod toml;
+
+pub struct Scene {
+    pub world: HittableList,
+    pub camera: Camera,
+    pub background: Color,
+    pub lights: HittableList,
+    /// Names of the light groups materials refer to by index. The first one, which also
+    /// gets the background, is `DEFAULT_LIGHT_GROUP`.
+    pub light_groups: Vec<String>,
+    pub integrator: Box<dyn Integrator>,
+    pub path_settings: PathSettings,
+}
+
+pub const DEFAULT_LIGHT_GROUP: &str = "default";
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        /// Dotted path of the offending key, e.g. `shapes[2].material.fuzz`.
+        key: Option<String>,
+        message: String,
+    },
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse {
+                path,
+                line,
+                column,
+                key,
+                message,
+            } => {
+                if let Some(path) = path {
+                    write!(f, "{}:", path.display())?;
+                }
+                write!(f, "{}:{}: ", line, column)?;
+                if let Some(key) = key {
+                    write!(f, "{}: ", key)?;
+                }
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { .. } => None,
+        }
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&source, base_dir).map_err(|error| match error {
+        SceneError::Parse {
+            line,
+            column,
+            key,
+            message,
+            ..
+        } => SceneError::Parse {
+            path: Some(path.to_path_buf()),
+            line,
+            column,
+            key,
+            message,
+        },
+        error => error,
+    })
+}
+
+/// Parses a scene from `source`, resolving relative paths against `base_dir`.
+pub fn parse(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let root = toml::parse(source).map_err(|error| SceneError::Parse {
+        path: None,
+        line: error.pos.line,
+        column: error.pos.column,
+        key: error.key,
+        message: error.message,
+    })?;
+    let root = Section {
+        table: &root,
+        pos: Pos { line: 1, column: 1 },
+        key: String::new(),
+    };
+    root.check_keys(&[
+        "background",
+        "camera",
+        "textures",
+        "materials",
+        "shapes",
+        "lights",
+        "integrator",
+    ])?;
+
+    let camera = camera(&root.required("camera")?.section()?)?;
+    let (integrator, path_settings) = match root.field("integrator") {
+        Some(field) => {
+            let section = field.section()?;
+            (integrator(&section)?, path_settings(&section)?)
+        }
+        None => (
+            Box::new(PathTracing) as Box<dyn Integrator>,
+            PathSettings::default(),
+        ),
+    };
+    let background = match root.field("background") {
+        Some(field) => field.vec3()?,
+        None => color::BLACK,
+    };
+
+    let mut builder = Builder {
+        base_dir,
+        time0: camera.time0,
+        time1: camera.time1,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        light_groups: RefCell::new(vec![DEFAULT_LIGHT_GROUP.to_string()]),
+    };
+    if let Some(textures) = root.field("textures") {
+        for (name, field) in textures.section()?.fields() {
+            let texture = builder.texture_definition(&field.section()?)?;
+            builder.textures.insert(name.to_string(), texture);
+        }
+    }
+    if let Some(materials) = root.field("materials") {
+        for (name, field) in materials.section()?.fields() {
+            let material = builder.material_definition(&field.section()?)?;
+            builder.materials.insert(name.to_string(), material);
+        }
+    }
+
+    let mut world = HittableList::default();
+    if let Some(shapes) = root.field("shapes") {
+        for field in shapes.array()? {
+            world.add(builder.shape(&field.section()?)?);
+        }
+    }
+    let mut lights = world.lights();
+    if let Some(shapes) = root.field("lights") {
+        for field in shapes.array()? {
+            lights.add(builder.shape(&field.section()?)?);
+        }
+    }
+
+    Ok(Scene {
+        world,
+        camera,
+        background,
+        lights,
+        light_groups: builder.light_groups.into_inner(),
+        integrator,
+        path_settings,
+    })
+}
+
+fn camera(section: &Section) -> Result<Camera, SceneError> {
+    section.check_keys(&[
+        "lookfrom",
+        "lookat",
+        "vup",
+        "vfov",
+        "aperture",
+        "focus_dist",
+        "time0",
+        "time1",
+        "aspect_ratio",
+        "width",
+        "samples_per_pixel",
+        "max_depth",
+    ])?;
+    let camera = Camera::new(
+        section.required("lookfrom")?.vec3()?,
+        section.required("lookat")?.vec3()?,
+        section.vec3_or("vup", Vec3::new(0.0, 1.0, 0.0))?,
+        section.required("vfov")?.f64()?,
+        section.f64_or("aperture", 0.0)?,
+        section.f64_or("focus_dist", 10.0)?,
+        section.f64_or("time0", 0.0)?,
+        section.f64_or("time1", 1.0)?,
+        section.required("aspect_ratio")?.f64()?,
+        section.required("width")?.usize()?,
+        section.usize_or("samples_per_pixel", 100)?,
+        section.usize_or("max_depth", 50)?,
+    );
+    match camera.validate() {
+        Ok(()) => {}
+        Err(CameraError::Width(_)) => {
+            return Err(section.required("width")?.error("must be at least 2"));
+        }
+        Err(e @ CameraError::Height(_)) => {
+            return Err(section.required("aspect_ratio")?.error(e.to_string()));
+        }
+        Err(CameraError::NoSamples) => {
+            return Err(section
+                .required("samples_per_pixel")?
+                .error("must be at least 1"));
+        }
+    }
+    Ok(camera)
+}
+
+/// The `roulette_depth` of the integrators that follow paths, whose keys are checked by
+/// `integrator`.
+fn path_settings(section: &Section) -> Result<PathSettings, SceneError> {
+    let defaults = PathSettings::default();
+    Ok(PathSettings {
+        roulette_depth: section.usize_or("roulette_depth", defaults.roulette_depth)?,
+    })
+}
+
+fn integrator(section: &Section) -> Result<Box<dyn Integrator>, SceneError> {
+    let kind = section.required("type")?;
+    match kind.str()? {
+        "ambient_occlusion" => {
+            section.check_keys(&["type", "samples", "distance"])?;
+            let defaults = AmbientOcclusion::default();
+            Ok(Box::new(AmbientOcclusion::new(
+                section.usize_or("samples", defaults.samples)?,
+                section.field("distance").map(|f| f.f64()).transpose()?,
+            )))
+        }
+        "photon_map" | "progressive_photon_map" => {
+            let progressive = kind.str()? == "progressive_photon_map";
+            let mut keys = vec!["type", "photons", "radius", "roulette_depth"];
+            if progressive {
+                keys.push("alpha");
+            }
+            section.check_keys(&keys)?;
+            let photons = section.usize_or("photons", PhotonMapping::PHOTONS)?;
+            if photons == 0 {
+                return Err(section.required("photons")?.error("must be at least 1"));
+            }
+            let radius = section.field("radius").map(|f| f.f64()).transpose()?;
+            if radius.is_some_and(|radius| !(radius > 0.0 && radius.is_finite())) {
+                return Err(section.required("radius")?.error("must be greater than 0"));
+            }
+            let photon_mapping = PhotonMapping::new(photons, radius);
+            if !progressive {
+                return Ok(Box::new(photon_mapping));
+            }
+            let alpha = section.f64_or("alpha", PhotonMapping::ALPHA)?;
+            if !(alpha > 0.0 && alpha < 1.0) {
+                return Err(section
+                    .required("alpha")?
+                    .error("must be between 0 and 1, exclusive"));
+            }
+            Ok(Box::new(photon_mapping.progressive(alpha)))
+        }
+        "bvh_cost" => {
+            if !DebugView::BVH_COST_AVAILABLE {
+                return Err(kind.error(DebugView::BVH_COST_UNAVAILABLE));
+            }
+            section.check_keys(&["type", "max"])?;
+            Ok(Box::new(DebugView::BvhCost {
+                max: section.f64_or("max", DebugView::BVH_COST_MAX)?,
+            }))
+        }
+        name => {
+            if matches!(name, "path" | "direct" | "bdpt") {
+                section.check_keys(&["type", "roulette_depth"])?;
+            } else {
+                section.check_keys(&["type"])?;
+            }
+            integrators::from_name(name)
+                .ok_or_else(|| kind.error(format!("unknown integrator '{}'", name)))
+        }
+    }
+}
+
+struct Builder<'a> {
+    base_dir: &'a Path,
+    time0: f64,
+    time1: f64,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    light_groups: RefCell<Vec<String>>,
+}
+
+impl Builder<'_> {
+    /// A texture parameter: a color, the name of a texture or an inline definition.
+    fn texture(&self, field: &Field) -> Result<Arc<dyn Texture>, SceneError> {
+        match &field.value.kind {
+            Kind::Array(_) => Ok(Arc::new(SolidColor::new(field.vec3()?))),
+            Kind::String(name) => self
+                .textures
+                .get(name)
+                .cloned()
+                .ok_or_else(|| field.error(format!("unknown texture '{}'", name))),
+            Kind::Table(_) => self.texture_definition(&field.section()?),
+            other => Err(field.error(format!(
+                "expected a color, texture name or table, found {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn texture_definition(&self, section: &Section) -> Result<Arc<dyn Texture>, SceneError> {
+        let kind = section.required("type")?;
+        match kind.str()? {
+            "solid" => {
+                section.check_keys(&["type", "color"])?;
+                Ok(Arc::new(SolidColor::new(
+                    section.required("color")?.vec3()?,
+                )))
+            }
+            "checker" => {
+                section.check_keys(&["type", "even", "odd"])?;
+                Ok(Arc::new(CheckerTexture::from_textures(
+                    self.texture(&section.required("even")?)?,
+                    self.texture(&section.required("odd")?)?,
+                )))
+            }
+            "noise" => {
+                section.check_keys(&["type", "scale"])?;
+                Ok(Arc::new(NoiseTexture::new(section.f64_or("scale", 1.0)?)))
+            }
+            "image" => {
+                section.check_keys(&["type", "path"])?;
+                let field = section.required("path")?;
+                let path = self.base_dir.join(field.str()?);
+                let texture = ImageTexture::open(&path)
+                    .map_err(|e| field.error(format!("{}: {}", path.display(), e)))?;
+                Ok(Arc::new(texture))
+            }
+            other => Err(kind.error(format!("unknown texture type '{}'", other))),
+        }
+    }
+
+    /// A material parameter: the name of a material or an inline definition.
+    fn material(&self, field: &Field) -> Result<Arc<dyn Material>, SceneError> {
+        match &field.value.kind {
+            Kind::String(name) => self
+                .materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| field.error(format!("unknown material '{}'", name))),
+            Kind::Table(_) => self.material_definition(&field.section()?),
+            other => Err(field.error(format!(
+                "expected a material name or table, found {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    /// The index of the named light group, which is added if it is new.
+    fn light_group(&self, name: &str) -> usize {
+        let mut light_groups = self.light_groups.borrow_mut();
+        match light_groups.iter().position(|group| group == name) {
+            Some(index) => index,
+            None => {
+                light_groups.push(name.to_string());
+                light_groups.len() - 1
+            }
+        }
+    }
+
+    fn material_definition(&self, section: &Section) -> Result<Arc<dyn Material>, SceneError> {
+        let kind = section.required("type")?;
+        match kind.str()? {
+            "lambertian" => {
+                section.check_keys(&["type", "albedo"])?;
+                let albedo = self.texture(&section.required("albedo")?)?;
+                Ok(Arc::new(Lambertian::from_texture(albedo)))
+            }
+            "metal" => {
+                section.check_keys(&["type", "albedo", "fuzz"])?;
+                Ok(Arc::new(Metal::new(
+                    section.required("albedo")?.vec3()?,
+                    section.f64_or("fuzz", 0.0)?,
+                )))
+            }
+            "dielectric" => {
+                section.check_keys(&["type", "ior"])?;
+                Ok(Arc::new(Dielectric::new(section.required("ior")?.f64()?)))
+            }
+            "diffuse_light" => {
+                section.check_keys(&["type", "emit", "light_group"])?;
+                let emit = self.texture(&section.required("emit")?)?;
+                let light_group = match section.field("light_group") {
+                    Some(field) => self.light_group(field.str()?),
+                    None => 0,
+                };
+                Ok(Arc::new(
+                    DiffuseLight::from_texture(emit).with_light_group(light_group),
+                ))
+            }
+            "isotropic" => {
+                section.check_keys(&["type", "albedo"])?;
+                let albedo = self.texture(&section.required("albedo")?)?;
+                Ok(Arc::new(Isotropic::from_texture(albedo)))
+            }
+            other => Err(kind.error(format!("unknown material type '{}'", other))),
+        }
+    }
+
+    /// Builds the shape, then applies its `transform`, `flip_face`, `volume` and
+    /// `importance` in that order.
+    fn shape(&self, section: &Section) -> Result<Arc<dyn Hittable>, SceneError> {
+        const COMMON: [&str; 5] = ["type", "transform", "flip_face", "volume", "importance"];
+        let kind = section.required("type")?;
+        let keys = |specific: &[&str]| {
+            let mut keys = COMMON.to_vec();
+            keys.extend_from_slice(specific);
+            section.check_keys(&keys)
+        };
+        let material = || self.material(&section.required("material")?);
+
+        let mut shape: Arc<dyn Hittable> = match kind.str()? {
+            "sphere" => {
+                keys(&["center", "radius", "material"])?;
+                Arc::new(Sphere::new(
+                    section.required("center")?.vec3()?,
+                    section.required("radius")?.f64()?,
+                    material()?,
+                ))
+            }
+            "moving_sphere" => {
+                keys(&["center0", "center1", "time0", "time1", "radius", "material"])?;
+                Arc::new(MovingSphere::new(
+                    section.required("center0")?.vec3()?,
+                    section.required("center1")?.vec3()?,
+                    section.f64_or("time0", self.time0)?,
+                    section.f64_or("time1", self.time1)?,
+                    section.required("radius")?.f64()?,
+                    material()?,
+                ))
+            }
+            "xy_rect" => {
+                keys(&["x0", "x1", "y0", "y1", "k", "material"])?;
+                let [x0, x1, y0, y1, k] = section.f64s(["x0", "x1", "y0", "y1", "k"])?;
+                Arc::new(XyRect::new(x0, x1, y0, y1, k, material()?))
+            }
+            "xz_rect" => {
+                keys(&["x0", "x1", "z0", "z1", "k", "material"])?;
+                let [x0, x1, z0, z1, k] = section.f64s(["x0", "x1", "z0", "z1", "k"])?;
+                Arc::new(XzRect::new(x0, x1, z0, z1, k, material()?))
+            }
+            "yz_rect" => {
+                keys(&["y0", "y1", "z0", "z1", "k", "material"])?;
+                let [y0, y1, z0, z1, k] = section.f64s(["y0", "y1", "z0", "z1", "k"])?;
+                Arc::new(YzRect::new(y0, y1, z0, z1, k, material()?))
+            }
+            "box" => {
+                keys(&["min", "max", "material"])?;
+                Arc::new(Bbox::new(
+                    section.required("min")?.vec3()?,
+                    section.required("max")?.vec3()?,
+                    material()?,
+                ))
+            }
+            "triangle" => {
+                keys(&["v0", "v1", "v2", "material"])?;
+                Arc::new(Triangle::new(
+                    section.required("v0")?.vec3()?,
+                    section.required("v1")?.vec3()?,
+                    section.required("v2")?.vec3()?,
+                    material()?,
+                ))
+            }
+            "obj" => {
+                keys(&["path", "material", "bvh"])?;
+                let field = section.required("path")?;
+                let path = self.base_dir.join(field.str()?);
+                // Faces without a material from the `.mtl` library fall back to this one.
+                let default_material = match section.field("material") {
+                    Some(field) => self.material(&field)?,
+                    None => Arc::new(Lambertian::from_color(Color::new(0.8, 0.8, 0.8))),
+                };
+                let list =
+                    obj::load(&path, default_material).map_err(|e| field.error(e.to_string()))?;
+                self.group(list, section.bool_or("bvh", true)?)
+            }
+            "ply" => {
+                keys(&["path", "material"])?;
+                let field = section.required("path")?;
+                let path = self.base_dir.join(field.str()?);
+                let mesh = ply::load(&path, material()?).map_err(|e| field.error(e.to_string()))?;
+                Arc::new(mesh)
+            }
+            "group" => {
+                keys(&["children", "bvh"])?;
+                let mut list = HittableList::default();
+                for field in section.required("children")?.array()? {
+                    list.add(self.shape(&field.section()?)?);
+                }
+                self.group(list, section.bool_or("bvh", false)?)
+            }
+            other => return Err(kind.error(format!("unknown shape type '{}'", other))),
+        };
+
+        if let Some(field) = section.field("transform") {
+            let matrix = transform(&field)?;
+            shape = Arc::new(
+                Transform::new(shape, matrix)
+                    .ok_or_else(|| field.error("transform can't be inverted"))?,
+            );
+        }
+        if section.bool_or("flip_face", false)? {
+            shape = Arc::new(FlipFace::new(shape));
+        }
+        if let Some(field) = section.field("volume") {
+            let volume = field.section()?;
+            volume.check_keys(&["density", "albedo"])?;
+            shape = Arc::new(ConstantVolume::from_texture(
+                shape,
+                volume.required("density")?.f64()?,
+                self.texture(&volume.required("albedo")?)?,
+            ));
+        }
+        if section.bool_or("importance", false)? {
+            shape = Arc::new(ImportanceTarget::new(shape));
+        }
+        Ok(shape)
+    }
+
+    fn group(&self, list: HittableList, bvh: bool) -> Arc<dyn Hittable> {
+        if bvh && !list.objects.is_empty() {
+            Arc::new(LinearBvh::from_list(
+                &list,
+                self.time0,
+                self.time1,
+                BvhOptions::default(),
+            ))
+        } else {
+            Arc::new(list)
+        }
+    }
+}
+
+/// A list of steps like `[{ scale = 2 }, { rotate_y = 15 }, { translate = [1, 0, 0] }]`,
+/// applied to the shape in order.
+fn transform(field: &Field) -> Result<Mat4, SceneError> {
+    let mut matrix = Mat4::default();
+    for step in field.array()? {
+        let section = step.section()?;
+        let mut fields = section.fields();
+        let (name, value) = match (fields.next(), fields.next()) {
+            (Some(entry), None) => entry,
+            _ => return Err(step.error("a transform step needs exactly one key")),
+        };
+        let step = match name {
+            "translate" => Mat4::translation(value.vec3()?),
+            "scale" => match value.value.kind {
+                Kind::Array(_) => Mat4::scaling(value.vec3()?),
+                _ => {
+                    let s = value.f64()?;
+                    Mat4::scaling(Vec3::new(s, s, s))
+                }
+            },
+            "rotate_x" => Mat4::rotation_x(value.f64()?),
+            "rotate_y" => Mat4::rotation_y(value.f64()?),
+            "rotate_z" => Mat4::rotation_z(value.f64()?),
+            "rotate" => {
+                let rotate = value.section()?;
+                rotate.check_keys(&["axis", "angle"])?;
+                Mat4::rotation(
+                    rotate.required("axis")?.vec3()?,
+                    rotate.required("angle")?.f64()?,
+                )
+            }
+            "matrix" => {
+                let rows = value.array()?;
+                if rows.len() != 4 {
+                    return Err(value.error("a matrix needs 4 rows"));
+                }
+                let mut m = [[0.0; 4]; 4];
+                for (row, field) in m.iter_mut().zip(&rows) {
+                    *row = field.f64_array()?;
+                }
+                Mat4::new(m)
+            }
+            other => return Err(value.error(format!("unknown transform '{}'", other))),
+        };
+        matrix = step * matrix;
+    }
+    Ok(matrix)
+}
+
+/// A value along with the dotted path of its key, for error messages.
+struct Field<'a> {
+    value: &'a Value,
+    key: String,
+}
+
+impl<'a> Field<'a> {
+    fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::Parse {
+            path: None,
+            line: self.value.pos.line,
+            column: self.value.pos.column,
+            key: Some(self.key.clone()),
+            message: message.into(),
+        }
+    }
+
+    fn expected(&self, what: &str) -> SceneError {
+        self.error(format!(
+            "expected {}, found {}",
+            what,
+            self.value.kind.type_name()
+        ))
+    }
+
+    fn f64(&self) -> Result<f64, SceneError> {
+        match self.value.kind {
+            Kind::Integer(i) => Ok(i as f64),
+            Kind::Float(f) => Ok(f),
+            _ => Err(self.expected("a number")),
+        }
+    }
+
+    fn usize(&self) -> Result<usize, SceneError> {
+        match self.value.kind {
+            Kind::Integer(i) => usize::try_from(i).map_err(|_| self.error("must not be negative")),
+            _ => Err(self.expected("an integer")),
+        }
+    }
+
+    fn bool(&self) -> Result<bool, SceneError> {
+        match self.value.kind {
+            Kind::Boolean(b) => Ok(b),
+            _ => Err(self.expected("a boolean")),
+        }
+    }
+
+    fn str(&self) -> Result<&'a str, SceneError> {
+        match &self.value.kind {
+            Kind::String(s) => Ok(s),
+            _ => Err(self.expected("a string")),
+        }
+    }
+
+    fn array(&self) -> Result<Vec<Field<'a>>, SceneError> {
+        match &self.value.kind {
+            Kind::Array(items) => Ok(items
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Field {
+                    value,
+                    key: format!("{}[{}]", self.key, i),
+                })
+                .collect()),
+            _ => Err(self.expected("an array")),
+        }
+    }
+
+    fn f64_array<const N: usize>(&self) -> Result<[f64; N], SceneError> {
+        let items = self.array()?;
+        if items.len() != N {
+            return Err(self.error(format!("expected {} numbers, found {}", N, items.len())));
+        }
+        let mut values = [0.0; N];
+        for (value, item) in values.iter_mut().zip(&items) {
+            *value = item.f64()?;
+        }
+        Ok(values)
+    }
+
+    fn vec3(&self) -> Result<Vec3, SceneError> {
+        let [x, y, z] = self.f64_array()?;
+        Ok(Vec3::new(x, y, z))
+    }
+
+    fn section(&self) -> Result<Section<'a>, SceneError> {
+        match &self.value.kind {
+            Kind::Table(table) => Ok(Section {
+                table,
+                pos: self.value.pos,
+                key: self.key.clone(),
+            }),
+            _ => Err(self.expected("a table")),
+        }
+    }
+}
+
+/// A table along with the dotted path of its key, for error messages.
+struct Section<'a> {
+    table: &'a Table,
+    pos: Pos,
+    key: String,
+}
+
+impl<'a> Section<'a> {
+    fn child_key(&self, name: &str) -> String {
+        if self.key.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.key, name)
+        }
+    }
+
+    fn fields(&self) -> impl Iterator<Item = (&'a str, Field<'a>)> + '_ {
+        self.table.entries.iter().map(|(name, value)| {
+            (
+                name.as_str(),
+                Field {
+                    value,
+                    key: self.child_key(name),
+                },
+            )
+        })
+    }
+
+    fn field(&self, name: &str) -> Option<Field<'a>> {
+        self.table.get(name).map(|value| Field {
+            value,
+            key: self.child_key(name),
+        })
+    }
+
+    fn required(&self, name: &str) -> Result<Field<'a>, SceneError> {
+        self.field(name).ok_or_else(|| SceneError::Parse {
+            path: None,
+            line: self.pos.line,
+            column: self.pos.column,
+            key: Some(self.child_key(name)),
+            message: "missing key".to_string(),
+        })
+    }
+
+    /// Rejects keys outside `allowed`, which are most likely typos.
+    fn check_keys(&self, allowed: &[&str]) -> Result<(), SceneError> {
+        match self.fields().find(|(name, _)| !allowed.contains(name)) {
+            Some((_, field)) => Err(field.error("unknown key")),
+            None => Ok(()),
+        }
+    }
+
+    fn f64_or(&self, name: &str, default: f64) -> Result<f64, SceneError> {
+        self.field(name).map_or(Ok(default), |field| field.f64())
+    }
+
+    fn f64s<const N: usize>(&self, names: [&str; N]) -> Result<[f64; N], SceneError> {
+        let mut values = [0.0; N];
+        for (value, name) in values.iter_mut().zip(names) {
+            *value = self.required(name)?.f64()?;
+        }
+        Ok(values)
+    }
+
+    fn usize_or(&self, name: &str, default: usize) -> Result<usize, SceneError> {
+        self.field(name).map_or(Ok(default), |field| field.usize())
+    }
+
+    fn bool_or(&self, name: &str, default: bool) -> Result<bool, SceneError> {
+        self.field(name).map_or(Ok(default), |field| field.bool())
+    }
+
+    fn vec3_or(&self, name: &str, default: Vec3) -> Result<Vec3, SceneError> {
+        self.field(name).map_or(Ok(default), |field| field.vec3())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::ray::Ray;
+
+    use super::*;
+
+    const CAMERA: &str = r#"
+[camera]
+lookfrom = [0, 0, -10]
+lookat = [0, 0, 0]
+vfov = 40
+aspect_ratio = 1
+width = 100
+"#;
+
+    fn parse_with_camera(shapes: &str) -> Result<Scene, SceneError> {
+        parse(&format!("{}\n{}", CAMERA, shapes), Path::new(""))
+    }
+
+    fn error_location(result: Result<Scene, SceneError>) -> (usize, usize, Option<String>) {
+        match result {
+            Err(SceneError::Parse {
+                line, column, key, ..
+            }) => (line, column, key),
+            Err(SceneError::Io { .. }) => panic!("unexpected io error"),
+            Ok(_) => panic!("the scene should not parse"),
+        }
+    }
+
+    #[test]
+    fn builds_the_cornell_box_example() {
+        let scene = load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/scenes/cornell_box.toml"
+        ))
+        .unwrap();
+        assert_eq!(scene.camera.width, 600);
+        assert_eq!(scene.camera.height, 600);
+        assert_eq!(scene.background, color::BLACK);
+        assert_eq!(scene.world.objects.len(), 8);
+        assert_eq!(scene.lights.objects.len(), 2);
+        assert_eq!(scene.light_groups, ["default", "ceiling"]);
+
+        // Straight up from the floor through the light.
+        let r = Ray::new(Vec3::new(278.0, 1.0, 278.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 553.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn applies_transforms_in_order() {
+        let scene = parse_with_camera(
+            r#"
+[[shapes]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = { type = "lambertian", albedo = [0.5, 0.5, 0.5] }
+transform = [{ scale = 2 }, { translate = [10, 0, 0] }]
+"#,
+        )
+        .unwrap();
+        let bbox = scene.world.objects[0].bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.min - Vec3::new(8.0, -2.0, -2.0)).length() < 1e-9);
+        assert!((bbox.max - Vec3::new(12.0, 2.0, 2.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn picks_the_integrator() {
+        let scene = parse_with_camera(
+            r#"
+[integrator]
+type = "ambient_occlusion"
+samples = 8
+distance = 5
+
+[[shapes]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = { type = "lambertian", albedo = [0.5, 0.5, 0.5] }
+"#,
+        )
+        .unwrap();
+        // Nothing occludes the outside of a sphere.
+        let r = Ray::new(Vec3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let hit = scene.world.hit(&r, 0.001, f64::INFINITY);
+        let color = scene.integrator.radiance(&scene, &r, hit, &mut []);
+        assert_eq!(color, Color::new(1.0, 1.0, 1.0));
+
+        let error = parse_with_camera("[integrator]\ntype = \"whitted\"\n");
+        assert_eq!(
+            error_location(error),
+            (10, 8, Some("integrator.type".to_string()))
+        );
+
+        // Only the progressive variant shrinks its radius.
+        let progressive = "[integrator]\ntype = \"progressive_photon_map\"\nalpha = 0.5\n";
+        assert!(parse_with_camera(progressive).is_ok());
+        let fixed = "[integrator]\ntype = \"photon_map\"\nradius = 2\nalpha = 0.5\n";
+        assert!(parse_with_camera(fixed).is_err());
+        for (key, value) in [
+            ("photons", "0"),
+            ("radius", "0"),
+            ("radius", "-1.5"),
+            ("alpha", "0"),
+            ("alpha", "1"),
+        ] {
+            let toml = format!(
+                "[integrator]\ntype = \"progressive_photon_map\"\n{} = {}\n",
+                key, value
+            );
+            let (_, _, error_key) = error_location(parse_with_camera(&toml));
+            assert_eq!(error_key, Some(format!("integrator.{}", key)), "{}", toml);
+        }
+
+        // Russian roulette is set for the integrators that follow paths.
+        let bdpt = parse_with_camera("[integrator]\ntype = \"bdpt\"\nroulette_depth = 7\n");
+        assert_eq!(bdpt.unwrap().path_settings.roulette_depth, 7);
+        let normal = "[integrator]\ntype = \"normal\"\nroulette_depth = 7\n";
+        assert!(parse_with_camera(normal).is_err());
+    }
+
+    #[test]
+    fn reports_offending_keys() {
+        let error = parse_with_camera(
+            r#"
+[materials.red]
+type = "lambertian"
+albedo = [0.65, 0.05]
+"#,
+        );
+        assert_eq!(
+            error_location(error),
+            (12, 10, Some("materials.red.albedo".to_string()))
+        );
+
+        let error = parse_with_camera(
+            r#"
+[[shapes]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "missing"
+"#,
+        );
+        assert_eq!(
+            error_location(error),
+            (14, 12, Some("shapes[0].material".to_string()))
+        );
+
+        let error = parse_with_camera("[[shapes]]\ntype = \"sphere\"\nradios = 1\n");
+        assert_eq!(
+            error_location(error),
+            (11, 10, Some("shapes[0].radios".to_string()))
+        );
+
+        let error = parse("[camera]\nlookfrom = [0, 0, 0]\n", Path::new(""));
+        assert_eq!(
+            error_location(error),
+            (1, 1, Some("camera.lookat".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_images_too_small_to_render() {
+        let camera = |from: &str, to: &str| parse(&CAMERA.replace(from, to), Path::new(""));
+        assert_eq!(
+            error_location(camera("width = 100", "width = 1")),
+            (7, 9, Some("camera.width".to_string()))
+        );
+        assert_eq!(
+            error_location(camera("aspect_ratio = 1", "aspect_ratio = 80")),
+            (6, 16, Some("camera.aspect_ratio".to_string()))
+        );
+        assert_eq!(
+            error_location(camera("width = 100", "width = 100\nsamples_per_pixel = 0")),
+            (8, 21, Some("camera.samples_per_pixel".to_string()))
+        );
+        assert!(camera("width = 100", "width = 2").is_ok());
+    }
+}
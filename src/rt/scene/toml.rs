@@ -0,0 +1,664 @@
+//! Parser for the subset of TOML used by scene files: bare, quoted and dotted keys,
+//! `[tables]`, `[[arrays of tables]]`, strings, numbers, booleans, arrays and inline tables.
+//! Dates and multi-line strings aren't supported.
+
+use std::fmt::{self, Display};
+
+/// 1-based line and column of a character in the source.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub pos: Pos,
+    /// Dotted path of the key being defined when the error happened, if any.
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.message)?;
+        if let Some(key) = &self.key {
+            write!(f, " (at '{}')", key)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Kind {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Kind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Kind::String(_) => "string",
+            Kind::Integer(_) => "integer",
+            Kind::Float(_) => "float",
+            Kind::Boolean(_) => "boolean",
+            Kind::Array(_) => "array",
+            Kind::Table(_) => "table",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub kind: Kind,
+    /// Where the value starts, or the header of a table defined with `[name]`.
+    pub pos: Pos,
+}
+
+/// Keys in the order they were defined.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub entries: Vec<(String, Value)>,
+    /// Set for tables created by a `[header]` or a `key = value` line, which can't be
+    /// defined a second time. Parents created implicitly by dotted keys can be.
+    defined: bool,
+}
+
+impl Table {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.entries
+            .iter_mut()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+pub fn parse(source: &str) -> Result<Table, ParseError> {
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        index: 0,
+        line: 1,
+        column: 1,
+        key: None,
+    };
+    parser.document()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+    key: Option<String>,
+}
+
+impl Parser {
+    fn pos(&self) -> Pos {
+        Pos {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn error_at(&self, pos: Pos, message: impl Into<String>) -> ParseError {
+        ParseError {
+            pos,
+            key: self.key.clone(),
+            message: message.into(),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        self.error_at(self.pos(), message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", c)))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        match self.peek() {
+            None => self.error(format!("expected {}, found end of file", expected)),
+            Some('\n') => self.error(format!("expected {}, found end of line", expected)),
+            Some(c) => self.error(format!("expected {}, found '{}'", expected, c)),
+        }
+    }
+
+    /// Skips spaces, tabs and a trailing comment, but not the end of the line.
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.bump();
+                }
+                '#' => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Skips blanks, comments and newlines.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_blank();
+            if !self.eat('\n') {
+                break;
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_blank();
+        if self.peek().is_none() || self.eat('\n') {
+            Ok(())
+        } else {
+            Err(self.unexpected("end of line"))
+        }
+    }
+
+    fn document(&mut self) -> Result<Table, ParseError> {
+        let mut root = Table::default();
+        // Path of the table the following key/value lines go into.
+        let mut current: Vec<String> = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            let pos = self.pos();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.bump();
+                    let array = self.eat('[');
+                    self.skip_blank();
+                    let path = self.dotted_key()?;
+                    self.key = Some(path.join("."));
+                    self.skip_blank();
+                    self.expect(']')?;
+                    if array {
+                        self.expect(']')?;
+                    }
+                    self.end_of_line()?;
+                    if array {
+                        self.push_table(&mut root, &path, pos)?;
+                    } else {
+                        self.define_table(&mut root, &path, pos)?;
+                    }
+                    current = path;
+                }
+                Some(_) => {
+                    let key_pos = self.pos();
+                    let path = self.dotted_key()?;
+                    let mut full: Vec<String> = current.clone();
+                    full.extend(path.iter().cloned());
+                    self.key = Some(full.join("."));
+                    self.skip_blank();
+                    self.expect('=')?;
+                    self.skip_blank();
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    let table = self.navigate(&mut root, &current, pos)?;
+                    self.insert(table, &path, value, key_pos)?;
+                }
+            }
+        }
+    }
+
+    fn bare_key_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '-'
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            Some(c) if Parser::bare_key_char(c) => {
+                let mut key = String::new();
+                while let Some(c) = self.peek().filter(|&c| Parser::bare_key_char(c)) {
+                    key.push(c);
+                    self.bump();
+                }
+                Ok(key)
+            }
+            _ => Err(self.unexpected("a key")),
+        }
+    }
+
+    fn dotted_key(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_blank();
+            if !self.eat('.') {
+                return Ok(path);
+            }
+            self.skip_blank();
+            path.push(self.key()?);
+        }
+    }
+
+    /// Walks down `path` from `root`, stepping into the last element of arrays of tables
+    /// and creating missing tables on the way.
+    fn navigate<'t>(
+        &self,
+        root: &'t mut Table,
+        path: &[String],
+        pos: Pos,
+    ) -> Result<&'t mut Table, ParseError> {
+        let mut table = root;
+        for key in path {
+            if table.get(key).is_none() {
+                table.entries.push((
+                    key.clone(),
+                    Value {
+                        kind: Kind::Table(Table::default()),
+                        pos,
+                    },
+                ));
+            }
+            table = match &mut table.get_mut(key).unwrap().kind {
+                Kind::Table(t) => t,
+                Kind::Array(items) => match items.last_mut().map(|v| &mut v.kind) {
+                    Some(Kind::Table(t)) => t,
+                    _ => return Err(self.error_at(pos, format!("'{}' is not a table", key))),
+                },
+                other => {
+                    return Err(self.error_at(
+                        pos,
+                        format!("'{}' is a {}, not a table", key, other.type_name()),
+                    ))
+                }
+            };
+        }
+        Ok(table)
+    }
+
+    fn define_table(&self, root: &mut Table, path: &[String], pos: Pos) -> Result<(), ParseError> {
+        let (last, parents) = path.split_last().unwrap();
+        let parent = self.navigate(root, parents, pos)?;
+        match parent.get_mut(last) {
+            None => {
+                parent.entries.push((
+                    last.clone(),
+                    Value {
+                        kind: Kind::Table(Table {
+                            entries: Vec::new(),
+                            defined: true,
+                        }),
+                        pos,
+                    },
+                ));
+                Ok(())
+            }
+            Some(Value {
+                kind: Kind::Table(t),
+                pos: table_pos,
+            }) if !t.defined => {
+                t.defined = true;
+                *table_pos = pos;
+                Ok(())
+            }
+            Some(_) => Err(self.error_at(pos, format!("'{}' is defined twice", path.join(".")))),
+        }
+    }
+
+    fn push_table(&self, root: &mut Table, path: &[String], pos: Pos) -> Result<(), ParseError> {
+        let (last, parents) = path.split_last().unwrap();
+        let parent = self.navigate(root, parents, pos)?;
+        let table = Value {
+            kind: Kind::Table(Table {
+                entries: Vec::new(),
+                defined: true,
+            }),
+            pos,
+        };
+        match parent.get_mut(last) {
+            None => {
+                parent.entries.push((
+                    last.clone(),
+                    Value {
+                        kind: Kind::Array(vec![table]),
+                        pos,
+                    },
+                ));
+                Ok(())
+            }
+            Some(Value {
+                kind: Kind::Array(items),
+                ..
+            }) if items.iter().all(|v| matches!(v.kind, Kind::Table(_))) => {
+                items.push(table);
+                Ok(())
+            }
+            Some(_) => Err(self.error_at(
+                pos,
+                format!("'{}' is not an array of tables", path.join(".")),
+            )),
+        }
+    }
+
+    fn insert(
+        &self,
+        table: &mut Table,
+        path: &[String],
+        value: Value,
+        pos: Pos,
+    ) -> Result<(), ParseError> {
+        let (last, parents) = path.split_last().unwrap();
+        let table = self.navigate(table, parents, pos)?;
+        if table.get(last).is_some() {
+            return Err(self.error_at(pos, "key is defined twice"));
+        }
+        table.entries.push((last.clone(), value));
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        let pos = self.pos();
+        let kind = match self.peek() {
+            Some('"') => Kind::String(self.basic_string()?),
+            Some('\'') => Kind::String(self.literal_string()?),
+            Some('[') => Kind::Array(self.array()?),
+            Some('{') => Kind::Table(self.inline_table()?),
+            Some('t') | Some('f') if self.word_is("true") || self.word_is("false") => {
+                let value = self.word_is("true");
+                for _ in 0..if value { 4 } else { 5 } {
+                    self.bump();
+                }
+                Kind::Boolean(value)
+            }
+            Some(c) if c.is_ascii_digit() || matches!(c, '+' | '-' | 'i' | 'n') => self.number()?,
+            _ => return Err(self.unexpected("a value")),
+        };
+        Ok(Value { kind, pos })
+    }
+
+    fn word_is(&self, word: &str) -> bool {
+        let end = self.index + word.len();
+        end <= self.chars.len()
+            && self.chars[self.index..end].iter().copied().eq(word.chars())
+            && !self
+                .chars
+                .get(end)
+                .is_some_and(|&c| Parser::bare_key_char(c))
+    }
+
+    fn number(&mut self) -> Result<Kind, ParseError> {
+        let pos = self.pos();
+        let mut text = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|&c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_'))
+        {
+            text.push(c);
+            self.bump();
+        }
+        let invalid = || self.error_at(pos, format!("invalid number '{}'", text));
+        let digits = text.replace('_', "");
+        let unsigned = digits.trim_start_matches(['+', '-']);
+        match unsigned {
+            "inf" => {
+                return Ok(Kind::Float(if digits.starts_with('-') {
+                    f64::NEG_INFINITY
+                } else {
+                    f64::INFINITY
+                }))
+            }
+            "nan" => return Ok(Kind::Float(f64::NAN)),
+            _ => {}
+        }
+        if !unsigned.starts_with(|c: char| c.is_ascii_digit()) || text.contains("__") {
+            return Err(invalid());
+        }
+        if unsigned.contains(['.', 'e', 'E']) {
+            digits.parse().map(Kind::Float).map_err(|_| invalid())
+        } else {
+            digits.parse().map(Kind::Integer).map_err(|_| invalid())
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('"') => {
+                    self.bump();
+                    return Ok(s);
+                }
+                Some('\\') => {
+                    self.bump();
+                    let escape = self.pos();
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(u @ ('u' | 'U')) => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let mut hex = String::new();
+                            for _ in 0..len {
+                                hex.extend(self.bump());
+                            }
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error_at(escape, "invalid unicode escape"))?
+                        }
+                        _ => return Err(self.error_at(escape, "invalid escape sequence")),
+                    };
+                    s.push(c);
+                }
+                Some(c) => {
+                    s.push(c);
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<Value>, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.eat(']') {
+                return Ok(items);
+            }
+            items.push(self.value()?);
+            self.skip_blank_lines();
+            if !self.eat(',') {
+                self.skip_blank_lines();
+                self.expect(']')?;
+                return Ok(items);
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Table, ParseError> {
+        self.expect('{')?;
+        let mut table = Table {
+            entries: Vec::new(),
+            defined: true,
+        };
+        let outer_key = self.key.clone();
+        self.skip_blank();
+        if self.eat('}') {
+            return Ok(table);
+        }
+        loop {
+            self.skip_blank();
+            let key_pos = self.pos();
+            let path = self.dotted_key()?;
+            self.key = Some(match &outer_key {
+                Some(outer) => format!("{}.{}", outer, path.join(".")),
+                None => path.join("."),
+            });
+            self.skip_blank();
+            self.expect('=')?;
+            self.skip_blank();
+            let value = self.value()?;
+            self.insert(&mut table, &path, value, key_pos)?;
+            self.skip_blank();
+            if self.eat('}') {
+                self.key = outer_key;
+                return Ok(table);
+            }
+            if !self.eat(',') {
+                return Err(self.unexpected("',' or '}'"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(table: &Table, key: &str) -> f64 {
+        match table.get(key).unwrap().kind {
+            Kind::Integer(i) => i as f64,
+            Kind::Float(f) => f,
+            _ => panic!("{} isn't a number", key),
+        }
+    }
+
+    #[test]
+    fn parses_tables_and_arrays_of_tables() {
+        let doc = parse(
+            r#"
+# comment
+title = "cornell" # trailing
+[camera]
+vfov = 40
+lookfrom = [278, 278.5, -8e2]
+
+[[shapes]]
+type = 'sphere'
+material = { type = "dielectric", ior = 1.5 }
+
+[[shapes]]
+type = "box"
+transform = [
+    { translate = [1, 2, 3] },
+    { rotate_y = -18 }, # comment inside an array
+]
+"#,
+        )
+        .unwrap();
+
+        assert!(matches!(&doc.get("title").unwrap().kind, Kind::String(s) if s == "cornell"));
+        let Kind::Table(camera) = &doc.get("camera").unwrap().kind else {
+            panic!("camera isn't a table");
+        };
+        assert_eq!(number(camera, "vfov"), 40.0);
+        assert_eq!(camera.get("vfov").unwrap().pos, Pos { line: 5, column: 8 });
+        let Kind::Array(lookfrom) = &camera.get("lookfrom").unwrap().kind else {
+            panic!("lookfrom isn't an array");
+        };
+        assert!(matches!(lookfrom[2].kind, Kind::Float(z) if z == -800.0));
+
+        let Kind::Array(shapes) = &doc.get("shapes").unwrap().kind else {
+            panic!("shapes isn't an array");
+        };
+        assert_eq!(shapes.len(), 2);
+        assert_eq!(shapes[1].pos.line, 12);
+        let Kind::Table(sphere) = &shapes[0].kind else {
+            panic!("shape isn't a table");
+        };
+        let Kind::Table(material) = &sphere.get("material").unwrap().kind else {
+            panic!("material isn't a table");
+        };
+        assert_eq!(number(material, "ior"), 1.5);
+    }
+
+    #[test]
+    fn dotted_keys_create_tables() {
+        let doc = parse("a.b = 1\n[c.d]\ne = 2\n[c]\nf = 3\n").unwrap();
+        let Kind::Table(a) = &doc.get("a").unwrap().kind else {
+            panic!("a isn't a table");
+        };
+        assert_eq!(number(a, "b"), 1.0);
+        let Kind::Table(c) = &doc.get("c").unwrap().kind else {
+            panic!("c isn't a table");
+        };
+        assert!(c.get("d").is_some());
+        assert_eq!(number(c, "f"), 3.0);
+    }
+
+    #[test]
+    fn reports_position_and_key_of_errors() {
+        let error = parse("[camera]\nvfov = 40\nwidth = 6x0\n").unwrap_err();
+        assert_eq!(error.pos, Pos { line: 3, column: 9 });
+        assert_eq!(error.key.as_deref(), Some("camera.width"));
+
+        let error = parse("[camera]\nvfov = 40\nvfov = 20\n").unwrap_err();
+        assert_eq!(error.pos, Pos { line: 3, column: 1 });
+        assert_eq!(error.message, "key is defined twice");
+
+        let error = parse("m = { type = \"metal\" fuzz = 1 }").unwrap_err();
+        assert_eq!(
+            error.pos,
+            Pos {
+                line: 1,
+                column: 22
+            }
+        );
+        assert_eq!(error.key.as_deref(), Some("m.type"));
+
+        let error = parse("s = \"open\n").unwrap_err();
+        assert_eq!(error.message, "unterminated string");
+    }
+}
@@ -1,6 +1,6 @@
 use std::mem::swap;
 
-use crate::rt::{ray::Ray, Point3};
+use crate::rt::{mat4::Mat4, ray::Ray, vec3::Vec3, Point3};
 
 #[derive(Copy, Clone)]
 pub struct Aabb {
@@ -27,6 +27,34 @@ impl Aabb {
         Aabb::new(small, big)
     }
 
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Box around the eight transformed corners.
+    pub fn transform(&self, m: &Mat4) -> Aabb {
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            let p = m.transform_point(corner);
+            for c in 0..3 {
+                min.set(c, min.get(c).min(p.get(c)));
+                max.set(c, max.get(c).max(p.get(c)));
+            }
+        }
+        Aabb::new(min, max)
+    }
+
     pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
         let mut t_min = t_min;
         let mut t_max = t_max;
@@ -45,7 +73,30 @@ impl Aabb {
                 return false;
             }
         }
-        return true;
+        true
+    }
+
+    /// Same test as `hit` with the reciprocal of the ray direction computed by the caller,
+    /// for traversals that test one ray against many boxes.
+    pub fn hit_inverse(&self, r: &Ray, inv_direction: Vec3, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d = inv_direction.get(a);
+            let mut t0 = (self.min.get(a) - r.origin.get(a)) * inv_d;
+            let mut t1 = (self.max.get(a) - r.origin.get(a)) * inv_d;
+
+            if inv_d < 0.0 {
+                swap(&mut t0, &mut t1);
+            }
+
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
     }
 
     // pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
@@ -0,0 +1,347 @@
+use std::sync::Arc;
+
+use crate::rt::{
+    mat4::Mat4,
+    quaternion::{self, Quaternion},
+    ray::Ray,
+    vec3::Vec3,
+    Point3,
+};
+
+use super::{
+    aabb::Aabb,
+    hit_record::HitRecord,
+    transform::{transformed_hit, transformed_pdf_value, transformed_random},
+    Hittable, LightParts,
+};
+
+/// Number of shutter samples used to bound the swept volume, on top of the key times.
+const BOUND_SAMPLES: usize = 64;
+
+pub trait Interpolate: Copy {
+    fn interpolate(a: Self, b: Self, t: f64) -> Self;
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(a: Self, b: Self, t: f64) -> Self {
+        (1.0 - t) * a + t * b
+    }
+}
+
+impl Interpolate for Quaternion {
+    fn interpolate(a: Self, b: Self, t: f64) -> Self {
+        Quaternion::slerp(a, b, t)
+    }
+}
+
+/// Values keyed by time. Sampling before the first or after the last key holds the end
+/// value.
+#[derive(Clone)]
+pub struct Track<T> {
+    keys: Vec<(f64, T)>,
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new(mut keys: Vec<(f64, T)>) -> Track<T> {
+        assert!(!keys.is_empty(), "a track needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Track { keys }
+    }
+
+    pub fn constant(value: T) -> Track<T> {
+        Track::new(vec![(0.0, value)])
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|&(t, _)| t <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (t0, a) = self.keys[next - 1];
+        let (t1, b) = self.keys[next];
+        T::interpolate(a, b, (time - t0) / (t1 - t0))
+    }
+
+    fn key_times(&self) -> impl Iterator<Item = f64> + '_ {
+        self.keys.iter().map(|&(t, _)| t)
+    }
+}
+
+/// Moves any hittable along translation, rotation and scale tracks evaluated at the ray
+/// time, so boxes, meshes and whole lists can be motion blurred. The object is scaled,
+/// then rotated, then translated.
+pub struct AnimatedTransform {
+    hittable: Arc<dyn Hittable>,
+    translation: Track<Vec3>,
+    rotation: Track<Quaternion>,
+    scale: Track<Vec3>,
+}
+
+impl AnimatedTransform {
+    /// Returns `None` when a scale key has a zero component or a component changes sign
+    /// between two keys, since the interpolated scale would then reach zero and the matrix
+    /// couldn't be inverted.
+    pub fn new(
+        hittable: Arc<dyn Hittable>,
+        translation: Track<Vec3>,
+        rotation: Track<Quaternion>,
+        scale: Track<Vec3>,
+    ) -> Option<AnimatedTransform> {
+        // Every component keeps the strictly positive or negative sign of the first key.
+        let first = scale.keys[0].1;
+        let valid = scale
+            .keys
+            .iter()
+            .all(|&(_, s)| s.x * first.x > 0.0 && s.y * first.y > 0.0 && s.z * first.z > 0.0);
+        if !valid {
+            return None;
+        }
+        Some(AnimatedTransform {
+            hittable,
+            translation,
+            rotation,
+            scale,
+        })
+    }
+
+    pub fn from_translation(hittable: Arc<dyn Hittable>, translation: Track<Vec3>) -> Self {
+        AnimatedTransform {
+            hittable,
+            translation,
+            rotation: Track::constant(quaternion::IDENTITY),
+            scale: Track::constant(Vec3::new(1.0, 1.0, 1.0)),
+        }
+    }
+
+    /// Object to world matrix at `time`, and its inverse.
+    pub fn matrices(&self, time: f64) -> (Mat4, Mat4) {
+        let t = self.translation.sample(time);
+        let r = self.rotation.sample(time);
+        let s = self.scale.sample(time);
+        let matrix = Mat4::translation(t) * r.to_mat4() * Mat4::scaling(s);
+        let inverse = Mat4::scaling(Vec3::new(1.0 / s.x, 1.0 / s.y, 1.0 / s.z))
+            * r.conjugate().to_mat4()
+            * Mat4::translation(-t);
+        (matrix, inverse)
+    }
+}
+
+impl Hittable for AnimatedTransform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (matrix, inverse) = self.matrices(r.time);
+        transformed_hit(self.hittable.as_ref(), &matrix, &inverse, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let local_box = self.hittable.bounding_box(time0, time1)?;
+
+        let mut times: Vec<f64> = (0..=BOUND_SAMPLES)
+            .map(|i| time0 + (time1 - time0) * i as f64 / BOUND_SAMPLES as f64)
+            .chain(self.translation.key_times())
+            .chain(self.rotation.key_times())
+            .chain(self.scale.key_times())
+            .filter(|&t| t >= time0 && t <= time1)
+            .collect();
+        times.sort_by(f64::total_cmp);
+        times.dedup();
+
+        // Translation and scale are linear between consecutive times, so the sampled boxes
+        // only miss the bulge of the rotation. Between two samples that rotate by theta a
+        // point at distance r from the pivot strays at most 2 * r * theta from them.
+        let max_scale = times
+            .iter()
+            .map(|&t| {
+                let s = self.scale.sample(t);
+                s.x.abs().max(s.y.abs()).max(s.z.abs())
+            })
+            .fold(0.0, f64::max);
+        let radius = local_box.min.length().max(local_box.max.length())
+            + (local_box.max - local_box.min).length();
+        let radius = radius * max_scale;
+
+        let mut output_box: Option<Aabb> = None;
+        let mut padding: f64 = 0.0;
+        for (i, &t) in times.iter().enumerate() {
+            let (matrix, _) = self.matrices(t);
+            let sample_box = local_box.transform(&matrix);
+            output_box = Some(match output_box {
+                None => sample_box,
+                Some(b) => Aabb::surrounding_box(&b, &sample_box),
+            });
+            if i > 0 {
+                let theta = Quaternion::angle_between(
+                    self.rotation.sample(times[i - 1]),
+                    self.rotation.sample(t),
+                );
+                padding = padding.max(2.0 * radius * theta);
+            }
+        }
+
+        output_box.map(|b| {
+            let padding = Vec3::new(padding, padding, padding);
+            Aabb::new(b.min - padding, b.max + padding)
+        })
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        let (_, inverse) = self.matrices(time);
+        transformed_pdf_value(self.hittable.as_ref(), &inverse, o, v, time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        let (matrix, inverse) = self.matrices(time);
+        transformed_random(self.hittable.as_ref(), &matrix, &inverse, o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        self.hittable.light_parts().wrap(|part| {
+            Arc::new(AnimatedTransform {
+                hittable: part,
+                translation: self.translation.clone(),
+                rotation: self.rotation.clone(),
+                scale: self.scale.clone(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        color::Color,
+        materials::diffuse_light::DiffuseLight,
+        shapes::{mooving_sphere::MovingSphere, sphere::Sphere},
+        PI,
+    };
+
+    use super::*;
+
+    fn light() -> Arc<DiffuseLight> {
+        Arc::new(DiffuseLight::from_color(Color::new(1.0, 1.0, 1.0)))
+    }
+
+    fn cone_pdf(distance: f64) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - f64::sqrt(1.0 - 1.0 / (distance * distance))))
+    }
+
+    #[test]
+    fn samples_keyframed_lights_where_they_are() {
+        let object: Arc<dyn Hittable> = Arc::new(AnimatedTransform::from_translation(
+            Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, light())),
+            Track::new(vec![
+                (0.0, Vec3::new(0.0, 0.0, 0.0)),
+                (1.0, Vec3::new(10.0, 0.0, 0.0)),
+            ]),
+        ));
+
+        let o = Vec3::new(10.0, 0.0, -5.0);
+        let toward = Vec3::new(0.0, 0.0, 1.0);
+        assert!((object.pdf_value(o, toward, 1.0) - cone_pdf(5.0)).abs() < 1e-9);
+        assert_eq!(object.pdf_value(o, toward, 0.0), 0.0);
+        for _ in 0..100 {
+            let direction = object.random(o, 1.0);
+            assert!(object
+                .hit(&Ray::new(o, direction, 1.0), 0.001, f64::INFINITY)
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn samples_moving_spheres_where_they_are() {
+        let object: Arc<dyn Hittable> = Arc::new(MovingSphere::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(10.0, 0.0, 0.0),
+            0.0,
+            1.0,
+            1.0,
+            light(),
+        ));
+
+        let o = Vec3::new(5.0, 0.0, -5.0);
+        let toward = Vec3::new(0.0, 0.0, 1.0);
+        assert!((object.pdf_value(o, toward, 0.5) - cone_pdf(5.0)).abs() < 1e-9);
+        assert_eq!(object.pdf_value(o, toward, 0.0), 0.0);
+        for _ in 0..100 {
+            let direction = object.random(o, 0.5);
+            assert!(object
+                .hit(&Ray::new(o, direction, 0.5), 0.001, f64::INFINITY)
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_scales_that_reach_zero() {
+        let sphere = || Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, light()));
+        let animated = |keys| {
+            AnimatedTransform::new(
+                sphere(),
+                Track::constant(Vec3::new(0.0, 0.0, 0.0)),
+                Track::constant(quaternion::IDENTITY),
+                Track::new(keys),
+            )
+        };
+        assert!(animated(vec![(0.0, Vec3::new(1.0, 0.0, 1.0))]).is_none());
+        assert!(animated(vec![
+            (0.0, Vec3::new(1.0, 1.0, 1.0)),
+            (1.0, Vec3::new(1.0, -1.0, 1.0)),
+        ])
+        .is_none());
+        assert!(animated(vec![
+            (0.0, Vec3::new(1.0, -1.0, 1.0)),
+            (1.0, Vec3::new(2.0, -0.5, 3.0)),
+        ])
+        .is_some());
+    }
+
+    #[test]
+    fn bounding_box_covers_the_object_over_the_shutter() {
+        let object = AnimatedTransform::new(
+            Arc::new(Sphere::new(Vec3::new(2.0, 0.0, 0.0), 0.5, light())),
+            Track::new(vec![
+                (0.0, Vec3::new(0.0, 0.0, 0.0)),
+                (1.0, Vec3::new(1.0, 3.0, -2.0)),
+            ]),
+            Track::new(vec![
+                (0.0, quaternion::IDENTITY),
+                (
+                    0.5,
+                    Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 170.0),
+                ),
+                (
+                    1.0,
+                    Quaternion::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 300.0),
+                ),
+            ]),
+            Track::new(vec![
+                (0.0, Vec3::new(1.0, 1.0, 1.0)),
+                (1.0, Vec3::new(2.0, 0.5, 1.5)),
+            ]),
+        )
+        .unwrap();
+        let bbox = object.bounding_box(0.0, 1.0).unwrap();
+
+        for i in 0..=200 {
+            let (matrix, _) = object.matrices(i as f64 / 200.0);
+            for j in 0..50 {
+                // Points spread over the sphere surface.
+                let z = 1.0 - 2.0 * (j as f64 + 0.5) / 50.0;
+                let phi = j as f64 * 2.4;
+                let r = (1.0 - z * z).sqrt();
+                let local =
+                    Vec3::new(2.0, 0.0, 0.0) + 0.5 * Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                let p = matrix.transform_point(local);
+                for a in 0..3 {
+                    assert!(
+                        p.get(a) >= bbox.min.get(a) && p.get(a) <= bbox.max.get(a),
+                        "point {:?} at time {} outside the box",
+                        p,
+                        i as f64 / 200.0
+                    );
+                }
+            }
+        }
+    }
+}
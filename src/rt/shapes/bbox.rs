@@ -4,7 +4,7 @@ use crate::rt::{materials::Material, ray::Ray, Point3};
 
 use super::{
     aabb::Aabb, hit_record::HitRecord, hittable_list::HittableList, xy_rect::XyRect,
-    xz_rect::XzRect, yz_rect::YzRect, Hittable,
+    xz_rect::XzRect, yz_rect::YzRect, Hittable, LightParts,
 };
 
 pub struct Bbox {
@@ -73,11 +73,15 @@ impl Bbox {
 }
 
 impl Hittable for Bbox {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         self.sides.hit(r, t_min, t_max)
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(self.min, self.max))
     }
+
+    fn light_parts(&self) -> LightParts {
+        self.sides.light_parts()
+    }
 }
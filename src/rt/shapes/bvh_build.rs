@@ -0,0 +1,471 @@
+#[cfg(feature = "bvh_stats")]
+use std::cell::Cell;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::rt::Point3;
+
+use super::{aabb::Aabb, Hittable};
+
+/// Cost of visiting an interior node relative to intersecting one primitive.
+pub(super) const TRAVERSAL_COST: f64 = 0.125;
+
+/// Nodes with fewer primitives than this are built on the current thread, below it the
+/// overhead of handing work to rayon outweighs the gain.
+pub(super) const PARALLEL_THRESHOLD: usize = 1024;
+
+/// Primitives binned by one rayon task during a parallel SAH sweep.
+const BIN_CHUNK_SIZE: usize = 4096;
+
+/// Whether `count_node_visits` counts anything, which takes the `bvh_stats` feature.
+pub const COUNTS_NODE_VISITS: bool = cfg!(feature = "bvh_stats");
+
+#[cfg(feature = "bvh_stats")]
+thread_local! {
+    static NODES_VISITED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Called by the BVHs for every node whose box they test a ray against. Does nothing
+/// without the `bvh_stats` feature.
+#[inline(always)]
+pub(super) fn count_node_visit() {
+    #[cfg(feature = "bvh_stats")]
+    NODES_VISITED.with(|visited| visited.set(visited.get() + 1));
+}
+
+/// Runs `f`, returning its result with the number of BVH nodes it visited on this thread,
+/// always zero without the `bvh_stats` feature.
+#[cfg(feature = "bvh_stats")]
+pub fn count_node_visits<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = NODES_VISITED.with(Cell::get);
+    let result = f();
+    (result, NODES_VISITED.with(Cell::get) - before)
+}
+
+#[cfg(not(feature = "bvh_stats"))]
+pub fn count_node_visits<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    (f(), 0)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SplitMethod {
+    /// Splits at the centroid median of the widest axis.
+    Median,
+    /// Binned surface area heuristic, evaluating `bins - 1` candidate planes per axis.
+    Sah { bins: usize },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BvhOptions {
+    pub split: SplitMethod,
+    /// Largest number of primitives a leaf may hold. The SAH may stop splitting earlier
+    /// when a leaf is cheaper, the median split always fills leaves up to this size.
+    pub max_leaf_size: usize,
+    /// Builds subtrees and bins primitives on the rayon thread pool. The tree is the same
+    /// either way.
+    pub parallel: bool,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        BvhOptions {
+            split: SplitMethod::Sah { bins: 16 },
+            max_leaf_size: 4,
+            parallel: true,
+        }
+    }
+}
+
+pub(super) struct PrimitiveInfo {
+    pub index: usize,
+    pub bbox: Aabb,
+    pub centroid: Point3,
+}
+
+pub(super) fn primitive_infos(
+    objects: &[Arc<dyn Hittable>],
+    time0: f64,
+    time1: f64,
+    parallel: bool,
+) -> Vec<PrimitiveInfo> {
+    let info = |(index, object): (usize, &Arc<dyn Hittable>)| {
+        let bbox = object
+            .bounding_box(time0, time1)
+            .expect("bvh primitives need a bounding box");
+        PrimitiveInfo {
+            index,
+            bbox,
+            centroid: bbox.centroid(),
+        }
+    };
+    if parallel && objects.len() >= PARALLEL_THRESHOLD {
+        objects.par_iter().enumerate().map(info).collect()
+    } else {
+        objects.iter().enumerate().map(info).collect()
+    }
+}
+
+pub(super) fn bounds(infos: &[PrimitiveInfo]) -> Option<Aabb> {
+    let (head, tail) = infos.split_first()?;
+    Some(
+        tail.iter()
+            .fold(head.bbox, |b, info| Aabb::surrounding_box(&b, &info.bbox)),
+    )
+}
+
+fn centroid_bounds(infos: &[PrimitiveInfo]) -> Aabb {
+    let first = infos[0].centroid;
+    infos.iter().fold(Aabb::new(first, first), |b, info| {
+        Aabb::surrounding_box(&b, &Aabb::new(info.centroid, info.centroid))
+    })
+}
+
+fn widest_axis(b: &Aabb) -> i32 {
+    let d = b.max - b.min;
+    if d.x >= d.y && d.x >= d.z {
+        0
+    } else if d.y >= d.z {
+        1
+    } else {
+        2
+    }
+}
+
+/// Runs `a` and `b`, in parallel when `parallel` is set.
+pub(super) fn join<A, B, RA, RB>(parallel: bool, a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    if parallel {
+        rayon::join(a, b)
+    } else {
+        (a(), b())
+    }
+}
+
+/// Where a node was divided, `infos[..mid]` goes to the first child and `infos[mid..]` to
+/// the second.
+#[derive(Copy, Clone)]
+pub(super) struct Split {
+    pub mid: usize,
+    pub axis: i32,
+}
+
+/// Reorders `infos` for the two children of a node, or returns `None` when the primitives
+/// should stay together in a leaf.
+pub(super) fn split(infos: &mut [PrimitiveInfo], options: &BvhOptions) -> Option<Split> {
+    let n = infos.len();
+    if n <= 1 {
+        return None;
+    }
+
+    let centroids = centroid_bounds(infos);
+    let axis = widest_axis(&centroids);
+    let extent = centroids.max.get(axis) - centroids.min.get(axis);
+    if extent <= 0.0 {
+        // Every centroid coincides, no plane can separate them.
+        return if n <= options.max_leaf_size {
+            None
+        } else {
+            Some(Split { mid: n / 2, axis })
+        };
+    }
+
+    match options.split {
+        SplitMethod::Median if n <= options.max_leaf_size => None,
+        SplitMethod::Median => Some(median_split(infos, axis)),
+        SplitMethod::Sah { bins } => sah_split(infos, &centroids, bins.max(2), options),
+    }
+}
+
+fn median_split(infos: &mut [PrimitiveInfo], axis: i32) -> Split {
+    let mid = infos.len() / 2;
+    infos.select_nth_unstable_by(mid, |a, b| {
+        a.centroid.get(axis).total_cmp(&b.centroid.get(axis))
+    });
+    Split { mid, axis }
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    count: usize,
+    bbox: Option<Aabb>,
+}
+
+impl Bin {
+    const EMPTY: Bin = Bin {
+        count: 0,
+        bbox: None,
+    };
+
+    fn add(&mut self, bbox: &Aabb) {
+        self.count += 1;
+        self.bbox = Some(match self.bbox {
+            None => *bbox,
+            Some(b) => Aabb::surrounding_box(&b, bbox),
+        });
+    }
+
+    fn merge(a: Bin, b: Bin) -> Bin {
+        Bin {
+            count: a.count + b.count,
+            bbox: match (a.bbox, b.bbox) {
+                (Some(a), Some(b)) => Some(Aabb::surrounding_box(&a, &b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+
+    fn cost(&self) -> f64 {
+        self.bbox.map(|b| b.surface_area()).unwrap_or(0.0) * self.count as f64
+    }
+}
+
+fn bin_index(centroid: f64, min: f64, extent: f64, bins: usize) -> usize {
+    (((centroid - min) / extent * bins as f64) as usize).min(bins - 1)
+}
+
+fn fill_bins(infos: &[PrimitiveInfo], axis: i32, min: f64, extent: f64, bins: usize) -> Vec<Bin> {
+    let mut binned = vec![Bin::EMPTY; bins];
+    for info in infos {
+        binned[bin_index(info.centroid.get(axis), min, extent, bins)].add(&info.bbox);
+    }
+    binned
+}
+
+/// Bins `infos` along `axis`. Merging takes the min and max of the boxes, so the chunks
+/// can be combined in any grouping and still give the serial result.
+fn bin_axis(
+    infos: &[PrimitiveInfo],
+    axis: i32,
+    min: f64,
+    extent: f64,
+    bins: usize,
+    parallel: bool,
+) -> Vec<Bin> {
+    if !parallel {
+        return fill_bins(infos, axis, min, extent, bins);
+    }
+    infos
+        .par_chunks(BIN_CHUNK_SIZE)
+        .map(|chunk| fill_bins(chunk, axis, min, extent, bins))
+        .reduce(
+            || vec![Bin::EMPTY; bins],
+            |a, b| {
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| Bin::merge(a, b))
+                    .collect()
+            },
+        )
+}
+
+fn sah_split(
+    infos: &mut [PrimitiveInfo],
+    centroids: &Aabb,
+    bins: usize,
+    options: &BvhOptions,
+) -> Option<Split> {
+    let n = infos.len();
+    let parent_area = bounds(infos).map(|b| b.surface_area()).unwrap_or(0.0);
+    if parent_area <= 0.0 {
+        return if n <= options.max_leaf_size {
+            None
+        } else {
+            Some(median_split(infos, widest_axis(centroids)))
+        };
+    }
+
+    let parallel = options.parallel && n >= PARALLEL_THRESHOLD;
+    let binned_axes = |axis: i32| {
+        let min = centroids.min.get(axis);
+        let extent = centroids.max.get(axis) - min;
+        (extent > 0.0).then(|| bin_axis(infos, axis, min, extent, bins, parallel))
+    };
+    let binned_axes: Vec<Option<Vec<Bin>>> = if parallel {
+        (0..3).into_par_iter().map(binned_axes).collect()
+    } else {
+        (0..3).map(binned_axes).collect()
+    };
+
+    let mut best: Option<(f64, i32, usize)> = None;
+    for (axis, binned) in (0..3).zip(binned_axes) {
+        let Some(binned) = binned else {
+            continue;
+        };
+
+        // Sweep from the right so each candidate plane sees the merged bins on both sides.
+        let mut right_costs = vec![0.0; bins];
+        let mut right = Bin::EMPTY;
+        for i in (1..bins).rev() {
+            right = Bin::merge(right, binned[i]);
+            right_costs[i] = right.cost();
+        }
+        let mut left = Bin::EMPTY;
+        for i in 0..bins - 1 {
+            left = Bin::merge(left, binned[i]);
+            if left.count == 0 || left.count == n {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + (left.cost() + right_costs[i + 1]) / parent_area;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    let leaf_cost = n as f64;
+    let (cost, axis, bin) = match best {
+        Some(best) => best,
+        // All primitives share a bin on every axis, fall back to the median.
+        None if n > options.max_leaf_size => {
+            return Some(median_split(infos, widest_axis(centroids)))
+        }
+        None => return None,
+    };
+    if n <= options.max_leaf_size && leaf_cost <= cost {
+        return None;
+    }
+
+    let min = centroids.min.get(axis);
+    let extent = centroids.max.get(axis) - min;
+    let mut mid = 0;
+    for i in 0..n {
+        if bin_index(infos[i].centroid.get(axis), min, extent, bins) <= bin {
+            infos.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some(Split { mid, axis })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        color::Color,
+        materials::lambertian::Lambertian,
+        random_f64,
+        ray::Ray,
+        shapes::{
+            bvh_node::BvhNode, hittable_list::HittableList, linear_bvh::LinearBvh, sphere::Sphere,
+        },
+        vec3::Vec3,
+    };
+
+    use super::*;
+
+    fn infos(centroids: &[f64]) -> Vec<PrimitiveInfo> {
+        centroids
+            .iter()
+            .enumerate()
+            .map(|(index, &x)| {
+                let centroid = Point3::new(x, 0.0, 0.0);
+                let half = Vec3::new(0.1, 0.1, 0.1);
+                PrimitiveInfo {
+                    index,
+                    bbox: Aabb::new(centroid - half, centroid + half),
+                    centroid,
+                }
+            })
+            .collect()
+    }
+
+    fn sah(max_leaf_size: usize) -> BvhOptions {
+        BvhOptions {
+            split: SplitMethod::Sah { bins: 16 },
+            max_leaf_size,
+            parallel: false,
+        }
+    }
+
+    #[test]
+    fn sah_splits_between_clusters() {
+        let mut infos = infos(&[0.0, 10.0, 0.2, 10.2, 0.4, 10.4, 0.6, 10.6]);
+        let split = split(&mut infos, &sah(4)).unwrap();
+        assert_eq!((split.mid, split.axis), (4, 0));
+        assert!(infos[..4].iter().all(|info| info.centroid.x < 1.0));
+        assert!(infos[4..].iter().all(|info| info.centroid.x > 9.0));
+    }
+
+    #[test]
+    fn sah_keeps_cheap_leaves() {
+        // Four overlapping boxes cost more to split than to intersect.
+        let mut infos = infos(&[0.0, 0.001, 0.002, 0.003]);
+        assert!(split(&mut infos, &sah(4)).is_none());
+        // Over the leaf size they're split anyway.
+        assert!(split(&mut infos, &sah(2)).is_some());
+    }
+
+    #[test]
+    fn splits_coincident_centroids_over_the_leaf_size() {
+        let mut infos = infos(&[1.0; 6]);
+        assert!(split(&mut infos, &sah(8)).is_none());
+        assert_eq!(split(&mut infos, &sah(4)).unwrap().mid, 3);
+    }
+
+    #[test]
+    fn builds_trees_that_hit_like_a_list() {
+        let material = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::default();
+        for _ in 0..200 {
+            let center = Point3::new(random_f64(), random_f64(), random_f64()) * 20.0;
+            list.add(Arc::new(Sphere::new(
+                center,
+                0.2 + random_f64(),
+                material.clone(),
+            )));
+        }
+
+        for split in [SplitMethod::Median, SplitMethod::Sah { bins: 8 }] {
+            let options = BvhOptions {
+                split,
+                max_leaf_size: 3,
+                parallel: true,
+            };
+            let bvh = BvhNode::from_list_with_options(&list, 0.0, 1.0, options);
+            for _ in 0..500 {
+                let origin = Point3::new(random_f64(), random_f64(), random_f64()) * 30.0;
+                let target = Point3::new(random_f64(), random_f64(), random_f64()) * 20.0;
+                let r = Ray::new(origin, target - origin, 0.0);
+                let expected = list.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+                let found = bvh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+                assert_eq!(found, expected, "{:?}", split);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_builds_match_serial_ones() {
+        let material = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::default();
+        for _ in 0..3 * PARALLEL_THRESHOLD {
+            let center = Point3::new(random_f64(), random_f64(), random_f64()) * 100.0;
+            list.add(Arc::new(Sphere::new(
+                center,
+                random_f64(),
+                material.clone(),
+            )));
+        }
+
+        let build = |parallel| {
+            let options = BvhOptions {
+                parallel,
+                ..BvhOptions::default()
+            };
+            LinearBvh::from_list(&list, 0.0, 1.0, options)
+        };
+        let serial = build(false);
+        let parallel = build(true);
+        assert_eq!(serial.node_count(), parallel.node_count());
+        assert_eq!(serial.sah_cost(), parallel.sah_cost());
+        assert!(serial
+            .primitives()
+            .iter()
+            .zip(parallel.primitives())
+            .all(|(a, b)| Arc::ptr_eq(a, b)));
+    }
+}
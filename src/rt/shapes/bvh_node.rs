@@ -1,8 +1,16 @@
 use std::{cmp::Ordering, sync::Arc};
 
-use crate::rt::{random_i32_between, ray::Ray};
+use fastrand::Rng;
 
-use super::{aabb::Aabb, hit_record::HitRecord, hittable_list::HittableList, Hittable};
+use crate::rt::{ray::Ray, vec3::Vec3, Point3};
+
+use super::{
+    aabb::Aabb,
+    bvh_build::{self, BvhOptions, PrimitiveInfo},
+    hit_record::HitRecord,
+    hittable_list::HittableList,
+    Hittable, LightParts,
+};
 
 pub struct BvhNode {
     left: Option<Arc<dyn Hittable>>,
@@ -12,7 +20,17 @@ pub struct BvhNode {
 
 impl BvhNode {
     pub fn from_slice(objects: &mut [Arc<dyn Hittable>], time0: f64, time1: f64) -> BvhNode {
-        let axis = random_i32_between(0, 2);
+        let mut rng = Rng::with_seed(fastrand::u64(..));
+        BvhNode::from_slice_with_rng(objects, time0, time1, &mut rng)
+    }
+
+    fn from_slice_with_rng(
+        objects: &mut [Arc<dyn Hittable>],
+        time0: f64,
+        time1: f64,
+        rng: &mut Rng,
+    ) -> BvhNode {
+        let axis = rng.i32(0..=2);
         let comparator = match axis {
             0 => BvhNode::box_x_compare,
             1 => BvhNode::box_y_compare,
@@ -36,11 +54,20 @@ impl BvhNode {
             Some((head, [tail])) => (Some(tail.clone()), Some(head.clone())),
             _ => {
                 objects.sort_by(comparator_ordering);
+                let parallel = objects.len() >= bvh_build::PARALLEL_THRESHOLD;
                 let mid = objects.len() / 2;
-                let left: Arc<dyn Hittable> =
-                    Arc::new(BvhNode::from_slice(&mut objects[0..mid], time0, time1));
-                let right: Arc<dyn Hittable> =
-                    Arc::new(BvhNode::from_slice(&mut objects[mid..], time0, time1));
+                let (left_objects, right_objects) = objects.split_at_mut(mid);
+                // Seed both halves before forking, so the axes don't depend on which thread
+                // builds which subtree.
+                let mut left_rng = Rng::with_seed(rng.u64(..));
+                let mut right_rng = Rng::with_seed(rng.u64(..));
+                let (left, right) = bvh_build::join(
+                    parallel,
+                    || BvhNode::from_slice_with_rng(left_objects, time0, time1, &mut left_rng),
+                    || BvhNode::from_slice_with_rng(right_objects, time0, time1, &mut right_rng),
+                );
+                let left: Arc<dyn Hittable> = Arc::new(left);
+                let right: Arc<dyn Hittable> = Arc::new(right);
                 (Some(left), Some(right))
             }
         };
@@ -67,6 +94,62 @@ impl BvhNode {
         BvhNode::from_slice(&mut list.objects[..], time0, time1)
     }
 
+    /// Builds the tree with a deterministic split strategy instead of the random axis used
+    /// by `from_slice`.
+    pub fn with_options(
+        objects: &[Arc<dyn Hittable>],
+        time0: f64,
+        time1: f64,
+        options: BvhOptions,
+    ) -> BvhNode {
+        let mut infos = bvh_build::primitive_infos(objects, time0, time1, options.parallel);
+        BvhNode::build(objects, &mut infos, &options)
+    }
+
+    pub fn from_list_with_options(
+        list: &HittableList,
+        time0: f64,
+        time1: f64,
+        options: BvhOptions,
+    ) -> BvhNode {
+        BvhNode::with_options(&list.objects, time0, time1, options)
+    }
+
+    fn build(
+        objects: &[Arc<dyn Hittable>],
+        infos: &mut [PrimitiveInfo],
+        options: &BvhOptions,
+    ) -> BvhNode {
+        let bbox = bvh_build::bounds(infos);
+        let (left, right) = match bvh_build::split(infos, options) {
+            Some(split) => {
+                let parallel = options.parallel && infos.len() >= bvh_build::PARALLEL_THRESHOLD;
+                let (left_infos, right_infos) = infos.split_at_mut(split.mid);
+                let (left, right) = bvh_build::join(
+                    parallel,
+                    || BvhNode::build(objects, left_infos, options),
+                    || BvhNode::build(objects, right_infos, options),
+                );
+                let left: Arc<dyn Hittable> = Arc::new(left);
+                let right: Arc<dyn Hittable> = Arc::new(right);
+                (Some(left), Some(right))
+            }
+            None => {
+                let mut leaf = infos.iter().map(|info| objects[info.index].clone());
+                match infos.len() {
+                    0..=2 => (leaf.next(), leaf.next()),
+                    _ => {
+                        let list: Arc<dyn Hittable> =
+                            Arc::new(HittableList::new_from_objects(leaf.collect()));
+                        (Some(list), None)
+                    }
+                }
+            }
+        };
+
+        Self { left, right, bbox }
+    }
+
     fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: i32) -> bool {
         let box_a = a
             .bounding_box(0.0, 0.0)
@@ -91,7 +174,8 @@ impl BvhNode {
 }
 
 impl Hittable for BvhNode {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        bvh_build::count_node_visit();
         if !self.bbox.map(|b| b.hit(r, t_min, t_max)).unwrap_or(false) {
             return None;
         }
@@ -108,4 +192,25 @@ impl Hittable for BvhNode {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         self.bbox
     }
+
+    /// The density of the primitive `v` hits first, for trees over the parts of a single
+    /// light that each know the density of the whole, like the triangles of a mesh.
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        let r = Ray::new(o, v, time);
+        let hit_left = self
+            .left
+            .as_ref()
+            .and_then(|h| h.hit(&r, 0.001, f64::INFINITY));
+        let t_max = hit_left.as_ref().map(|h| h.t).unwrap_or(f64::INFINITY);
+        let first = match &self.right {
+            Some(right) if right.hit(&r, 0.001, t_max).is_some() => right,
+            _ if hit_left.is_some() => self.left.as_ref().expect("the left child was hit"),
+            _ => return 0.0,
+        };
+        first.pdf_value(o, v, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::collect(self.left.iter().chain(&self.right))
+    }
 }
@@ -32,7 +32,7 @@ impl ConstantVolume {
 }
 
 impl Hittable for ConstantVolume {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let enable_debug = false;
         let debugging = enable_debug && random_f64() < 0.00001;
 
@@ -85,6 +85,8 @@ impl Hittable for ConstantVolume {
             material: &self.phase_function, // also arbitrary
             u: 0.0,
             v: 0.0,
+            vertex_color: None,
+            barycentrics: None,
         })
     }
 
@@ -1,8 +1,8 @@
 use std::sync::Arc;
 
-use crate::rt::ray::Ray;
+use crate::rt::{ray::Ray, vec3::Vec3, Point3};
 
-use super::{aabb::Aabb, hit_record::HitRecord, Hittable};
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
 
 pub struct FlipFace {
     hittable: Arc<dyn Hittable>,
@@ -15,7 +15,7 @@ impl FlipFace {
 }
 
 impl Hittable for FlipFace {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut hit = self.hittable.hit(r, t_min, t_max)?;
         hit.front_face = !hit.front_face;
         Some(hit)
@@ -24,4 +24,18 @@ impl Hittable for FlipFace {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.hittable.bounding_box(time0, time1)
     }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        self.hittable.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        self.hittable.random(o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        self.hittable
+            .light_parts()
+            .wrap(|part| Arc::new(FlipFace::new(part)))
+    }
 }
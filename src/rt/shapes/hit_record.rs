@@ -1,5 +1,6 @@
-use crate::rt::{materials::Material, ray::Ray, vec3::Vec3, Point3};
+use crate::rt::{color::Color, materials::Material, ray::Ray, vec3::Vec3, Point3};
 
+#[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub p: Point3,
     pub normal: Vec3,
@@ -8,6 +9,9 @@ pub struct HitRecord<'a> {
     pub v: f64,
     pub front_face: bool,
     pub material: &'a dyn Material,
+    pub vertex_color: Option<Color>,
+    /// Barycentric weights of the second and third vertex, for hits on triangles.
+    pub barycentrics: Option<(f64, f64)>,
 }
 
 impl<'a> HitRecord<'a> {
@@ -29,6 +33,8 @@ impl<'a> HitRecord<'a> {
             v,
             front_face,
             material,
+            vertex_color: None,
+            barycentrics: None,
         }
     }
 
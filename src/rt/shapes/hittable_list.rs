@@ -1,8 +1,13 @@
 use std::sync::Arc;
 
-use crate::rt::{random_i32_between, ray::Ray, vec3::Vec3, Point3};
+use crate::rt::{
+    random_i32_between,
+    ray::Ray,
+    vec3::{self, Vec3},
+    Point3,
+};
 
-use super::{aabb::Aabb, HitRecord, Hittable};
+use super::{aabb::Aabb, HitRecord, Hittable, LightParts};
 
 #[derive(Default)]
 pub struct HittableList {
@@ -27,21 +32,34 @@ impl HittableList {
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object)
     }
-}
 
-impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    /// Like `hit`, with the index of the object that was hit.
+    pub fn closest_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(usize, HitRecord<'_>)> {
         let mut closest_hit = None;
         let mut closest_so_far = t_max;
 
-        for object in self.objects.iter() {
+        for (i, object) in self.objects.iter().enumerate() {
             if let Some(hit) = object.hit(r, t_min, closest_so_far) {
                 closest_so_far = hit.t;
-                closest_hit = Some(hit);
+                closest_hit = Some((i, hit));
             }
         }
 
-        return closest_hit;
+        closest_hit
+    }
+
+    /// Walks the list for the parts to importance sample, see `Hittable::light_parts`.
+    pub fn lights(&self) -> HittableList {
+        match self.light_parts() {
+            LightParts::Some(parts) => HittableList::new_from_objects(parts),
+            LightParts::All | LightParts::None => HittableList::default(),
+        }
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(r, t_min, t_max).map(|(_, hit)| hit)
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
@@ -51,19 +69,121 @@ impl Hittable for HittableList {
             let tail_box = object.bounding_box(time0, time1)?;
             output_box = Aabb::surrounding_box(&output_box, &tail_box);
         }
-        return Some(output_box);
+        Some(output_box)
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
         let weight = 1.0 / self.objects.len() as f64;
         self.objects
             .iter()
-            .map(|obj| weight * obj.pdf_value(o, v))
+            .map(|obj| weight * obj.pdf_value(o, v, time))
             .sum()
     }
 
-    fn random(&self, o: Point3) -> Vec3 {
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        if self.objects.is_empty() {
+            return vec3::UNIT_X;
+        }
         let index = random_i32_between(0, self.objects.len() as i32 - 1) as usize;
-        self.objects[index].random(o)
+        self.objects[index].random(o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::collect(&self.objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        color::Color,
+        mat4::Mat4,
+        materials::Material,
+        materials::{dielectric::Dielectric, diffuse_light::DiffuseLight, lambertian::Lambertian},
+        shapes::{
+            bbox::Bbox, bvh_build::BvhOptions, bvh_node::BvhNode, flip_face::FlipFace,
+            importance_target::ImportanceTarget, instance::Instance, linear_bvh::LinearBvh,
+            sphere::Sphere, transform::Transform, xz_rect::XzRect,
+        },
+    };
+
+    use super::*;
+
+    fn light() -> Arc<dyn Material> {
+        Arc::new(DiffuseLight::from_color(Color::new(15.0, 15.0, 15.0)))
+    }
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn collects_emissive_and_marked_objects() {
+        let ceiling: Arc<dyn Hittable> =
+            Arc::new(XzRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light()));
+        let glass: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            Point3::new(190.0, 90.0, 190.0),
+            90.0,
+            Arc::new(Dielectric::new(1.5)),
+        ));
+        let mut world = HittableList::default();
+        world.add(Arc::new(FlipFace::new(ceiling)));
+        world.add(Arc::new(XzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, gray())));
+        world.add(Arc::new(Bbox::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            gray(),
+        )));
+        world.add(glass.clone());
+        assert_eq!(world.lights().objects.len(), 1);
+
+        world.objects[3] = Arc::new(ImportanceTarget::new(glass));
+        let lights = world.lights();
+        assert_eq!(lights.objects.len(), 2);
+
+        // Pointing straight up at the ceiling light from the floor.
+        let o = Point3::new(278.0, 0.0, 278.0);
+        assert!(lights.pdf_value(o, Vec3::new(0.0, 1.0, 0.0), 0.0) > 0.0);
+    }
+
+    #[test]
+    fn finds_lights_inside_acceleration_structures_and_transforms() {
+        let mut blas = HittableList::default();
+        blas.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            gray(),
+        )));
+        blas.add(Arc::new(Sphere::new(
+            Point3::new(3.0, 0.0, 0.0),
+            1.0,
+            light(),
+        )));
+        let blas: Arc<dyn Hittable> =
+            Arc::new(LinearBvh::from_list(&blas, 0.0, 1.0, BvhOptions::default()));
+        let offset = Vec3::new(0.0, 10.0, 0.0);
+
+        let world = HittableList::new_from_objects(vec![
+            Arc::new(Transform::new(blas.clone(), Mat4::translation(offset)).unwrap()),
+            Arc::new(
+                Instance::with_material(blas.clone(), Mat4::translation(offset), gray()).unwrap(),
+            ),
+            Arc::new(BvhNode::from_list(
+                &mut HittableList::new_from_object(blas),
+                0.0,
+                1.0,
+            )),
+        ]);
+        let lights = world.lights();
+        assert_eq!(lights.objects.len(), 2);
+
+        // Only the emissive sphere is sampled, at its transformed position.
+        let bbox = lights.objects[0].bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.centroid() - Point3::new(3.0, 10.0, 0.0)).length() < 1e-9);
+        let bbox = lights.objects[1].bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.centroid() - Point3::new(3.0, 0.0, 0.0)).length() < 1e-9);
     }
 }
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use crate::rt::{ray::Ray, vec3::Vec3, Point3};
+
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
+
+/// Marks a hittable without an emissive material, like a glass sphere focusing light into
+/// caustics, for importance sampling alongside the lights found by `HittableList::lights`.
+pub struct ImportanceTarget {
+    hittable: Arc<dyn Hittable>,
+}
+
+impl ImportanceTarget {
+    pub fn new(hittable: Arc<dyn Hittable>) -> ImportanceTarget {
+        ImportanceTarget { hittable }
+    }
+}
+
+impl Hittable for ImportanceTarget {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.hittable.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.hittable.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        self.hittable.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        self.hittable.random(o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::All
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use crate::rt::{mat4::Mat4, materials::Material, ray::Ray, vec3::Vec3, Point3};
+
+use super::{aabb::Aabb, hit_record::HitRecord, transform::Transform, Hittable, LightParts};
+
+/// One placement of a shared bottom-level structure, usually a `LinearBvh` or a
+/// `TriangleMesh` built once. Put the instances in a `LinearBvh` of their own to get the
+/// top level, so memory grows with the number of instances rather than their geometry.
+pub struct Instance {
+    transform: Transform,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    /// Returns `None` when `matrix` is singular.
+    pub fn new(blas: Arc<dyn Hittable>, matrix: Mat4) -> Option<Instance> {
+        Some(Instance {
+            transform: Transform::new(blas, matrix)?,
+            material: None,
+        })
+    }
+
+    /// Renders every primitive of the instance with `material` instead of its own.
+    pub fn with_material(
+        blas: Arc<dyn Hittable>,
+        matrix: Mat4,
+        material: Arc<dyn Material>,
+    ) -> Option<Instance> {
+        Some(Instance {
+            transform: Transform::new(blas, matrix)?,
+            material: Some(material),
+        })
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        self.transform.matrix()
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec = self.transform.hit(r, t_min, t_max)?;
+        if let Some(material) = &self.material {
+            rec.material = material.as_ref();
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.transform.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        self.transform.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        self.transform.random(o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        match &self.material {
+            // The override replaces the material of every primitive.
+            Some(material) => LightParts::of_material(material.as_ref()),
+            None => self.transform.light_parts(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        color::{self, Color},
+        mat4,
+        materials::{diffuse_light::DiffuseLight, lambertian::Lambertian},
+        shapes::sphere::Sphere,
+    };
+
+    use super::*;
+
+    fn sphere(material: Arc<dyn Material>) -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material))
+    }
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn light() -> Arc<dyn Material> {
+        Arc::new(DiffuseLight::from_color(Color::new(4.0, 4.0, 4.0)))
+    }
+
+    #[test]
+    fn hits_the_blas_where_the_matrix_places_it() {
+        let matrix =
+            Mat4::translation(Vec3::new(5.0, 0.0, 0.0)) * Mat4::scaling(Vec3::new(2.0, 2.0, 2.0));
+        let instance = Instance::new(sphere(gray()), matrix).unwrap();
+
+        let ray = Ray::new(Point3::new(5.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let rec = instance.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 8.0).abs() < 1e-9);
+        assert!((rec.p - Point3::new(5.0, 0.0, -2.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-9);
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(instance.hit(&ray, 0.001, f64::INFINITY).is_none());
+
+        let bbox = instance.bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.min - Point3::new(3.0, -2.0, -2.0)).length() < 1e-9);
+        assert!((bbox.max - Point3::new(7.0, 2.0, 2.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn applies_the_material_override() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let plain = Instance::new(sphere(gray()), mat4::IDENTITY).unwrap();
+        let rec = plain.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let emitted = rec.material.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+        assert_eq!(emitted, color::BLACK);
+
+        let lit = Instance::with_material(sphere(gray()), mat4::IDENTITY, light()).unwrap();
+        let rec = lit.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let emitted = rec.material.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+        assert_eq!(emitted, Color::new(4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn instances_share_one_blas() {
+        let blas = sphere(gray());
+        let instances: Vec<Instance> = (0..100)
+            .map(|i| {
+                let offset = Vec3::new(3.0 * i as f64, 0.0, 0.0);
+                Instance::new(blas.clone(), Mat4::translation(offset)).unwrap()
+            })
+            .collect();
+        assert_eq!(Arc::strong_count(&blas), 101);
+
+        let ray = Ray::new(
+            Point3::new(297.0, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        assert!(instances[99].hit(&ray, 0.001, f64::INFINITY).is_some());
+        assert!(instances[98].hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+}
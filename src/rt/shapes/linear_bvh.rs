@@ -0,0 +1,449 @@
+use std::sync::Arc;
+
+use crate::rt::{
+    random_i32_between,
+    ray::Ray,
+    vec3::{self, Vec3},
+    Point3,
+};
+
+use super::{
+    aabb::Aabb,
+    bvh_build::{self, BvhOptions, PrimitiveInfo},
+    hit_record::HitRecord,
+    hittable_list::HittableList,
+    Hittable, LightParts,
+};
+
+/// Deepest level a node can sit at. Subtrees that would go deeper become leaves, which
+/// keeps the traversal stack a fixed size.
+const MAX_DEPTH: usize = 64;
+
+/// `update` rebuilds from scratch once refitting has made the tree this much more expensive
+/// than it was right after its last build.
+const REBUILD_COST_RATIO: f64 = 1.5;
+
+#[derive(Copy, Clone)]
+struct LinearNode {
+    bbox: Aabb,
+    /// First primitive of a leaf, or the second child of an interior node. The first child
+    /// of an interior node is stored right after it.
+    offset: u32,
+    /// Number of primitives in a leaf, zero for interior nodes.
+    count: u32,
+    /// Axis the children were split along.
+    axis: u8,
+}
+
+/// BVH flattened into a depth-first array of nodes, with the primitives reordered so every
+/// leaf owns a contiguous range of them.
+pub struct LinearBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<dyn Hittable>>,
+    /// Index in the input of every entry of `primitives`, so a refit can pick up a new list.
+    order: Vec<usize>,
+    options: BvhOptions,
+    build_cost: f64,
+}
+
+impl LinearBvh {
+    pub fn new(
+        objects: &[Arc<dyn Hittable>],
+        time0: f64,
+        time1: f64,
+        options: BvhOptions,
+    ) -> LinearBvh {
+        let mut infos = bvh_build::primitive_infos(objects, time0, time1, options.parallel);
+        let mut tree = Subtree {
+            nodes: Vec::with_capacity(2 * objects.len()),
+            order: Vec::with_capacity(objects.len()),
+        };
+        if !infos.is_empty() {
+            tree.build(&mut infos, &options, 0);
+        }
+        let mut bvh = LinearBvh {
+            nodes: tree.nodes,
+            primitives: tree.order.iter().map(|&i| objects[i].clone()).collect(),
+            order: tree.order,
+            options,
+            build_cost: 0.0,
+        };
+        bvh.build_cost = bvh.sah_cost();
+        bvh
+    }
+
+    pub fn from_list(
+        list: &HittableList,
+        time0: f64,
+        time1: f64,
+        options: BvhOptions,
+    ) -> LinearBvh {
+        LinearBvh::new(&list.objects, time0, time1, options)
+    }
+
+    pub fn primitives(&self) -> &[Arc<dyn Hittable>] {
+        &self.primitives
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Recomputes every bounding box bottom-up, keeping the topology. `objects` replaces
+    /// the primitives and must hold the moved versions of the ones the tree was built from,
+    /// in the same order.
+    pub fn refit(&mut self, objects: &[Arc<dyn Hittable>], time0: f64, time1: f64) {
+        assert_eq!(
+            objects.len(),
+            self.order.len(),
+            "refit needs as many objects as the tree was built with"
+        );
+        for (primitive, &index) in self.primitives.iter_mut().zip(&self.order) {
+            *primitive = objects[index].clone();
+        }
+
+        // Children always come after their parent, so walking backwards sees them first.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].bbox = if node.count > 0 {
+                let first = node.offset as usize;
+                self.primitives[first..first + node.count as usize]
+                    .iter()
+                    .map(|object| {
+                        object
+                            .bounding_box(time0, time1)
+                            .expect("bvh primitives need a bounding box")
+                    })
+                    .reduce(|a, b| Aabb::surrounding_box(&a, &b))
+                    .expect("bvh nodes can't be empty")
+            } else {
+                Aabb::surrounding_box(
+                    &self.nodes[i + 1].bbox,
+                    &self.nodes[node.offset as usize].bbox,
+                )
+            };
+        }
+    }
+
+    /// Refits to `objects`, then rebuilds the tree if the refit degraded it too much.
+    /// Returns whether it was rebuilt.
+    pub fn update(&mut self, objects: &[Arc<dyn Hittable>], time0: f64, time1: f64) -> bool {
+        self.refit(objects, time0, time1);
+        if self.sah_cost() <= REBUILD_COST_RATIO * self.build_cost {
+            return false;
+        }
+        *self = LinearBvh::new(objects, time0, time1, self.options);
+        true
+    }
+
+    /// Expected cost of tracing a ray that hits the root box, in units of primitive
+    /// intersections.
+    pub fn sah_cost(&self) -> f64 {
+        let root_area = match self.nodes.first() {
+            Some(root) => root.bbox.surface_area(),
+            None => return 0.0,
+        };
+        if root_area <= 0.0 {
+            return self.primitives.len() as f64;
+        }
+        self.nodes
+            .iter()
+            .map(|node| {
+                let cost = if node.count > 0 {
+                    node.count as f64
+                } else {
+                    bvh_build::TRAVERSAL_COST
+                };
+                cost * node.bbox.surface_area() / root_area
+            })
+            .sum()
+    }
+}
+
+/// Nodes and primitive order of a tree under construction, with offsets relative to its
+/// own vectors until it is spliced into its parent's.
+struct Subtree {
+    nodes: Vec<LinearNode>,
+    order: Vec<usize>,
+}
+
+impl Subtree {
+    fn build(&mut self, infos: &mut [PrimitiveInfo], options: &BvhOptions, depth: usize) {
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bbox: bvh_build::bounds(infos).expect("bvh nodes can't be empty"),
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        let split = if depth + 1 < MAX_DEPTH {
+            bvh_build::split(infos, options)
+        } else {
+            None
+        };
+        match split {
+            Some(split) => {
+                let parallel = options.parallel && infos.len() >= bvh_build::PARALLEL_THRESHOLD;
+                let (left, right) = infos.split_at_mut(split.mid);
+                let second = if parallel {
+                    // The first child goes straight after this node while the second is
+                    // built on its own and appended, the same layout a serial build gives.
+                    let mut second = Subtree {
+                        nodes: Vec::with_capacity(2 * right.len()),
+                        order: Vec::with_capacity(right.len()),
+                    };
+                    bvh_build::join(
+                        true,
+                        || self.build(left, options, depth + 1),
+                        || second.build(right, options, depth + 1),
+                    );
+                    self.append(second)
+                } else {
+                    self.build(left, options, depth + 1);
+                    let second = self.nodes.len();
+                    self.build(right, options, depth + 1);
+                    second
+                };
+                self.nodes[index].offset = second as u32;
+                self.nodes[index].axis = split.axis as u8;
+            }
+            None => {
+                self.nodes[index].offset = self.order.len() as u32;
+                self.nodes[index].count = infos.len() as u32;
+                self.order.extend(infos.iter().map(|info| info.index));
+            }
+        }
+    }
+
+    /// Moves `other` to the end of this tree, returning the index its root lands at.
+    fn append(&mut self, other: Subtree) -> usize {
+        let first_node = self.nodes.len() as u32;
+        let first_primitive = self.order.len() as u32;
+        self.nodes.extend(other.nodes.into_iter().map(|mut node| {
+            node.offset += if node.count > 0 {
+                first_primitive
+            } else {
+                first_node
+            };
+            node
+        }));
+        self.order.extend(other.order);
+        first_node as usize
+    }
+}
+
+impl LinearBvh {
+    /// The closest hit along `r`, with the primitive it's on.
+    pub fn closest_hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(&Arc<dyn Hittable>, HitRecord<'_>)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_direction = Vec3::new(
+            1.0 / r.direction.x,
+            1.0 / r.direction.y,
+            1.0 / r.direction.z,
+        );
+        let mut closest_hit = None;
+        let mut closest_so_far = t_max;
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            bvh_build::count_node_visit();
+            if node
+                .bbox
+                .hit_inverse(r, inv_direction, t_min, closest_so_far)
+            {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for object in &self.primitives[first..first + node.count as usize] {
+                        if let Some(hit) = object.hit(r, t_min, closest_so_far) {
+                            closest_so_far = hit.t;
+                            closest_hit = Some((object, hit));
+                        }
+                    }
+                } else {
+                    // Visit the child on the ray's side of the split first, the farther
+                    // one can often be culled by the closer hit.
+                    let (near, far) = if inv_direction.get(node.axis as i32) < 0.0 {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_size] = far;
+                    stack_size += 1;
+                    current = near;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+
+        closest_hit
+    }
+}
+
+impl Hittable for LinearBvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.closest_hit(r, t_min, t_max).map(|(_, rec)| rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bbox)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        if self.primitives.is_empty() {
+            return 0.0;
+        }
+        let weight = 1.0 / self.primitives.len() as f64;
+        self.primitives
+            .iter()
+            .map(|obj| weight * obj.pdf_value(o, v, time))
+            .sum()
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        if self.primitives.is_empty() {
+            return vec3::UNIT_X;
+        }
+        let index = random_i32_between(0, self.primitives.len() as i32 - 1) as usize;
+        self.primitives[index].random(o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::collect(&self.primitives)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        color::Color,
+        materials::lambertian::Lambertian,
+        random_f64,
+        shapes::{bvh_node::BvhNode, sphere::Sphere, translate::Translate},
+    };
+
+    use super::*;
+
+    fn random_point(scale: f64) -> Point3 {
+        Point3::new(random_f64(), random_f64(), random_f64()) * scale
+    }
+
+    fn spheres(count: usize) -> HittableList {
+        let material = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::default();
+        for _ in 0..count {
+            let radius = 0.2 + random_f64();
+            list.add(Arc::new(Sphere::new(
+                random_point(20.0),
+                radius,
+                material.clone(),
+            )));
+        }
+        list
+    }
+
+    fn random_ray() -> Ray {
+        let origin = random_point(30.0);
+        Ray::new(origin, random_point(20.0) - origin, 0.0)
+    }
+
+    #[test]
+    fn hits_like_bvh_node() {
+        let mut list = spheres(300);
+        let options = BvhOptions::default();
+        let linear = LinearBvh::from_list(&list, 0.0, 1.0, options);
+        let node = BvhNode::from_list_with_options(&list, 0.0, 1.0, options);
+        let random_node = BvhNode::from_list(&mut list, 0.0, 1.0);
+        assert_eq!(linear.primitives().len(), 300);
+        for _ in 0..1000 {
+            let r = random_ray();
+            let expected = node.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(
+                linear.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                expected
+            );
+            assert_eq!(
+                random_node.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                expected
+            );
+            if let Some((object, rec)) = linear.closest_hit(&r, 0.001, f64::INFINITY) {
+                let own = object.hit(&r, 0.001, f64::INFINITY).unwrap();
+                assert_eq!(own.t, rec.t);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_trees_miss() {
+        let bvh = LinearBvh::new(&[], 0.0, 1.0, BvhOptions::default());
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(bvh.node_count(), 0);
+        assert!(bvh.hit(&r, 0.001, f64::INFINITY).is_none());
+        assert!(bvh.bounding_box(0.0, 1.0).is_none());
+        assert_eq!(bvh.sah_cost(), 0.0);
+        assert_eq!(bvh.pdf_value(r.origin, r.direction, 0.0), 0.0);
+        assert_eq!(bvh.random(r.origin, 0.0), vec3::UNIT_X);
+    }
+
+    fn moved(list: &HittableList, offset: impl Fn(usize) -> Vec3) -> Vec<Arc<dyn Hittable>> {
+        list.objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| -> Arc<dyn Hittable> {
+                Arc::new(Translate::new(object.clone(), offset(i)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refits_to_moved_primitives() {
+        let list = spheres(200);
+        let mut bvh = LinearBvh::from_list(&list, 0.0, 1.0, BvhOptions::default());
+        let node_count = bvh.node_count();
+        let objects = moved(&list, |i| Vec3::new(0.0, (i % 5) as f64, 0.0));
+        bvh.refit(&objects, 0.0, 1.0);
+        assert_eq!(bvh.node_count(), node_count);
+
+        let moved_list = HittableList { objects };
+        for _ in 0..1000 {
+            let r = random_ray();
+            assert_eq!(
+                bvh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                moved_list.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t)
+            );
+        }
+    }
+
+    #[test]
+    fn rebuilds_once_refitting_degrades_the_tree() {
+        let list = spheres(200);
+        let mut bvh = LinearBvh::from_list(&list, 0.0, 1.0, BvhOptions::default());
+        let nudged = moved(&list, |_| Vec3::new(0.01, 0.0, 0.0));
+        assert!(!bvh.update(&nudged, 0.0, 1.0));
+        // Scattering the spheres over eight corners stretches every box across the scene.
+        let scattered = moved(&list, |i| {
+            let corner = |bit: usize| if i & bit == 0 { 0.0 } else { 40.0 };
+            Vec3::new(corner(1), corner(2), corner(4))
+        });
+        assert!(bvh.update(&scattered, 0.0, 1.0));
+        let rebuilt = LinearBvh::new(&scattered, 0.0, 1.0, BvhOptions::default());
+        assert_eq!(bvh.sah_cost(), rebuilt.sah_cost());
+    }
+}
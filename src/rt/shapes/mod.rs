@@ -1,35 +1,99 @@
+use std::sync::Arc;
+
 use self::{aabb::Aabb, hit_record::HitRecord};
 
 use super::{
+    materials::Material,
     ray::Ray,
     vec3::{self, Vec3},
     Point3,
 };
 
 pub mod aabb;
+pub mod animated_transform;
 pub mod bbox;
+pub mod bvh_build;
 pub mod bvh_node;
 pub mod constant_volume;
 pub mod flip_face;
 pub mod hit_record;
 pub mod hittable_list;
+pub mod importance_target;
+pub mod instance;
+pub mod linear_bvh;
 pub mod mooving_sphere;
 pub mod rotate_y;
 pub mod sphere;
+pub mod transform;
 pub mod translate;
+pub mod triangle;
+pub mod triangle_mesh;
 pub mod xy_rect;
 pub mod xz_rect;
 pub mod yz_rect;
 
 pub trait Hittable: Sync + Send {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
 
-    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f64 {
+    fn pdf_value(&self, _o: Point3, _v: Vec3, _time: f64) -> f64 {
         0.0
     }
 
-    fn random(&self, _o: Point3) -> Vec3 {
+    fn random(&self, _o: Point3, _time: f64) -> Vec3 {
         vec3::UNIT_X
     }
+
+    /// The parts of this hittable to importance sample as lights: surfaces with an emissive
+    /// material and anything wrapped in an `ImportanceTarget`. Collected for a whole scene
+    /// with `HittableList::lights`.
+    fn light_parts(&self) -> LightParts {
+        LightParts::None
+    }
+}
+
+pub enum LightParts {
+    None,
+    /// The whole hittable.
+    All,
+    /// These pieces of it, already placed where the hittable puts them.
+    Some(Vec<Arc<dyn Hittable>>),
+}
+
+impl LightParts {
+    /// `All` for a primitive made of `material`.
+    pub fn of_material(material: &dyn Material) -> LightParts {
+        if material.is_emissive() {
+            LightParts::All
+        } else {
+            LightParts::None
+        }
+    }
+
+    /// Gathers the parts of a group of hittables, keeping whole the children that are
+    /// lights in their entirety.
+    pub fn collect<'a>(children: impl IntoIterator<Item = &'a Arc<dyn Hittable>>) -> LightParts {
+        let mut parts = Vec::new();
+        for child in children {
+            match child.light_parts() {
+                LightParts::None => {}
+                LightParts::All => parts.push(child.clone()),
+                LightParts::Some(children) => parts.extend(children),
+            }
+        }
+        if parts.is_empty() {
+            LightParts::None
+        } else {
+            LightParts::Some(parts)
+        }
+    }
+
+    /// Places the parts found inside a wrapper with `wrap`. A wrapper around a light in its
+    /// entirety is itself entirely a light.
+    pub fn wrap(self, wrap: impl Fn(Arc<dyn Hittable>) -> Arc<dyn Hittable>) -> LightParts {
+        match self {
+            LightParts::Some(parts) => LightParts::Some(parts.into_iter().map(wrap).collect()),
+            parts => parts,
+        }
+    }
 }
@@ -2,7 +2,11 @@ use std::sync::Arc;
 
 use crate::rt::{materials::Material, ray::Ray, vec3::Vec3, Point3};
 
-use super::{aabb::Aabb, sphere::Sphere, HitRecord, Hittable};
+use super::{
+    aabb::Aabb,
+    sphere::{self, Sphere},
+    HitRecord, Hittable, LightParts,
+};
 
 pub struct MovingSphere {
     center0: Point3,
@@ -39,7 +43,7 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.center(r.time);
         let a = r.direction.length_squared();
         let half_b = Vec3::dot(oc, r.direction);
@@ -81,4 +85,19 @@ impl Hittable for MovingSphere {
         let box1 = Aabb::new(center1 - radius, center1 + radius);
         Some(Aabb::surrounding_box(&box0, &box1))
     }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        match self.hit(&Ray::new(o, v, time), 0.001, f64::INFINITY) {
+            None => 0.0,
+            Some(_) => sphere::cone_pdf(self.center(time), self.radius, o),
+        }
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        sphere::random_in_cone(self.center(time), self.radius, o)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::of_material(self.material.as_ref())
+    }
 }
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crate::rt::{degrees_to_radians, ray::Ray, vec3::Vec3, Point3};
 
-use super::{aabb::Aabb, hit_record::HitRecord, Hittable};
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
 
 pub struct RotateY {
     hittable: Arc<dyn Hittable>,
@@ -41,7 +41,7 @@ impl RotateY {
                 }
             }
 
-            return Aabb::new(min, max);
+            Aabb::new(min, max)
         });
 
         RotateY {
@@ -51,10 +51,26 @@ impl RotateY {
             bbox,
         }
     }
+
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
 }
 
 impl Hittable for RotateY {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut origin = r.origin;
         let mut direction = r.direction;
 
@@ -108,4 +124,20 @@ impl Hittable for RotateY {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         self.bbox
     }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        self.hittable
+            .pdf_value(self.to_object(o), self.to_object(v), time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        self.to_world(self.hittable.random(self.to_object(o), time))
+    }
+
+    fn light_parts(&self) -> LightParts {
+        let angle = self.sin_theta.atan2(self.cos_theta).to_degrees();
+        self.hittable
+            .light_parts()
+            .wrap(|part| Arc::new(RotateY::new(part, angle)))
+    }
 }
@@ -4,7 +4,7 @@ use crate::rt::{
     materials::Material, onb::Onb, random_to_sphere, ray::Ray, vec3::Vec3, Point3, PI,
 };
 
-use super::{aabb::Aabb, HitRecord, Hittable};
+use super::{aabb::Aabb, HitRecord, Hittable, LightParts};
 
 pub struct Sphere {
     center: Point3,
@@ -35,7 +35,7 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.center;
         let a = r.direction.length_squared();
         let half_b = Vec3::dot(oc, r.direction);
@@ -74,22 +74,33 @@ impl Hittable for Sphere {
         Some(Aabb::new(self.center - radius, self.center + radius))
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
-        match self.hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY) {
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        match self.hit(&Ray::new(o, v, time), 0.001, f64::INFINITY) {
             None => 0.0,
-            Some(_) => {
-                let cos_theta_max =
-                    f64::sqrt(1.0 - self.radius * self.radius / (self.center - o).length_squared());
-                let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
-                1.0 / solid_angle
-            }
+            Some(_) => cone_pdf(self.center, self.radius, o),
         }
     }
 
-    fn random(&self, o: Point3) -> Vec3 {
-        let direction = self.center - o;
-        let distance_squared = direction.length_squared();
-        let uvw = Onb::build_from_w(direction);
-        uvw.local(random_to_sphere(self.radius, distance_squared))
+    fn random(&self, o: Point3, _time: f64) -> Vec3 {
+        random_in_cone(self.center, self.radius, o)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::of_material(self.material.as_ref())
     }
 }
+
+/// Density of `random_in_cone` over the directions from `o` that hit the sphere.
+pub(super) fn cone_pdf(center: Point3, radius: f64, o: Point3) -> f64 {
+    let cos_theta_max = f64::sqrt(1.0 - radius * radius / (center - o).length_squared());
+    let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+    1.0 / solid_angle
+}
+
+/// Uniform direction from `o` within the cone the sphere subtends.
+pub(super) fn random_in_cone(center: Point3, radius: f64, o: Point3) -> Vec3 {
+    let direction = center - o;
+    let distance_squared = direction.length_squared();
+    let uvw = Onb::build_from_w(direction);
+    uvw.local(random_to_sphere(radius, distance_squared))
+}
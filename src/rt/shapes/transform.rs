@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use crate::rt::{mat4::Mat4, ray::Ray, vec3::Vec3, Point3};
+
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
+
+/// Instances a hittable under an arbitrary affine matrix, e.g.
+/// `Mat4::translation(offset) * Mat4::rotation_x(30.0) * Mat4::scaling(factors)`.
+pub struct Transform {
+    hittable: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Transform {
+    /// Returns `None` when `matrix` is singular, e.g. a scale by zero.
+    pub fn new(hittable: Arc<dyn Hittable>, matrix: Mat4) -> Option<Transform> {
+        let inverse = matrix.inverse()?;
+        Some(Transform {
+            hittable,
+            matrix,
+            inverse,
+        })
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        transformed_hit(
+            self.hittable.as_ref(),
+            &self.matrix,
+            &self.inverse,
+            r,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.hittable
+            .bounding_box(time0, time1)
+            .map(|bbox| bbox.transform(&self.matrix))
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        transformed_pdf_value(self.hittable.as_ref(), &self.inverse, o, v, time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        transformed_random(self.hittable.as_ref(), &self.matrix, &self.inverse, o, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        self.hittable.light_parts().wrap(|part| {
+            Arc::new(Transform {
+                hittable: part,
+                matrix: self.matrix,
+                inverse: self.inverse,
+            })
+        })
+    }
+}
+
+pub(super) fn transformed_hit<'a>(
+    hittable: &'a dyn Hittable,
+    matrix: &Mat4,
+    inverse: &Mat4,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    // The direction is not renormalized, so t means the same thing in both spaces.
+    let local_r = Ray::new(
+        inverse.transform_point(r.origin),
+        inverse.transform_vector(r.direction),
+        r.time,
+    );
+    let mut rec = hittable.hit(&local_r, t_min, t_max)?;
+    rec.p = matrix.transform_point(rec.p);
+    // The inverse transpose keeps the normal's side relative to the ray, so front_face
+    // (including any FlipFace underneath) stays valid.
+    rec.normal = Vec3::unit_vector(inverse.transform_normal(rec.normal));
+    Some(rec)
+}
+
+pub(super) fn transformed_pdf_value(
+    hittable: &dyn Hittable,
+    inverse: &Mat4,
+    o: Point3,
+    v: Vec3,
+    time: f64,
+) -> f64 {
+    let local_v = inverse.transform_vector(v);
+    let local_pdf = hittable.pdf_value(inverse.transform_point(o), local_v, time);
+    // Change of variables between unit directions: a linear map A sends the solid
+    // angle around w to |det A| / |A w|^3 times as much.
+    let stretch = local_v.length() / v.length();
+    local_pdf * inverse.linear_determinant().abs() / (stretch * stretch * stretch)
+}
+
+pub(super) fn transformed_random(
+    hittable: &dyn Hittable,
+    matrix: &Mat4,
+    inverse: &Mat4,
+    o: Point3,
+    time: f64,
+) -> Vec3 {
+    matrix.transform_vector(hittable.random(inverse.transform_point(o), time))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{
+        color::Color, materials::diffuse_light::DiffuseLight, shapes::sphere::Sphere, PI,
+    };
+
+    use super::*;
+
+    fn ellipsoid() -> Transform {
+        let light = Arc::new(DiffuseLight::from_color(Color::new(1.0, 1.0, 1.0)));
+        Transform::new(
+            Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, light)),
+            Mat4::rotation_y(30.0) * Mat4::scaling(Vec3::new(2.0, 1.0, 0.5)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rejects_singular_matrices() {
+        let light = Arc::new(DiffuseLight::from_color(Color::new(1.0, 1.0, 1.0)));
+        let sphere = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, light));
+        assert!(Transform::new(sphere, Mat4::scaling(Vec3::new(1.0, 0.0, 1.0))).is_none());
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_the_directions() {
+        // Midpoint rule over cos(theta) and phi, which are uniform in solid angle.
+        const STEPS: usize = 600;
+        let object = ellipsoid();
+        let o = Vec3::new(0.5, 2.0, 2.5);
+        let mut total = 0.0;
+        for i in 0..STEPS {
+            let z = 1.0 - 2.0 * (i as f64 + 0.5) / STEPS as f64;
+            let r = (1.0 - z * z).sqrt();
+            for j in 0..STEPS {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / STEPS as f64;
+                let v = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                total += object.pdf_value(o, v, 0.0);
+            }
+        }
+        let solid_angle = 4.0 * PI / (STEPS * STEPS) as f64;
+        assert!(
+            (total * solid_angle - 1.0).abs() < 0.01,
+            "{}",
+            total * solid_angle
+        );
+    }
+
+    #[test]
+    fn samples_directions_that_hit() {
+        let object = ellipsoid();
+        let o = Vec3::new(0.5, 2.0, 2.5);
+        for _ in 0..1000 {
+            let v = object.random(o, 0.0);
+            assert!(object
+                .hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY)
+                .is_some());
+            assert!(object.pdf_value(o, v, 0.0) > 0.0);
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use std::sync::Arc;
 
-use crate::rt::{ray::Ray, vec3::Vec3};
+use crate::rt::{ray::Ray, vec3::Vec3, Point3};
 
-use super::{aabb::Aabb, hit_record::HitRecord, Hittable};
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
 
 pub struct Translate {
     hittable: Arc<dyn Hittable>,
@@ -16,7 +16,7 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let moved_r = Ray::new(r.origin - self.offset, r.direction, r.time);
         let mut rec = self.hittable.hit(&moved_r, t_min, t_max)?;
         rec.p = rec.p + self.offset;
@@ -31,4 +31,18 @@ impl Hittable for Translate {
             output_box.max + self.offset,
         ))
     }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        self.hittable.pdf_value(o - self.offset, v, time)
+    }
+
+    fn random(&self, o: Point3, time: f64) -> Vec3 {
+        self.hittable.random(o - self.offset, time)
+    }
+
+    fn light_parts(&self) -> LightParts {
+        self.hittable
+            .light_parts()
+            .wrap(|part| Arc::new(Translate::new(part, self.offset)))
+    }
 }
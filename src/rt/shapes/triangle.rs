@@ -0,0 +1,263 @@
+use std::sync::Arc;
+
+use crate::rt::{materials::Material, random_f64, ray::Ray, vec3::Vec3, Point3};
+
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
+
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<dyn Material>) -> Triangle {
+        Triangle::with_attributes([v0, v1, v2], None, None, material)
+    }
+
+    pub fn with_attributes(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: Arc<dyn Material>,
+    ) -> Triangle {
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            material,
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        area(self.vertices)
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = intersect(self.vertices, r, t_min, t_max)?;
+        Some(hit_record(
+            self.vertices,
+            self.normals,
+            self.uvs,
+            r,
+            t,
+            b1,
+            b2,
+            self.material.as_ref(),
+        ))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(bounding_box(self.vertices))
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        match self.hit(&Ray::new(o, v, time), 0.001, f64::INFINITY) {
+            None => 0.0,
+            Some(rec) => {
+                let [v0, v1, v2] = self.vertices;
+                let normal = Vec3::unit_vector(Vec3::cross(v1 - v0, v2 - v0));
+                let distance_squared = rec.t * rec.t * v.length_squared();
+                let cosine = f64::abs(Vec3::dot(v, normal) / v.length());
+                distance_squared / (cosine * self.area())
+            }
+        }
+    }
+
+    fn random(&self, o: Point3, _time: f64) -> Vec3 {
+        sample_point(self.vertices) - o
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::of_material(self.material.as_ref())
+    }
+}
+
+/// Möller–Trumbore ray/triangle intersection, returns `(t, b1, b2)` where `b1` and `b2` are
+/// the barycentric weights of the second and third vertex.
+pub(super) fn intersect(
+    v: [Point3; 3],
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    const EPSILON: f64 = 1e-12;
+    let edge1 = v[1] - v[0];
+    let edge2 = v[2] - v[0];
+    let pvec = Vec3::cross(r.direction, edge2);
+    let det = Vec3::dot(edge1, pvec);
+    if det.abs() < EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let tvec = r.origin - v[0];
+    let b1 = Vec3::dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = Vec3::cross(tvec, edge1);
+    let b2 = Vec3::dot(r.direction, qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = Vec3::dot(edge2, qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+
+    Some((t, b1, b2))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn hit_record<'a>(
+    v: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    r: &Ray,
+    t: f64,
+    b1: f64,
+    b2: f64,
+    material: &'a dyn Material,
+) -> HitRecord<'a> {
+    let b0 = 1.0 - b1 - b2;
+    let (u, v_coord) = match uvs {
+        Some(uv) => (
+            b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0,
+            b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1,
+        ),
+        None => (b1, b2),
+    };
+
+    let mut outward_normal = Vec3::unit_vector(Vec3::cross(v[1] - v[0], v[2] - v[0]));
+    let shading_normal = normals.map(|n| Vec3::unit_vector(b0 * n[0] + b1 * n[1] + b2 * n[2]));
+    // Keep the geometric normal on the same side as the interpolated one so that
+    // front_face agrees with the winding the normals were authored for.
+    if let Some(n) = shading_normal {
+        if Vec3::dot(outward_normal, n) < 0.0 {
+            outward_normal = -outward_normal;
+        }
+    }
+
+    let mut rec = HitRecord::new(r.at(t), t, u, v_coord, r, outward_normal, material);
+    rec.barycentrics = Some((b1, b2));
+    if let Some(n) = shading_normal {
+        rec.normal = if rec.front_face { n } else { -n };
+    }
+    rec
+}
+
+pub(super) fn bounding_box(v: [Point3; 3]) -> Aabb {
+    // Pad the box so axis-aligned triangles still get a non-zero extent.
+    const PADDING: f64 = 0.0001;
+    let mut min = v[0];
+    let mut max = v[0];
+    for p in &v[1..] {
+        for a in 0..3 {
+            min.set(a, min.get(a).min(p.get(a)));
+            max.set(a, max.get(a).max(p.get(a)));
+        }
+    }
+    let padding = Vec3::new(PADDING, PADDING, PADDING);
+    Aabb::new(min - padding, max + padding)
+}
+
+pub(super) fn area(v: [Point3; 3]) -> f64 {
+    0.5 * Vec3::cross(v[1] - v[0], v[2] - v[0]).length()
+}
+
+/// Uniformly samples a point on the triangle.
+pub(super) fn sample_point(v: [Point3; 3]) -> Point3 {
+    let su0 = random_f64().sqrt();
+    let b0 = 1.0 - su0;
+    let b1 = random_f64() * su0;
+    b0 * v[0] + b1 * v[1] + (1.0 - b0 - b1) * v[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::{color::Color, materials::lambertian::Lambertian};
+
+    use super::*;
+
+    fn triangle(normals: Option<[Vec3; 3]>, uvs: Option<[(f64, f64); 3]>) -> Triangle {
+        Triangle::with_attributes(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            normals,
+            uvs,
+            Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5))),
+        )
+    }
+
+    fn ray_at(x: f64, y: f64) -> Ray {
+        Ray::new(Point3::new(x, y, -1.0), Vec3::new(0.0, 0.0, 1.0), 0.0)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn hits_inside_and_misses_outside() {
+        let object = triangle(None, None);
+        let rec = object
+            .hit(&ray_at(0.25, 0.5), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!(close(rec.t, 1.0));
+        assert!((rec.p - Point3::new(0.25, 0.5, 0.0)).length() < 1e-9);
+        // Counter-clockwise winding faces +z, away from the ray.
+        assert!(!rec.front_face);
+        assert!(close(rec.normal.z, -1.0));
+
+        assert!(object
+            .hit(&ray_at(0.75, 0.5), 0.001, f64::INFINITY)
+            .is_none());
+        assert!(object
+            .hit(&ray_at(-0.1, 0.5), 0.001, f64::INFINITY)
+            .is_none());
+        assert!(object.hit(&ray_at(0.25, 0.5), 0.001, 0.5).is_none());
+    }
+
+    #[test]
+    fn writes_barycentric_or_interpolated_uvs() {
+        let object = triangle(None, None);
+        let rec = object
+            .hit(&ray_at(0.25, 0.5), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!(close(rec.u, 0.25) && close(rec.v, 0.5));
+
+        let object = triangle(None, Some([(0.5, 0.5), (2.5, 0.5), (0.5, 4.5)]));
+        let rec = object
+            .hit(&ray_at(0.25, 0.5), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!(close(rec.u, 1.0) && close(rec.v, 2.5));
+        let (b1, b2) = rec.barycentrics.unwrap();
+        assert!(close(b1, 0.25) && close(b2, 0.5));
+    }
+
+    #[test]
+    fn interpolates_vertex_normals() {
+        let normals = [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+        ];
+        let object = triangle(Some(normals), None);
+        let rec = object
+            .hit(&ray_at(0.25, 0.5), 0.001, f64::INFINITY)
+            .unwrap();
+        let expected = Vec3::unit_vector(0.25 * normals[0] + 0.25 * normals[1] + 0.5 * normals[2]);
+        // The vertex normals face the ray, so the hit is on the front face.
+        assert!(rec.front_face);
+        assert!((rec.normal - expected).length() < 1e-9);
+    }
+}
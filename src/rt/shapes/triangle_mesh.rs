@@ -0,0 +1,359 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    sync::Arc,
+};
+
+use crate::rt::{
+    color::Color,
+    materials::Material,
+    random_f64,
+    ray::Ray,
+    vec3::{self, Vec3},
+    Point3,
+};
+
+use super::{
+    aabb::Aabb, bvh_build::BvhOptions, hit_record::HitRecord, linear_bvh::LinearBvh, triangle,
+    Hittable, LightParts,
+};
+
+/// Vertex buffers shared by every triangle of a mesh. `normals`, `uvs` and `colors` are
+/// either empty or indexed exactly like `positions`.
+#[derive(Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    fn vertices(&self, index: usize) -> [Point3; 3] {
+        let [i0, i1, i2] = self.indices[index];
+        [self.positions[i0], self.positions[i1], self.positions[i2]]
+    }
+
+    fn normals(&self, index: usize) -> Option<[Vec3; 3]> {
+        if self.normals.is_empty() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[index];
+        Some([self.normals[i0], self.normals[i1], self.normals[i2]])
+    }
+
+    fn uvs(&self, index: usize) -> Option<[(f64, f64); 3]> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[index];
+        Some([self.uvs[i0], self.uvs[i1], self.uvs[i2]])
+    }
+
+    fn color(&self, index: usize, b1: f64, b2: f64) -> Option<Color> {
+        if self.colors.is_empty() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[index];
+        Some((1.0 - b1 - b2) * self.colors[i0] + b1 * self.colors[i1] + b2 * self.colors[i2])
+    }
+}
+
+/// Why `MeshData` can't be turned into a `TriangleMesh`.
+#[derive(Debug, PartialEq)]
+pub enum MeshError {
+    /// A non-empty attribute buffer whose length differs from the vertex count.
+    AttributeCount {
+        attribute: &'static str,
+        found: usize,
+        vertices: usize,
+    },
+    /// A face references a vertex past the end of `positions`.
+    IndexOutOfRange { index: usize, vertices: usize },
+}
+
+impl Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::AttributeCount {
+                attribute,
+                found,
+                vertices,
+            } => write!(
+                f,
+                "mesh has {} {} for {} vertices",
+                found, attribute, vertices
+            ),
+            MeshError::IndexOutOfRange { index, vertices } => {
+                write!(f, "mesh face references vertex {} of {}", index, vertices)
+            }
+        }
+    }
+}
+
+impl Error for MeshError {}
+
+struct Mesh {
+    data: MeshData,
+    material: Arc<dyn Material>,
+    total_area: f64,
+}
+
+struct MeshTriangle {
+    mesh: Arc<Mesh>,
+    index: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let data = &self.mesh.data;
+        let vertices = data.vertices(self.index);
+        let (t, b1, b2) = triangle::intersect(vertices, r, t_min, t_max)?;
+        let mut rec = triangle::hit_record(
+            vertices,
+            data.normals(self.index),
+            data.uvs(self.index),
+            r,
+            t,
+            b1,
+            b2,
+            self.mesh.material.as_ref(),
+        );
+        rec.vertex_color = data.color(self.index, b1, b2);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(triangle::bounding_box(self.mesh.data.vertices(self.index)))
+    }
+
+    /// Density of `TriangleMesh::random` picking the point `v` hits on this triangle, which
+    /// takes the geometric normal rather than the interpolated one.
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        let vertices = self.mesh.data.vertices(self.index);
+        match triangle::intersect(vertices, &Ray::new(o, v, time), 0.001, f64::INFINITY) {
+            None => 0.0,
+            Some((t, _, _)) => {
+                let [v0, v1, v2] = vertices;
+                let normal = Vec3::unit_vector(Vec3::cross(v1 - v0, v2 - v0));
+                let distance_squared = t * t * v.length_squared();
+                let cosine = f64::abs(Vec3::dot(v, normal) / v.length());
+                distance_squared / (cosine * self.mesh.total_area)
+            }
+        }
+    }
+}
+
+pub struct TriangleMesh {
+    mesh: Arc<Mesh>,
+    bvh: LinearBvh,
+    area_cdf: Vec<f64>,
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData, material: Arc<dyn Material>) -> Result<TriangleMesh, MeshError> {
+        TriangleMesh::with_options(data, material, BvhOptions::default())
+    }
+
+    pub fn with_options(
+        data: MeshData,
+        material: Arc<dyn Material>,
+        options: BvhOptions,
+    ) -> Result<TriangleMesh, MeshError> {
+        let vertices = data.positions.len();
+        let attributes = [
+            ("normals", data.normals.len()),
+            ("uvs", data.uvs.len()),
+            ("colors", data.colors.len()),
+        ];
+        for (attribute, found) in attributes {
+            if found != 0 && found != vertices {
+                return Err(MeshError::AttributeCount {
+                    attribute,
+                    found,
+                    vertices,
+                });
+            }
+        }
+        if let Some(&index) = data.indices.iter().flatten().find(|&&i| i >= vertices) {
+            return Err(MeshError::IndexOutOfRange { index, vertices });
+        }
+
+        let mut total_area = 0.0;
+        let area_cdf = (0..data.indices.len())
+            .map(|i| {
+                total_area += triangle::area(data.vertices(i));
+                total_area
+            })
+            .collect();
+
+        let mesh = Arc::new(Mesh {
+            data,
+            material,
+            total_area,
+        });
+        let triangles: Vec<Arc<dyn Hittable>> = (0..mesh.data.indices.len())
+            .map(|index| {
+                Arc::new(MeshTriangle {
+                    mesh: mesh.clone(),
+                    index,
+                }) as Arc<dyn Hittable>
+            })
+            .collect();
+        let bvh = LinearBvh::new(&triangles, 0.0, 1.0, options);
+
+        Ok(TriangleMesh {
+            mesh,
+            bvh,
+            area_cdf,
+        })
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.mesh.data
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.mesh.material
+    }
+
+    pub fn area(&self) -> f64 {
+        self.mesh.total_area
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.bvh.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3, time: f64) -> f64 {
+        if self.mesh.total_area <= 0.0 {
+            return 0.0;
+        }
+        match self
+            .bvh
+            .closest_hit(&Ray::new(o, v, time), 0.001, f64::INFINITY)
+        {
+            None => 0.0,
+            Some((triangle, _)) => triangle.pdf_value(o, v, time),
+        }
+    }
+
+    fn random(&self, o: Point3, _time: f64) -> Vec3 {
+        if self.mesh.total_area <= 0.0 {
+            return vec3::UNIT_X;
+        }
+        // Pick a triangle proportionally to its area, then a uniform point on it.
+        let target = random_f64() * self.mesh.total_area;
+        let index = self
+            .area_cdf
+            .partition_point(|&a| a <= target)
+            .min(self.area_cdf.len() - 1);
+        triangle::sample_point(self.mesh.data.vertices(index)) - o
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::of_material(self.mesh.material.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rt::materials::lambertian::Lambertian;
+
+    use super::*;
+
+    fn quad() -> MeshData {
+        MeshData {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            indices: vec![[0, 1, 2], [0, 2, 3]],
+            ..MeshData::default()
+        }
+    }
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn hits_every_face() {
+        let mesh = TriangleMesh::new(quad(), material()).unwrap();
+        assert!((mesh.area() - 1.0).abs() < 1e-12);
+
+        for (x, y) in [(0.75, 0.25), (0.25, 0.75)] {
+            let ray = Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+            let rec = mesh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.t - 1.0).abs() < 1e-9);
+        }
+        let ray = Ray::new(Point3::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(mesh.hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn interpolates_vertex_colors() {
+        let ray = Ray::new(Point3::new(0.75, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mesh = TriangleMesh::new(quad(), material()).unwrap();
+        let rec = mesh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.vertex_color.is_none());
+
+        let mut data = quad();
+        data.colors = vec![
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(0.0, 0.0, 1.0),
+        ];
+        let mesh = TriangleMesh::new(data, material()).unwrap();
+        let rec = mesh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let color = rec.vertex_color.unwrap();
+        assert!((color - Color::new(0.25, 0.5, 0.25)).length() < 1e-9);
+    }
+
+    #[test]
+    fn light_pdf_uses_the_face_normal() {
+        let mut data = quad();
+        // Shading normals leaning away from the face mustn't change the density.
+        data.normals = vec![Vec3::unit_vector(Vec3::new(1.0, 0.0, 1.0)); 4];
+        let mesh = TriangleMesh::new(data, material()).unwrap();
+        let o = Point3::new(0.25, 0.75, 2.0);
+        let v = Vec3::new(0.0, 0.0, -1.0);
+        // A unit area seen head on from a distance of 2.
+        assert!((mesh.pdf_value(o, v, 0.0) - 4.0).abs() < 1e-9);
+        assert_eq!(mesh.pdf_value(o, -v, 0.0), 0.0);
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let mut data = quad();
+        data.normals = vec![Vec3::new(0.0, 0.0, 1.0)];
+        assert_eq!(
+            TriangleMesh::new(data, material()).err(),
+            Some(MeshError::AttributeCount {
+                attribute: "normals",
+                found: 1,
+                vertices: 4
+            })
+        );
+
+        let mut data = quad();
+        data.indices.push([0, 3, 4]);
+        assert_eq!(
+            TriangleMesh::new(data, material()).err(),
+            Some(MeshError::IndexOutOfRange {
+                index: 4,
+                vertices: 4
+            })
+        );
+    }
+}
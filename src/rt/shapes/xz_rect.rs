@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crate::rt::{materials::Material, random_f64_between, ray::Ray, vec3::Vec3, Point3};
 
-use super::{aabb::Aabb, hit_record::HitRecord, Hittable};
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
 
 pub struct XzRect {
     x0: f64,
@@ -27,7 +27,7 @@ impl XzRect {
 }
 
 impl Hittable for XzRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin.y) / r.direction.y;
         if t < t_min || t > t_max {
             return None;
@@ -62,8 +62,8 @@ impl Hittable for XzRect {
         ))
     }
 
-    fn pdf_value(&self, origin: Point3, v: Vec3) -> f64 {
-        match self.hit(&Ray::new(origin, v, 0.0), 0.001, f64::INFINITY) {
+    fn pdf_value(&self, origin: Point3, v: Vec3, time: f64) -> f64 {
+        match self.hit(&Ray::new(origin, v, time), 0.001, f64::INFINITY) {
             None => 0.0,
             Some(rec) => {
                 let area = (self.x1 - self.x0) * (self.z1 - self.z0);
@@ -74,7 +74,7 @@ impl Hittable for XzRect {
         }
     }
 
-    fn random(&self, origin: Point3) -> Vec3 {
+    fn random(&self, origin: Point3, _time: f64) -> Vec3 {
         let random_point = Point3::new(
             random_f64_between(self.x0, self.x1),
             self.k,
@@ -82,4 +82,8 @@ impl Hittable for XzRect {
         );
         random_point - origin
     }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::of_material(self.material.as_ref())
+    }
 }
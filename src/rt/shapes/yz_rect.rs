@@ -1,8 +1,8 @@
 use std::sync::Arc;
 
-use crate::rt::{materials::Material, ray::Ray, vec3::Vec3, Point3};
+use crate::rt::{materials::Material, random_f64_between, ray::Ray, vec3::Vec3, Point3};
 
-use super::{aabb::Aabb, hit_record::HitRecord, Hittable};
+use super::{aabb::Aabb, hit_record::HitRecord, Hittable, LightParts};
 
 pub struct YzRect {
     y0: f64,
@@ -27,7 +27,7 @@ impl YzRect {
 }
 
 impl Hittable for YzRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin.x) / r.direction.x;
         if t < t_min || t > t_max {
             return None;
@@ -61,4 +61,29 @@ impl Hittable for YzRect {
             Point3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn pdf_value(&self, origin: Point3, v: Vec3, time: f64) -> f64 {
+        match self.hit(&Ray::new(origin, v, time), 0.001, f64::INFINITY) {
+            None => 0.0,
+            Some(rec) => {
+                let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+                let distance_squared = rec.t * rec.t * v.length_squared();
+                let cosine = f64::abs(Vec3::dot(v, rec.normal) / v.length());
+                distance_squared / (cosine * area)
+            }
+        }
+    }
+
+    fn random(&self, origin: Point3, _time: f64) -> Vec3 {
+        let random_point = Point3::new(
+            self.k,
+            random_f64_between(self.y0, self.y1),
+            random_f64_between(self.z0, self.z1),
+        );
+        random_point - origin
+    }
+
+    fn light_parts(&self) -> LightParts {
+        LightParts::of_material(self.material.as_ref())
+    }
 }
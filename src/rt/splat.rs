@@ -0,0 +1,133 @@
+//! Light that integrators find for pixels other than the one they are sampling, like
+//! bidirectional path tracing does when it joins light subpaths to the lens.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::{color::Color, framebuffer::Framebuffer};
+
+/// Sums of the light found for every pixel during a pass, in total and by light group,
+/// which threads add to at the same time. Rows are stored top to bottom, like in a
+/// framebuffer.
+#[derive(Debug)]
+pub struct SplatBuffer {
+    width: usize,
+    height: usize,
+    light_groups: usize,
+    /// The RGB sum of the total and then of every light group, for every pixel.
+    sums: Vec<AtomicU32>,
+}
+
+impl SplatBuffer {
+    pub fn new(width: usize, height: usize, light_groups: usize) -> SplatBuffer {
+        SplatBuffer {
+            width,
+            height,
+            light_groups,
+            sums: (0..width * height * (1 + light_groups) * 3)
+                .map(|_| AtomicU32::new(0.0f32.to_bits()))
+                .collect(),
+        }
+    }
+
+    /// Adds `light` of light group `group` to the pixel at the film coordinates `u`, `v`,
+    /// as `Camera::get_ray` takes them. Light that lands outside the image is dropped.
+    pub fn add(&self, u: f64, v: f64, group: usize, light: Color) {
+        let x = (u * (self.width - 1) as f64).floor();
+        let y = (v * (self.height - 1) as f64).floor();
+        if !(0.0..self.width as f64).contains(&x) || !(0.0..self.height as f64).contains(&y) {
+            return;
+        }
+        let row = self.height - 1 - y as usize;
+        let start = (row * self.width + x as usize) * self.stride();
+        add(&self.sums[start..start + 3], light);
+        if group < self.light_groups {
+            let start = start + 3 * (group + 1);
+            add(&self.sums[start..start + 3], light);
+        }
+    }
+
+    /// Adds the light found in a pass that followed `light_paths` paths to `framebuffer`,
+    /// whose pixels had `before` samples before the pass. The sum of a pixel over
+    /// `light_paths` estimates its light, and every sample the pass took of the pixel
+    /// carries that estimate, so the light keeps its share however samples are spread
+    /// between pixels and passes. The variance of the pixels doesn't see it.
+    pub(crate) fn merge_into(
+        &self,
+        framebuffer: &mut Framebuffer,
+        before: &[u32],
+        light_paths: usize,
+    ) {
+        if light_paths == 0 {
+            return;
+        }
+        let width = self.width;
+        let stride = self.stride();
+        for (y, row) in framebuffer.rows_mut().into_iter().enumerate() {
+            let mut aovs = row.aovs;
+            for (x, pixel) in row.pixels.iter_mut().enumerate() {
+                let i = y * width + x;
+                let taken = pixel.samples() - before[i];
+                if taken == 0 {
+                    continue;
+                }
+                let scale = taken as f64 / light_paths as f64;
+                let sums = &self.sums[i * stride..(i + 1) * stride];
+                pixel.add_light(get(&sums[..3]) * scale);
+                if let Some((aov_pixels, light_groups)) = &mut aovs {
+                    let groups = light_groups.len() / aov_pixels.len();
+                    let pixel_groups = &mut light_groups[x * groups..(x + 1) * groups];
+                    for (sum, group) in pixel_groups.iter_mut().zip(sums[3..].chunks_exact(3)) {
+                        *sum = *sum + get(group) * scale;
+                    }
+                }
+            }
+        }
+    }
+
+    fn stride(&self) -> usize {
+        (1 + self.light_groups) * 3
+    }
+}
+
+fn add(sum: &[AtomicU32], light: Color) {
+    for (sum, c) in sum.iter().zip([light.x, light.y, light.z]) {
+        // Only fails when the closure returns `None`.
+        let _ = sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f32::from_bits(bits) + c as f32).to_bits())
+        });
+    }
+}
+
+fn get(sum: &[AtomicU32]) -> Color {
+    let [r, g, b] = [0, 1, 2].map(|c| f32::from_bits(sum[c].load(Ordering::Relaxed)) as f64);
+    Color::new(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_light_over_the_samples_of_a_pass() {
+        let mut framebuffer = Framebuffer::new(2, 2);
+        let before: Vec<u32> = vec![0; 4];
+        for (i, pixel) in framebuffer.pixels_mut().iter_mut().enumerate() {
+            for _ in 0..i {
+                pixel.add_sample(Color::default(), true);
+            }
+        }
+        let splats = SplatBuffer::new(2, 2, 1);
+        // The bottom of the image is at `v` = 0.
+        splats.add(1.5, 0.9, 0, Color::new(8.0, 8.0, 8.0));
+        splats.add(1.2, 1.2, 0, Color::new(4.0, 4.0, 4.0));
+        splats.add(0.2, 1.2, 0, Color::new(4.0, 4.0, 4.0));
+        splats.add(2.1, 0.5, 0, Color::new(100.0, 100.0, 100.0));
+        splats.merge_into(&mut framebuffer, &before, 4);
+
+        // The top left pixel took no samples, so it gets nothing.
+        assert_eq!(framebuffer.pixel(0, 0), Color::default());
+        assert_eq!(framebuffer.pixel(1, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(framebuffer.pixel(0, 1), Color::default());
+        assert_eq!(framebuffer.pixel(1, 1), Color::new(2.0, 2.0, 2.0));
+    }
+}
@@ -1,6 +1,7 @@
 use super::Texture;
 use crate::rt::{color::Color, Point3};
-use image::{io::Reader as ImageReader, DynamicImage};
+use image::{DynamicImage, ImageResult};
+use std::path::Path;
 
 pub struct ImageTexture {
     data: Vec<u8>,
@@ -22,23 +23,26 @@ impl ImageTexture {
     }
 
     pub fn from_file(file_name: &str) -> ImageTexture {
-        let img = ImageReader::open(file_name)
-            .expect("couldn't open image file")
-            .decode()
-            .expect("couldn't decode image file");
+        ImageTexture::open(file_name).expect("couldn't load image file")
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> ImageResult<ImageTexture> {
+        let img = match image::open(path)? {
+            img @ (DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)) => img,
+            img => DynamicImage::ImageRgb8(img.to_rgb8()),
+        };
 
         let bytes_per_pixel = match &img {
-            DynamicImage::ImageRgb8(_) => 3,
             DynamicImage::ImageRgba8(_) => 4,
-            _ => panic!("unsupported texture file"),
+            _ => 3,
         };
 
-        ImageTexture::new(
+        Ok(ImageTexture::new(
             Vec::from(img.as_bytes()),
             img.width() as usize,
             img.height() as usize,
-            bytes_per_pixel as usize,
-        )
+            bytes_per_pixel,
+        ))
     }
 }
 
@@ -57,10 +61,10 @@ impl Texture for ImageTexture {
         let color_scale = 1.0 / 255.0;
         let index = j * self.bytes_per_scanline + i * self.bytes_per_pixel;
 
-        return Color::new(
+        Color::new(
             color_scale * self.data[index] as f64,
             color_scale * self.data[index + 1] as f64,
             color_scale * self.data[index + 2] as f64,
-        );
+        )
     }
 }
@@ -1,10 +1,15 @@
-use super::{color::Color, Point3};
+use super::{color::Color, shapes::hit_record::HitRecord, Point3};
 
 pub mod checker_texture;
 pub mod image_texture;
 pub mod noise_texture;
 pub mod solid_color;
+pub mod vertex_color_texture;
 
 pub trait Texture: Sync + Send {
     fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, rec.p)
+    }
 }
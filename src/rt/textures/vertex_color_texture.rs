@@ -0,0 +1,39 @@
+use crate::rt::{
+    color::{self, Color},
+    shapes::hit_record::HitRecord,
+    Point3,
+};
+
+use super::Texture;
+
+/// Uses the per-vertex colors interpolated by a `TriangleMesh`, falling back to a constant
+/// color for surfaces that carry none.
+pub struct VertexColorTexture {
+    fallback: Color,
+}
+
+impl VertexColorTexture {
+    pub fn new() -> VertexColorTexture {
+        VertexColorTexture::with_fallback(color::WHITE)
+    }
+
+    pub fn with_fallback(fallback: Color) -> VertexColorTexture {
+        VertexColorTexture { fallback }
+    }
+}
+
+impl Default for VertexColorTexture {
+    fn default() -> Self {
+        VertexColorTexture::new()
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.fallback
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        rec.vertex_color.unwrap_or(self.fallback)
+    }
+}
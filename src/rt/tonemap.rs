@@ -0,0 +1,151 @@
+use super::color::{self, Color};
+
+/// Compresses linear radiance into [0, 1] for display.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapper {
+    /// Cuts everything above 1 off.
+    Clamp,
+    /// `L / (1 + L)` on luminance, keeping the hue.
+    Reinhard,
+    /// Reinhard with luminance `white` and above mapped to 1.
+    ExtendedReinhard { white: f64 },
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve.
+    Hable,
+}
+
+impl ToneMapper {
+    pub const NAMES: &'static [&'static str] =
+        &["clamp", "reinhard", "reinhard_extended", "aces", "hable"];
+
+    /// Looks a tone mapper up by name, extended Reinhard gets a white point of 4.
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        match name {
+            "clamp" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "reinhard_extended" => Some(ToneMapper::ExtendedReinhard { white: 4.0 }),
+            "aces" => Some(ToneMapper::Aces),
+            "hable" => Some(ToneMapper::Hable),
+            _ => None,
+        }
+    }
+
+    pub fn map(&self, color: Color) -> Color {
+        match *self {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard { white } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapper::Aces => per_channel(color, |x| {
+                // The fit was made for an exposure that is 1/0.6 too bright.
+                let x = 0.6 * x;
+                x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+            ToneMapper::Hable => {
+                const WHITE: f64 = 11.2;
+                const EXPOSURE_BIAS: f64 = 2.0;
+                per_channel(color, |x| hable(EXPOSURE_BIAS * x) / hable(WHITE))
+            }
+        }
+        .clamp(0.0, 1.0)
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15; // shoulder strength
+    const B: f64 = 0.50; // linear strength
+    const C: f64 = 0.10; // linear angle
+    const D: f64 = 0.20; // toe strength
+    const E: f64 = 0.02; // toe numerator
+    const F: f64 = 0.30; // toe denominator
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+fn per_channel(color: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.x), f(color.y), f(color.z))
+}
+
+fn scale_luminance(color: Color, f: impl Fn(f64) -> f64) -> Color {
+    let l = color::luminance(color);
+    if l <= 0.0 {
+        color::BLACK
+    } else {
+        color * (f(l) / l)
+    }
+}
+
+/// The sRGB transfer function, from linear [0, 1] to encoded [0, 1].
+pub fn srgb_encode(c: f64) -> f64 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Turns linear radiance into 8-bit sRGB: scales by the exposure, tone maps and encodes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DisplayTransform {
+    /// In stops, each one doubles the brightness.
+    pub exposure: f64,
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+        }
+    }
+}
+
+impl DisplayTransform {
+    pub fn apply(&self, color: Color) -> [u8; 3] {
+        let color = self.tone_mapper.map(color * self.exposure.exp2());
+        [color.x, color.y, color.z].map(|c| (srgb_encode(c) * 255.0).round() as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_start_at_black_and_saturate() {
+        for name in ToneMapper::NAMES {
+            let tone_mapper = ToneMapper::from_name(name).unwrap();
+            assert_eq!(tone_mapper.map(color::BLACK), color::BLACK, "{}", name);
+            let bright = tone_mapper.map(Color::new(1000.0, 1000.0, 1000.0));
+            assert!(bright.x > 0.95 && bright.x <= 1.0, "{}: {:?}", name, bright);
+            let dim = tone_mapper.map(Color::new(0.1, 0.1, 0.1)).x;
+            let mid = tone_mapper.map(Color::new(0.5, 0.5, 0.5)).x;
+            assert!(0.0 < dim && dim < mid, "{}", name);
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_reaches_white_at_the_white_point() {
+        let tone_mapper = ToneMapper::ExtendedReinhard { white: 4.0 };
+        let white = tone_mapper.map(Color::new(4.0, 4.0, 4.0));
+        assert!((white.x - 1.0).abs() < 1e-12);
+        // Reinhard keeps the ratio between channels.
+        let color = ToneMapper::Reinhard.map(Color::new(0.4, 0.2, 0.1));
+        assert!((color.x / color.y - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exposure_and_srgb_encoding() {
+        let display = DisplayTransform::default();
+        assert_eq!(display.apply(Color::new(0.0, 1.0, 2.0)), [0, 255, 255]);
+        // Linear 0.2142 is sRGB 0.5.
+        assert_eq!(display.apply(Color::new(0.2142, 0.2142, 0.2142)), [128; 3]);
+        let display = DisplayTransform {
+            exposure: -1.0,
+            ..display
+        };
+        assert_eq!(display.apply(Color::new(0.4284, 0.4284, 0.4284)), [128; 3]);
+    }
+}
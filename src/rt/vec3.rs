@@ -98,14 +98,14 @@ impl Vec3 {
     }
 
     pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
-        return v - 2.0 * Vec3::dot(v, n) * n;
+        v - 2.0 * Vec3::dot(v, n) * n
     }
 
     pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
         let cos_theta = Vec3::dot(-uv, n).min(1.0);
         let r_out_perp = etai_over_etat * (uv + cos_theta * n);
         let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
-        return r_out_parallel + r_out_perp;
+        r_out_parallel + r_out_perp
     }
 }
 